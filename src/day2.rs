@@ -2,17 +2,19 @@
     --- Day 2: 1202 Program Alarm ---
 */
 
-pub fn mem_set(memory: &mut Vec<i32>, index: usize, value: i32) {
+use intcode_vm::symbolic::{Constraint, SymbolicVM};
+
+pub fn mem_set(memory: &mut Vec<i64>, index: usize, value: i64) {
     let address = memory[index];
     memory[address as usize] = value;
 }
 
-pub fn mem_get(memory: &mut Vec<i32>, index: usize) -> i32 {
+pub fn mem_get(memory: &mut Vec<i64>, index: usize) -> i64 {
     let address = memory[index];
     return memory[address as usize];
 }
 
-pub fn opcode_add(memory: &mut Vec<i32>, index: usize) {
+pub fn opcode_add(memory: &mut Vec<i64>, index: usize) {
     // println!(
     //     "opcode_add {}+{}={}",
     //     mem_get(memory, index + 1),
@@ -23,7 +25,7 @@ pub fn opcode_add(memory: &mut Vec<i32>, index: usize) {
     mem_set(memory, index + 3, value);
 }
 
-pub fn opcode_mul(memory: &mut Vec<i32>, index: usize) {
+pub fn opcode_mul(memory: &mut Vec<i64>, index: usize) {
     // println!(
     //     "opcode_mul {}*{}={}",
     //     mem_get(memory, index + 1),
@@ -34,7 +36,7 @@ pub fn opcode_mul(memory: &mut Vec<i32>, index: usize) {
     mem_set(memory, index + 3, value);
 }
 
-pub fn run_intcode(memory: &mut Vec<i32>, noun: i32, verb: i32) -> i32 {
+pub fn run_intcode(memory: &mut Vec<i64>, noun: i64, verb: i64) -> i64 {
     let len = memory.len();
     memory[1] = noun;
     memory[2] = verb;
@@ -53,33 +55,21 @@ pub fn run_intcode(memory: &mut Vec<i32>, noun: i32, verb: i32) -> i32 {
 }
 
 #[aoc(day2, part1)]
-pub fn solve_part1(input: &str) -> i32 {
-    let mut memory: Vec<i32> = input
-        .split_terminator(",")
-        .map(|x| x.trim().parse::<i32>().expect("Expected integer"))
-        .collect();
+pub fn solve_part1(input: &str) -> i64 {
+    let mut memory: Vec<i64> = intcode_vm::parse_program(input).expect("Invalid Intcode program");
     return run_intcode(&mut memory, 12, 2);
 }
 
 #[aoc(day2, part2)]
-pub fn solve_part2(input: &str) -> i32 {
-    let mut memory: Vec<i32> = input
-        .split_terminator(",")
-        .map(|x| x.trim().parse::<i32>().expect("Expected integer"))
-        .collect();
-    let backup = memory.clone();
-    for noun in 0..100 {
-        for verb in 0..100 {
-            // println!("{}, {}", noun, verb);
-            let result = run_intcode(&mut memory, noun, verb);
-            if result == 19690720 {
-                return 100 * noun + verb;
-            } else {
-                memory = backup.clone();
-            }
-        }
-    }
-    return 0;
+pub fn solve_part2(input: &str) -> i64 {
+    let memory: Vec<i64> = intcode_vm::parse_program(input).expect("Invalid Intcode program");
+    let mut vm = SymbolicVM::from_memory(&memory);
+    let noun = vm.symbolic_cell(1, "noun", 0..=99);
+    let verb = vm.symbolic_cell(2, "verb", 0..=99);
+    let model = vm
+        .solve(|path| vec![Constraint::equal(path.memory(0), 19690720)])
+        .expect("No noun and verb produce 19690720");
+    return 100 * model[noun] + model[verb];
 }
 
 #[cfg(test)]
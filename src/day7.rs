@@ -2,8 +2,7 @@
 	--- Day 7: Amplification Circuit ---
 */
 
-use intcode_vm;
-use std::io::{self, BufRead};
+use intcode_vm::network::{Network, Topology};
 
 #[aoc_generator(day7)]
 pub fn input_generator(input: &str) -> Vec<i64> {
@@ -11,114 +10,27 @@ pub fn input_generator(input: &str) -> Vec<i64> {
 	// let input = "3,23,3,24,1002,24,10,24,1002,23,-1,23, 101,5,23,23,1,24,23,23,4,23,99,0,0"; // 54321 (0,1,2,3,4)
 	// let input = "3,31,3,32,1002,32,10,32,1001,31,-2,31,1007,31,0,33,1002,33,7,33,1,33,31,31,1,32,31,31,4,31,99,0,0,0"; // 65210 (1,0,4,3,2)
 	// let input =	"3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5";
-	let memory: Vec<i64> = input
-		.split_terminator(",")
-		.map(|x| x.trim().parse::<i64>().expect("Expected integer"))
-		.collect();
-	return memory;
+	return intcode_vm::parse_program(input).expect("Invalid Intcode program");
 }
 
 pub fn run_program(memory: &Vec<i64>, permutations: Vec<i64>, loopback_mode: bool) -> i64 {
-	// Create 5 VM instances
-	let mut machines: Vec<intcode_vm::VM> = Vec::new();
-	let num_machines: usize = 5;
-
-	// Enable logging for the VM
-	intcode_vm::enable_logging();
-
-	// Create each VM
-	for _i in 0..num_machines {
-		machines.push(intcode_vm::VM::from_memory(memory));
-	}
-
-	// Create a vector containing all permutations of the input
-	let perms = permute::permute(permutations);
-	// println!("Total # permutations: {}", perms.len());
-
-	// Track the largest permutation index
-	// let mut largest_perm_index = 0;
-	let mut largest_perm_value = 0;
-
-	// For each permutation, run each vm in sequence and log the result
-	for (_, perm) in perms.iter().enumerate() {
-		// Log the current perm
-		// println!("Current perm: {:?}", perm);
-
-		// Reset the memory in the VM between runs,
-		let mut vm_output = 0;
-		let mut first_run = true;
-		for (i, vm) in machines.iter_mut().enumerate() {
-			vm.reset(memory);
-			vm.input.push(*perm.get(i).expect("Invalid index"));
-		}
-
-		// Set the first VM's 2nd input instruction to 0
-		machines[0].input.push(0);
-
-		let mut status = intcode_vm::Status::Halt;
-		loop {
-			for i in 0..num_machines {
-				// println!("vm_index: {}", i);
-				let vm = &mut machines[i];
-				if first_run {
-					if i > 0 {
-						// println!("VM #{} (first run) - Pushing input {}", i, vm_output);
-						vm.input.push(vm_output);
-					}
-				} else {
-					// println!("VM #{} - Pushing input {}", i, vm_output);
-					vm.input.push(vm_output);
-				}
-				loop {
-					status = vm.run_intcode();
-					match status {
-						intcode_vm::Status::WaitForInput => {
-							println!("VM #{} - Waiting for input...", i);
-							let stdin = io::stdin();
-							let input = stdin
-								.lock()
-								.lines()
-								.next()
-								.unwrap()
-								.unwrap()
-								.parse::<i64>()
-								.expect("Expected integer");
-							vm.input.push(input);
-							vm.process_input();
-						}
-						intcode_vm::Status::NewOutput => {
-							vm_output = vm.output.remove(0);
-							// println!("VM #{} - Output: {}", i, vm_output);
-							break;
-						}
-						intcode_vm::Status::Halt => {
-							// println!("VM #{} - Has finshed", i);
-							break;
-						}
-					}
-				}
-			}
-			if loopback_mode {
-				first_run = false;
-				if status == intcode_vm::Status::Halt {
-					break;
-				}
-			} else {
-				break;
+	// Each amplifier feeds the next, and in loopback mode the last feeds the first
+	let topology = if loopback_mode { Topology::Ring } else { Topology::Pipeline };
+
+	// Try every ordering of the phase settings and keep the strongest signal
+	return permute::permute(permutations)
+		.iter()
+		.map(|phases| {
+			let mut network = Network::from_program(memory, phases.len(), topology);
+			for (amplifier, &phase) in phases.iter().enumerate() {
+				network.queue_input(amplifier, phase);
 			}
-		}
-		if vm_output > largest_perm_value {
-			largest_perm_value = vm_output;
-			// largest_perm_index = p;
-		}
-	}
-
-	// println!(
-	// 	"Largest permutation: #{} {:?}",
-	// 	largest_perm_value, perms[largest_perm_index]
-	// );
-
-	return largest_perm_value;
+			network.queue_input(0, 0);
+			network.run_to_completion().expect("Intcode VM error");
+			return network.last_output(phases.len() - 1).expect("No thruster signal");
+		})
+		.max()
+		.unwrap_or(0);
 }
 
 #[aoc(day7, part1)]
@@ -1,25 +1,43 @@
 /*
 	--- Intcode debugger ---
+
+	Usage: intcode-debugger <PROGRAM>
+
+	PROGRAM is an intcode source file or a snapshot saved with
+	`intcode-vm run --save`.
 */
 
-use imgui::*;
+use intcode_vm::debugger::Session;
+use intcode_vm::{snapshot, Snapshot, VM};
+use std::{env, process};
+
+mod panels;
 mod support;
 
+fn fail(message: &str) -> ! {
+	eprintln!("intcode-debugger: {}", message);
+	process::exit(1);
+}
+
+fn load_vm(path: &str) -> VM {
+	let source = std::fs::read_to_string(path)
+		.unwrap_or_else(|e| fail(&format!("could not load {}: {}", path, e)));
+	if snapshot::is_snapshot(&source) {
+		let snapshot = Snapshot::parse(&source)
+			.unwrap_or_else(|e| fail(&format!("could not load {}: {}", path, e)));
+		return VM::from_snapshot(&snapshot);
+	}
+	let program = intcode_vm::parse_program(&source)
+		.unwrap_or_else(|e| fail(&format!("could not load {}: {}", path, e)));
+	return VM::from_memory(&program);
+}
+
 fn main() {
-    let system = support::init(file!());
-    system.main_loop(|_, ui| {
-        Window::new(im_str!("Hello world"))
-            .size([300.0, 100.0], Condition::FirstUseEver)
-            .build(ui, || {
-                ui.text(im_str!("Hello world!"));
-                ui.text(im_str!("こんにちは世界！"));
-                ui.text(im_str!("This...is...imgui-rs!"));
-                ui.separator();
-                let mouse_pos = ui.io().mouse_pos;
-                ui.text(format!(
-                    "Mouse Position: ({:.1},{:.1})",
-                    mouse_pos[0], mouse_pos[1]
-                ));
-            });
-    });
-}
\ No newline at end of file
+	let path = env::args()
+		.nth(1)
+		.unwrap_or_else(|| fail("no program given\n\nUsage: intcode-debugger <PROGRAM>"));
+	let mut debugger = panels::Debugger::new(Session::attach(load_vm(&path)));
+
+	let system = support::init(&path); // Titled with the file name
+	system.main_loop(move |_, ui| debugger.draw(ui));
+}
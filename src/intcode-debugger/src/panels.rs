@@ -0,0 +1,413 @@
+/*
+	--- Intcode debugger: panels ---
+
+	Everything here drives an intcode_vm::debugger::Session, so the GUI and
+	the `intcode-vm debug` REPL behave the same way.
+*/
+
+use imgui::*;
+use intcode_vm::debugger::{Command, Location, Session, Stop};
+use intcode_vm::disasm::Line;
+use intcode_vm::grid::{Decoder, Grid, Palette};
+use intcode_vm::{Memory, Status};
+
+const STEPS_PER_FRAME: u64 = 10_000; // Instructions run per frame while running
+const MEMORY_COLUMNS: usize = 8;
+const MEMORY_ROWS: usize = 16;
+const OUTPUT_SHOWN: usize = 32; // Most recent outputs listed
+const TILE_SIZE: f32 = 8.0; // Pixels per grid cell
+
+const PC_COLOUR: [f32; 4] = [1.0, 0.8, 0.2, 1.0];
+const CHANGED_COLOUR: [f32; 4] = [1.0, 0.4, 0.4, 1.0];
+const ERROR_COLOUR: [f32; 4] = [1.0, 0.3, 0.3, 1.0];
+
+pub struct Debugger {
+	session: Session,
+	running: bool,          // Run a batch of instructions every frame
+	before: Memory,         // Memory before the last step or run, to highlight changes
+	follow_pc: bool,        // Keep the disassembly centred on the pc
+	listing_start: i32,     // Disassembly start when not following the pc
+	memory_start: i32,      // First address in the memory grid
+	hex: bool,              // Show memory in hexadecimal
+	edit_address: ImString, // Memory cell to write from the grid panel
+	edit_value: ImString,
+	input: ImString,       // Input box contents
+	console: ImString,     // Console command line
+	log: Vec<String>,      // Console history
+	error: Option<String>, // Last command error
+	grid: Grid,            // Output drawn as a picture
+	palette: Palette,
+	palette_text: ImString, // Custom palette, empty for the default
+	as_text: bool,          // Draw the grid as characters rather than tiles
+}
+
+impl Debugger {
+	pub fn new(session: Session) -> Self {
+		Debugger {
+			before: session.vm().memory().clone(),
+			session,
+			running: false,
+			follow_pc: true,
+			listing_start: 0,
+			memory_start: 0,
+			hex: false,
+			edit_address: ImString::with_capacity(32),
+			edit_value: ImString::with_capacity(32),
+			input: ImString::with_capacity(256),
+			console: ImString::with_capacity(256),
+			log: Vec::new(),
+			error: None,
+			grid: Grid::new(Decoder::Triples),
+			palette: Palette::tiles(),
+			palette_text: ImString::with_capacity(256),
+			as_text: false,
+		}
+	}
+
+	pub fn draw(&mut self, ui: &Ui) {
+		if self.running {
+			self.before = self.session.vm().memory().clone();
+			let stop = self.session.run(Some(STEPS_PER_FRAME));
+			if stop != Stop::Status(Status::BudgetExhausted) {
+				self.running = false;
+			}
+		}
+		self.controls(ui);
+		self.disassembly(ui);
+		self.memory(ui);
+		self.registers(ui);
+		self.io(ui);
+		self.console(ui);
+		self.world(ui);
+	}
+
+	// Runs a debugger command, keeping any error for display
+	fn command(&mut self, command: Command) -> Option<String> {
+		self.before = self.session.vm().memory().clone();
+		match self.session.execute(&command) {
+			Ok(response) => {
+				self.error = None;
+				return Some(response);
+			}
+			Err(e) => {
+				self.error = Some(e.to_string());
+				return None;
+			}
+		}
+	}
+
+	fn controls(&mut self, ui: &Ui) {
+		Window::new(im_str!("Controls"))
+			.position([10.0, 10.0], Condition::FirstUseEver)
+			.size([460.0, 70.0], Condition::FirstUseEver)
+			.build(ui, || {
+				if self.running {
+					if ui.button(im_str!("Pause"), [60.0, 0.0]) {
+						self.running = false;
+					}
+				} else if ui.button(im_str!("Run"), [60.0, 0.0]) {
+					self.running = true;
+				}
+				ui.same_line(0.0);
+				if ui.button(im_str!("Step"), [60.0, 0.0]) {
+					self.command(Command::Step(1));
+				}
+				ui.same_line(0.0);
+				if ui.button(im_str!("Next"), [60.0, 0.0]) {
+					self.command(Command::Next);
+				}
+				ui.same_line(0.0);
+				if ui.button(im_str!("Back"), [60.0, 0.0]) {
+					self.command(Command::Back(1));
+				}
+				ui.same_line(0.0);
+				if ui.button(im_str!("Restart"), [60.0, 0.0]) {
+					self.running = false;
+					self.command(Command::Restart);
+				}
+				match self.session.last_stop() {
+					_ if self.running => ui.text("running"),
+					Some(Stop::Error(e)) => ui.text_colored(ERROR_COLOUR, format!("error: {}", e)),
+					Some(stop) => ui.text(stop.to_string()),
+					None => ui.text("not started"),
+				}
+			});
+	}
+
+	fn disassembly(&mut self, ui: &Ui) {
+		let pc = self.session.vm().pc();
+		let lines = if self.follow_pc {
+			self.session.disassemble_around(pc, 10, 30)
+		} else {
+			self.session
+				.disassemble_around(self.listing_start.max(0) as usize, 0, 40)
+		};
+		Window::new(im_str!("Disassembly"))
+			.position([10.0, 90.0], Condition::FirstUseEver)
+			.size([460.0, 660.0], Condition::FirstUseEver)
+			.build(ui, || {
+				ui.checkbox(im_str!("Follow pc"), &mut self.follow_pc);
+				if !self.follow_pc {
+					ui.same_line(0.0);
+					ui.input_int(im_str!("Start"), &mut self.listing_start)
+						.build();
+				}
+				ui.separator();
+				ChildWindow::new(im_str!("listing")).build(ui, || {
+					for line in &lines {
+						self.listing_line(ui, line, pc);
+					}
+				});
+			});
+	}
+
+	// One line of disassembly, with a button to toggle a breakpoint on it
+	fn listing_line(&mut self, ui: &Ui, line: &Line, pc: usize) {
+		let address = line.address();
+		let breakpoint = self
+			.session
+			.vm()
+			.breakpoints()
+			.addresses()
+			.any(|a| a == address);
+		let label = im_str!("{}##break{}", if breakpoint { "*" } else { " " }, address);
+		if ui.small_button(&label) {
+			let command = if breakpoint {
+				Command::Delete(Location::Address(address))
+			} else {
+				Command::Break(Location::Address(address))
+			};
+			self.command(command);
+		}
+		ui.same_line(0.0);
+		let text = line.to_string();
+		if let Line::Code { .. } = line {
+			if address == pc {
+				ui.text_colored(PC_COLOUR, text);
+				return;
+			}
+		}
+		ui.text(text);
+	}
+
+	fn memory(&mut self, ui: &Ui) {
+		Window::new(im_str!("Memory"))
+			.position([480.0, 10.0], Condition::FirstUseEver)
+			.size([530.0, 400.0], Condition::FirstUseEver)
+			.build(ui, || {
+				ui.input_int(im_str!("Address"), &mut self.memory_start)
+					.step(MEMORY_COLUMNS as i32)
+					.build();
+				ui.same_line(0.0);
+				ui.checkbox(im_str!("Hex"), &mut self.hex);
+				ui.separator();
+
+				let start = self.memory_start.max(0) as usize;
+				let memory = self.session.vm().memory();
+				ui.columns(MEMORY_COLUMNS as i32 + 1, im_str!("cells"), false);
+				for row in 0..MEMORY_ROWS {
+					let address = start + row * MEMORY_COLUMNS;
+					ui.text(format!("{:>6}", address));
+					ui.next_column();
+					for cell in address..address + MEMORY_COLUMNS {
+						let value = memory.read(cell);
+						let text = if self.hex {
+							format!("{:x}", value)
+						} else {
+							value.to_string()
+						};
+						if value != self.before.read(cell) {
+							ui.text_colored(CHANGED_COLOUR, text);
+						} else {
+							ui.text(text);
+						}
+						ui.next_column();
+					}
+				}
+				ui.columns(1, im_str!("cells"), false);
+
+				ui.separator();
+				ui.input_text(im_str!("Cell"), &mut self.edit_address)
+					.build();
+				ui.input_text(im_str!("Value"), &mut self.edit_value)
+					.build();
+				if ui.button(im_str!("Write"), [60.0, 0.0]) {
+					// Text fields, as the cell and value can be any usize and i64
+					let address = self.edit_address.to_str().trim();
+					let value = self.edit_value.to_str().trim();
+					match (address.parse::<usize>(), value.parse::<i64>()) {
+						(Ok(address), Ok(value)) => {
+							self.command(Command::SetMemory { address, value })
+						}
+						(Err(_), _) => self.error = Some(format!("invalid address '{}'", address)),
+						(_, Err(_)) => self.error = Some(format!("invalid value '{}'", value)),
+					}
+				}
+			});
+	}
+
+	fn registers(&mut self, ui: &Ui) {
+		let vm = self.session.vm();
+		Window::new(im_str!("Registers"))
+			.position([480.0, 420.0], Condition::FirstUseEver)
+			.size([260.0, 100.0], Condition::FirstUseEver)
+			.build(ui, || {
+				ui.text(format!("pc            {}", vm.pc()));
+				ui.text(format!("relative base {}", vm.relative_base()));
+				ui.text(format!("instructions  {}", vm.instruction_count()));
+			});
+	}
+
+	fn io(&mut self, ui: &Ui) {
+		Window::new(im_str!("Input / Output"))
+			.position([750.0, 420.0], Condition::FirstUseEver)
+			.size([260.0, 330.0], Condition::FirstUseEver)
+			.build(ui, || {
+				let queued: Vec<String> = self
+					.session
+					.vm()
+					.input()
+					.iter()
+					.map(|x| x.to_string())
+					.collect();
+				ui.text(format!("Input queue: [{}]", queued.join(", ")));
+				let entered = ui
+					.input_text(im_str!("##input"), &mut self.input)
+					.enter_returns_true(true)
+					.build();
+				ui.same_line(0.0);
+				if (ui.button(im_str!("Queue"), [0.0, 0.0]) || entered) && !self.input.is_empty() {
+					// Numbers, or text in quotes, as for the `input` command
+					match Command::parse(&format!("input {}", self.input.to_str())) {
+						Ok(command) => {
+							self.command(command);
+							self.input.clear();
+						}
+						Err(e) => self.error = Some(e.to_string()),
+					}
+				}
+
+				ui.separator();
+				let output = self.session.output();
+				ui.text(format!("Output ({} values)", output.len()));
+				ChildWindow::new(im_str!("output")).build(ui, || {
+					let (text, _) = intcode_vm::ascii::decode(output);
+					if !text.is_empty() {
+						ui.text_wrapped(&ImString::new(text));
+						ui.separator();
+					}
+					let shown = output.len().saturating_sub(OUTPUT_SHOWN);
+					for value in &output[shown..] {
+						ui.text(value.to_string());
+					}
+				});
+			});
+	}
+
+	fn console(&mut self, ui: &Ui) {
+		Window::new(im_str!("Console"))
+			.position([10.0, 760.0], Condition::FirstUseEver)
+			.size([1000.0, 200.0], Condition::FirstUseEver)
+			.build(ui, || {
+				if let Some(error) = &self.error {
+					ui.text_colored(ERROR_COLOUR, error);
+				}
+				let entered = ui
+					.input_text(im_str!("Command"), &mut self.console)
+					.enter_returns_true(true)
+					.build();
+				if entered && !self.console.is_empty() {
+					let line = self.console.to_str().to_string();
+					self.console.clear();
+					self.log.push(format!("> {}", line));
+					match Command::parse(&line) {
+						Ok(command) => {
+							if let Some(response) = self.command(command) {
+								self.log.extend(response.lines().map(String::from));
+							}
+						}
+						Err(e) => self.error = Some(e.to_string()),
+					}
+				}
+				ChildWindow::new(im_str!("log")).build(ui, || {
+					for line in &self.log {
+						ui.text(line);
+					}
+				});
+			});
+	}
+
+	// Output decoded as a grid of tiles, e.g. the arcade cabinet's screen
+	fn world(&mut self, ui: &Ui) {
+		self.grid.follow(self.session.output());
+		Window::new(im_str!("World"))
+			.position([1020.0, 10.0], Condition::FirstUseEver)
+			.size([500.0, 740.0], Condition::FirstUseEver)
+			.build(ui, || {
+				let mut decoder = self.grid.decoder();
+				ui.radio_button(im_str!("x, y, tile"), &mut decoder, Decoder::Triples);
+				ui.same_line(0.0);
+				ui.radio_button(im_str!("ASCII"), &mut decoder, Decoder::Ascii);
+				ui.same_line(0.0);
+				ui.checkbox(im_str!("Text"), &mut self.as_text);
+				if decoder != self.grid.decoder() {
+					self.grid.set_decoder(decoder, self.session.output());
+				}
+
+				let changed = ui
+					.input_text(im_str!("Palette"), &mut self.palette_text)
+					.enter_returns_true(true)
+					.build();
+				if changed {
+					self.palette = match self.palette_text.to_str() {
+						"" => Palette::tiles(),
+						text => match Palette::parse(text) {
+							Ok(palette) => palette,
+							Err(e) => {
+								self.error = Some(e.to_string());
+								return;
+							}
+						},
+					};
+				}
+				if let Some(status) = self.grid.status() {
+					ui.text(format!("Status: {}", status));
+				}
+				ui.separator();
+
+				ChildWindow::new(im_str!("tiles"))
+					.horizontal_scrollbar(true)
+					.build(ui, || {
+						if self.as_text || self.grid.decoder() == Decoder::Ascii {
+							ui.text(self.grid.render(&self.palette));
+							return;
+						}
+						let (_, (width, height)) = match self.grid.extent() {
+							Some(extent) => extent,
+							None => return,
+						};
+						let origin = ui.cursor_screen_pos();
+						let draw_list = ui.get_window_draw_list();
+						for ((column, row), value) in self.grid.cells() {
+							let [r, g, b] = self.palette.tile(value).colour;
+							let left = origin[0] + column as f32 * TILE_SIZE;
+							let top = origin[1] + row as f32 * TILE_SIZE;
+							let colour = [
+								f32::from(r) / 255.0,
+								f32::from(g) / 255.0,
+								f32::from(b) / 255.0,
+								1.0,
+							];
+							draw_list
+								.add_rect([left, top], [left + TILE_SIZE, top + TILE_SIZE], colour)
+								.filled(true)
+								.build();
+						}
+						// Reserve the space drawn over so the window scrolls
+						let width = width as f32 * TILE_SIZE;
+						let height = height as f32 * TILE_SIZE;
+						ui.dummy([width, height]);
+					});
+			});
+	}
+}
@@ -0,0 +1,125 @@
+/*
+	--- Intcode VM: interpreter benchmarks ---
+
+	Compares running with the decode cache against decoding every instruction
+	as it executes, and against compiling basic blocks for the long running
+	day 9 program. Run with `cargo bench`.
+*/
+
+#![allow(clippy::needless_return)]
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use intcode_vm::compile::CompiledVM;
+use intcode_vm::network::{Network, Topology};
+use intcode_vm::{parse_program, Status, VM};
+
+fn vm(program: &[i64], cached: bool) -> VM {
+	let mut vm = VM::from_memory(program);
+	vm.set_decode_cache(cached);
+	return vm;
+}
+
+fn label(cached: bool) -> &'static str {
+	return if cached { "cached" } else { "uncached" };
+}
+
+// Day 2 part 2: try every noun and verb until the output matches. Resetting
+// keeps the decode cache, so after the first run every run is on a warm cache.
+fn noun_verb_search(c: &mut Criterion) {
+	let program = parse_program(include_str!("../../../input/2019/day2.txt")).unwrap();
+	let mut group = c.benchmark_group("day2 noun/verb search");
+	for &cached in &[false, true] {
+		let mut machine = vm(&program, cached);
+		group.bench_function(label(cached), |b| {
+			b.iter(|| {
+				for noun in 0..100 {
+					for verb in 0..100 {
+						machine.reset(&program);
+						machine.write_memory(1, noun).unwrap();
+						machine.write_memory(2, verb).unwrap();
+						assert_eq!(machine.run_intcode(), Ok(Status::Halt));
+						if machine.memory().read(0) == 19690720 {
+							return 100 * noun + verb;
+						}
+					}
+				}
+				panic!("No noun and verb found");
+			})
+		});
+	}
+	group.finish();
+}
+
+// Day 9 part 2: a long running program that spends its time in a few loops
+fn sensor_boost(c: &mut Criterion) {
+	let program = parse_program(include_str!("../../../input/2019/day9.txt")).unwrap();
+	let mut group = c.benchmark_group("day9 sensor boost");
+	group.sample_size(20);
+	for &cached in &[false, true] {
+		group.bench_function(label(cached), |b| {
+			b.iter(|| {
+				vm(&program, cached)
+					.run_until_halt(&[2])
+					.unwrap()
+					.last_output()
+			})
+		});
+	}
+	group.bench_function("compiled", |b| {
+		b.iter(|| {
+			CompiledVM::from_memory(&program)
+				.run_until_halt(&[2])
+				.unwrap()
+				.last_output()
+		})
+	});
+	group.finish();
+}
+
+// Day 7 part 2: every ordering of five amplifiers in a feedback loop
+fn amplifiers(c: &mut Criterion) {
+	let program = parse_program(include_str!("../../../input/2019/day7.txt")).unwrap();
+	let mut group = c.benchmark_group("day7 amplifiers");
+	for &cached in &[false, true] {
+		group.bench_with_input(
+			BenchmarkId::new(label(cached), 120),
+			&cached,
+			|b, &cached| {
+				b.iter(|| {
+					let mut best = 0;
+					for phases in permutations(&[5, 6, 7, 8, 9]) {
+						let mut network = Network::new(Topology::Ring);
+						for &phase in &phases {
+							let amplifier = network.add(vm(&program, cached));
+							network.queue_input(amplifier, phase);
+						}
+						network.queue_input(0, 0);
+						network.run_to_completion().unwrap();
+						best = best.max(network.last_output(4).unwrap());
+					}
+					return best;
+				})
+			},
+		);
+	}
+	group.finish();
+}
+
+fn permutations(values: &[i64]) -> Vec<Vec<i64>> {
+	if values.len() <= 1 {
+		return vec![values.to_vec()];
+	}
+	let mut result = Vec::new();
+	for (i, &first) in values.iter().enumerate() {
+		let mut rest = values.to_vec();
+		rest.remove(i);
+		for mut tail in permutations(&rest) {
+			tail.insert(0, first);
+			result.push(tail);
+		}
+	}
+	return result;
+}
+
+criterion_group!(benches, noun_verb_search, sensor_boost, amplifiers);
+criterion_main!(benches);
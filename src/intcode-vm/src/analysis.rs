@@ -0,0 +1,629 @@
+/*
+	--- Intcode VM: control-flow analysis ---
+
+	Builds a control-flow graph of the code reachable from a start address:
+	basic blocks joined by edges for each way execution can leave them. Jumps
+	through memory can't be followed, so their edges have no target.
+
+	Programs compiled for Intcode share a calling convention built on the
+	relative base. The caller stores the return address at rb+0 and jumps to
+	the subroutine, which moves the relative base past its frame with ARB,
+	then moves it back and jumps to rb+0 to return:
+
+		ADD #13, #0, rb+0    call 1424, returning to 13
+		JIF #0, #1424
+		...
+		1424: ARB #3         subroutine with a 3 cell frame
+		...
+		ARB #-3
+		JIT #1, rb+0         return
+
+	Storing the address of the next instruction and then jumping is treated
+	as a call, so the graph carries on after it. A subroutine is the code
+	reachable from a call target without following further calls, along
+	with how far each of its blocks has moved the relative base.
+*/
+
+use crate::disasm::{decode, Instruction, Operand};
+use crate::Opcode;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::Write;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EdgeKind {
+	Fallthrough, // On to the next instruction, including a branch not taken
+	Branch,      // A conditional jump taken
+	Jump,
+	Call,
+	AfterCall, // Where a call returns to
+	Return,    // A jump to a return address on the stack
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Edge {
+	pub kind: EdgeKind,
+	pub target: Option<usize>, // None if the target is computed at run time
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+	pub start: usize,
+	pub instructions: Vec<(usize, Instruction)>,
+	pub edges: Vec<Edge>,        // None if it halts or runs into a non-instruction
+	pub reads: BTreeSet<usize>,  // Absolute addresses read
+	pub writes: BTreeSet<usize>, // Absolute addresses written
+}
+
+impl Block {
+	// Address just past the last instruction
+	pub fn end(&self) -> usize {
+		let (address, instruction) = self.instructions.last().expect("Empty block");
+		return address + instruction.size();
+	}
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Subroutine {
+	pub entry: usize,
+	pub frame: Option<i64>,       // Cells allocated by an ARB on entry
+	pub callers: BTreeSet<usize>, // Addresses of the jumps that call it
+	// Start of each block, with how far the relative base has moved from the
+	// entry when the block starts, or None if that depends on the path taken
+	pub blocks: BTreeMap<usize, Option<i64>>,
+}
+
+// Edges out of the instruction at each address that ends a block
+type Exits = BTreeMap<usize, Vec<Edge>>;
+
+pub struct Cfg {
+	start: usize,
+	instructions: BTreeMap<usize, Instruction>,
+	blocks: BTreeMap<usize, Block>,
+	subroutines: BTreeMap<usize, Subroutine>, // Including one for the start address
+}
+
+impl Cfg {
+	pub fn build(memory: &[i64], start: usize) -> Self {
+		let (instructions, leaders, exits) = explore(memory, start);
+		let mut cfg = Cfg {
+			start,
+			instructions,
+			blocks: BTreeMap::new(),
+			subroutines: BTreeMap::new(),
+		};
+		for &leader in &leaders {
+			if let Some(block) = cfg.make_block(leader, &leaders, &exits) {
+				cfg.blocks.insert(leader, block);
+			}
+		}
+
+		let mut callers: BTreeMap<usize, BTreeSet<usize>> = BTreeMap::new();
+		callers.entry(start).or_default();
+		for block in cfg.blocks.values() {
+			for edge in &block.edges {
+				if let (EdgeKind::Call, Some(target)) = (edge.kind, edge.target) {
+					let (call, _) = block.instructions.last().expect("Empty block");
+					callers.entry(target).or_default().insert(*call);
+				}
+			}
+		}
+		for (entry, callers) in callers {
+			if cfg.blocks.contains_key(&entry) {
+				let subroutine = cfg.subroutine(entry, callers);
+				cfg.subroutines.insert(entry, subroutine);
+			}
+		}
+		return cfg;
+	}
+
+	pub fn start(&self) -> usize {
+		return self.start;
+	}
+
+	// Reachable instructions in address order
+	pub fn instructions(&self) -> impl Iterator<Item = (usize, &Instruction)> {
+		return self
+			.instructions
+			.iter()
+			.map(|(&address, instruction)| (address, instruction));
+	}
+
+	// Whether `address` is part of a reachable instruction, rather than data
+	pub fn is_code(&self, address: usize) -> bool {
+		return match self.instructions.range(..=address).next_back() {
+			Some((&start, instruction)) => address < start + instruction.size(),
+			None => false,
+		};
+	}
+
+	pub fn blocks(&self) -> impl Iterator<Item = &Block> {
+		return self.blocks.values();
+	}
+
+	// The block starting at `address`
+	pub fn block(&self, address: usize) -> Option<&Block> {
+		return self.blocks.get(&address);
+	}
+
+	pub fn subroutines(&self) -> impl Iterator<Item = &Subroutine> {
+		return self.subroutines.values();
+	}
+
+	// The subroutine entered at `address`
+	pub fn subroutine_at(&self, address: usize) -> Option<&Subroutine> {
+		return self.subroutines.get(&address);
+	}
+
+	// Writes into reachable code, as (instruction address, address written).
+	// These usually patch an operand to read through a pointer, so what that
+	// instruction does can't be known statically.
+	pub fn patches(&self) -> Vec<(usize, usize)> {
+		let mut patches = Vec::new();
+		for block in self.blocks.values() {
+			for (address, instruction) in &block.instructions {
+				if let Some(target) = write_address(instruction) {
+					if self.is_code(target) {
+						patches.push((*address, target));
+					}
+				}
+			}
+		}
+		return patches;
+	}
+
+	// Renders the graph in Graphviz DOT format, with each subroutine drawn as
+	// a cluster of its blocks
+	pub fn to_dot(&self) -> String {
+		let mut dot = String::from("digraph intcode {\n");
+		dot.push_str("\tnode [shape=box, fontname=\"monospace\"];\n");
+		let mut drawn = BTreeSet::new();
+		for subroutine in self.subroutines.values() {
+			let blocks: Vec<usize> = subroutine
+				.blocks
+				.keys()
+				.copied()
+				.filter(|block| drawn.insert(*block))
+				.collect();
+			let _ = writeln!(dot, "\tsubgraph cluster_{} {{", subroutine.entry);
+			let _ = writeln!(
+				dot,
+				"\t\tlabel=\"{}\";",
+				subroutine_label(subroutine, self.start)
+			);
+			for block in blocks {
+				dot.push_str("\t\t");
+				self.write_node(&mut dot, &self.blocks[&block], subroutine.blocks[&block]);
+			}
+			dot.push_str("\t}\n");
+		}
+		for block in self
+			.blocks
+			.values()
+			.filter(|block| !drawn.contains(&block.start))
+		{
+			dot.push('\t');
+			self.write_node(&mut dot, block, None);
+		}
+
+		let mut unknown = false;
+		for block in self.blocks.values() {
+			for edge in &block.edges {
+				let target = match (edge.kind, edge.target) {
+					(EdgeKind::Return, None) => continue, // Shown on the block itself
+					(_, Some(target)) if self.blocks.contains_key(&target) => {
+						format!("b{}", target)
+					}
+					(_, Some(target)) => {
+						let _ = writeln!(
+							dot,
+							"\tx{} [label=\"{}: invalid\", shape=plaintext];",
+							target, target
+						);
+						format!("x{}", target)
+					}
+					(_, None) => {
+						unknown = true;
+						String::from("unknown")
+					}
+				};
+				let style = match edge.kind {
+					EdgeKind::Fallthrough => "",
+					EdgeKind::Branch => " [color=darkgreen]",
+					EdgeKind::Jump => " [color=blue]",
+					EdgeKind::Call => " [style=dashed, label=\"call\"]",
+					EdgeKind::AfterCall => " [style=dotted]",
+					EdgeKind::Return => " [style=dashed, label=\"return\"]",
+				};
+				let _ = writeln!(dot, "\tb{} -> {}{};", block.start, target, style);
+			}
+		}
+		if unknown {
+			dot.push_str("\tunknown [label=\"?\", shape=circle];\n");
+		}
+		dot.push_str("}\n");
+		return dot;
+	}
+
+	fn write_node(&self, dot: &mut String, block: &Block, rb_offset: Option<i64>) {
+		let mut label = String::new();
+		for (address, instruction) in &block.instructions {
+			let _ = write!(label, "{:>5}: {}\\l", address, instruction);
+		}
+		let returns = block.edges.iter().any(|edge| edge.kind == EdgeKind::Return);
+		if returns {
+			label.push_str("return\\l");
+		}
+		let _ = write!(dot, "b{} [label=\"{}\"", block.start, label);
+		if returns || block.edges.is_empty() {
+			dot.push_str(", peripheries=2");
+		}
+		if let Some(offset) = rb_offset.filter(|&offset| offset != 0) {
+			let _ = write!(dot, ", xlabel=\"rb{:+}\"", offset);
+		}
+		dot.push_str("];\n");
+	}
+
+	// Follows instructions from `leader` until one leaves the block
+	fn make_block(&self, leader: usize, leaders: &BTreeSet<usize>, exits: &Exits) -> Option<Block> {
+		let mut block = Block {
+			start: leader,
+			instructions: Vec::new(),
+			edges: Vec::new(),
+			reads: BTreeSet::new(),
+			writes: BTreeSet::new(),
+		};
+		let mut address = leader;
+		while let Some(instruction) = self.instructions.get(&address) {
+			block.reads.extend(read_addresses(instruction));
+			block.writes.extend(write_address(instruction));
+			block.instructions.push((address, instruction.clone()));
+			if let Some(edges) = exits.get(&address) {
+				block.edges = edges.clone();
+				break;
+			}
+			address += instruction.size();
+			if leaders.contains(&address) {
+				block.edges.push(Edge {
+					kind: EdgeKind::Fallthrough,
+					target: Some(address),
+				});
+				break;
+			}
+		}
+		if block.instructions.is_empty() {
+			return None; // A jump target that isn't a valid instruction
+		}
+		return Some(block);
+	}
+
+	// Finds the blocks of the subroutine at `entry`, working out how far each
+	// has moved the relative base. Calls are assumed to leave it unchanged.
+	fn subroutine(&self, entry: usize, callers: BTreeSet<usize>) -> Subroutine {
+		let mut blocks = BTreeMap::new();
+		blocks.insert(entry, Some(0));
+		let mut pending = vec![entry];
+		while let Some(start) = pending.pop() {
+			let block = &self.blocks[&start];
+			let mut offset: Option<i64> = blocks[&start];
+			for (_, instruction) in &block.instructions {
+				if instruction.opcode == Opcode::ARB {
+					offset = match (offset, instruction.operands[0]) {
+						(Some(offset), Operand::Immediate(delta)) => offset.checked_add(delta),
+						_ => None,
+					};
+				}
+			}
+			for edge in &block.edges {
+				let target = match (edge.kind, edge.target) {
+					(EdgeKind::Call, _) | (EdgeKind::Return, _) => continue,
+					(_, Some(target)) if self.blocks.contains_key(&target) => target,
+					_ => continue,
+				};
+				let merged = match blocks.get(&target) {
+					None => offset,
+					Some(&existing) if existing == offset || existing.is_none() => continue,
+					Some(_) => None,
+				};
+				blocks.insert(target, merged);
+				pending.push(target);
+			}
+		}
+
+		let frame = match self.blocks[&entry].instructions[0].1 {
+			Instruction {
+				opcode: Opcode::ARB,
+				ref operands,
+			} => match operands[0] {
+				Operand::Immediate(size) => Some(size),
+				_ => None,
+			},
+			_ => None,
+		};
+		return Subroutine {
+			entry,
+			frame,
+			callers,
+			blocks,
+		};
+	}
+}
+
+// Decodes everything reachable from `start`, returning the instructions, the
+// addresses that start blocks, and the edges out of each jump or HALT
+fn explore(memory: &[i64], start: usize) -> (BTreeMap<usize, Instruction>, BTreeSet<usize>, Exits) {
+	let mut instructions = BTreeMap::new();
+	let mut leaders = BTreeSet::new();
+	let mut exits = BTreeMap::new();
+	let mut pending = VecDeque::new();
+	leaders.insert(start);
+	pending.push_back(start);
+	while let Some(mut address) = pending.pop_front() {
+		let mut stored_constant = None; // Last immediate value written to memory
+		loop {
+			if instructions.contains_key(&address) {
+				// Joins code already seen, so a block has to start here
+				leaders.insert(address);
+				break;
+			}
+			let instruction = match decode(memory, address) {
+				Some(instruction) => instruction,
+				None => break,
+			};
+			let next = address + instruction.size();
+			let edges = exit_edges(&instruction, next, stored_constant);
+			stored_constant = constant_store(&instruction);
+			instructions.insert(address, instruction);
+			if let Some(edges) = edges {
+				for target in edges.iter().filter_map(|edge| edge.target) {
+					if leaders.insert(target) {
+						pending.push_back(target);
+					}
+				}
+				exits.insert(address, edges);
+				break;
+			}
+			address = next;
+		}
+	}
+	return (instructions, leaders, exits);
+}
+
+// Where execution can go after a jump or HALT, or None for any other
+// instruction, which just carries on to the next
+fn exit_edges(
+	instruction: &Instruction,
+	next: usize,
+	stored_constant: Option<i64>,
+) -> Option<Vec<Edge>> {
+	let edge = |kind, target| Edge { kind, target };
+	match instruction.opcode {
+		Opcode::HALT => return Some(Vec::new()),
+		Opcode::JIT | Opcode::JIF => (),
+		_ => return None,
+	}
+	let target = instruction
+		.jump_target()
+		.filter(|&target| target >= 0)
+		.map(|target| target as usize);
+	if !instruction.is_terminator() {
+		return Some(vec![
+			edge(EdgeKind::Branch, target),
+			edge(EdgeKind::Fallthrough, Some(next)),
+		]);
+	}
+	if stored_constant == Some(next as i64) {
+		return Some(vec![
+			edge(EdgeKind::Call, target),
+			edge(EdgeKind::AfterCall, Some(next)),
+		]);
+	}
+	if let Operand::Relative(_) = instruction.operands[1] {
+		return Some(vec![edge(EdgeKind::Return, None)]);
+	}
+	return Some(vec![edge(EdgeKind::Jump, target)]);
+}
+
+// The value written by an ADD/MUL of two immediates, unknown if it overflows
+fn constant_store(instruction: &Instruction) -> Option<i64> {
+	match (instruction.opcode, &instruction.operands[..]) {
+		(Opcode::ADD, [Operand::Immediate(a), Operand::Immediate(b), _]) => a.checked_add(*b),
+		(Opcode::MUL, [Operand::Immediate(a), Operand::Immediate(b), _]) => a.checked_mul(*b),
+		_ => None,
+	}
+}
+
+// Absolute addresses an instruction reads
+fn read_addresses(instruction: &Instruction) -> impl Iterator<Item = usize> + '_ {
+	let sources = if instruction.opcode.writes() {
+		instruction.operands.len() - 1
+	} else {
+		instruction.operands.len()
+	};
+	return instruction.operands[..sources]
+		.iter()
+		.filter_map(|operand| match *operand {
+			Operand::Address(address) if address >= 0 => Some(address as usize),
+			_ => None,
+		});
+}
+
+// The absolute address an instruction writes, if any
+fn write_address(instruction: &Instruction) -> Option<usize> {
+	if !instruction.opcode.writes() {
+		return None;
+	}
+	match instruction.operands.last() {
+		Some(&Operand::Address(address)) if address >= 0 => Some(address as usize),
+		_ => None,
+	}
+}
+
+fn subroutine_label(subroutine: &Subroutine, start: usize) -> String {
+	let mut label = if subroutine.entry == start {
+		String::from("start")
+	} else {
+		format!("sub_{}", subroutine.entry)
+	};
+	if let Some(frame) = subroutine.frame {
+		let _ = write!(label, ", frame {}", frame);
+	}
+	if !subroutine.callers.is_empty() {
+		let _ = write!(label, ", {} callers", subroutine.callers.len());
+	}
+	return label;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[rustfmt::skip]
+	const PROGRAM: [i64; 34] = [
+		109, 10,          //  0: ARB #10
+		21101, 9, 0, 0,   //  2: ADD #9, #0, rb+0
+		1106, 0, 13,      //  6: JIF #0, #13     call 13
+		99,               //  9: HALT
+		7, 7, 7,          // 10: data
+		109, 2,           // 13: ARB #2
+		1001, 32, 1, 32,  // 15: ADD [32], #1, [32]
+		1008, 32, 3, 33,  // 19: EQ [32], #3, [33]
+		1006, 33, 15,     // 23: JIF [33], #15
+		109, -2,          // 26: ARB #-2
+		2105, 1, 0,       // 28: JIT #1, rb+0    return
+		0,                // 31: unused
+		0, 0,             // 32: counter and flag
+	];
+
+	fn edges(cfg: &Cfg, block: usize) -> Vec<(EdgeKind, Option<usize>)> {
+		return cfg
+			.block(block)
+			.unwrap()
+			.edges
+			.iter()
+			.map(|edge| (edge.kind, edge.target))
+			.collect();
+	}
+
+	#[test]
+	fn finds_blocks_and_subroutines() {
+		let cfg = Cfg::build(&PROGRAM, 0);
+		let starts: Vec<usize> = cfg.blocks().map(|block| block.start).collect();
+		assert_eq!(starts, [0, 9, 13, 15, 26]);
+		assert_eq!(
+			edges(&cfg, 0),
+			[(EdgeKind::Call, Some(13)), (EdgeKind::AfterCall, Some(9))]
+		);
+		assert_eq!(edges(&cfg, 9), []);
+		assert_eq!(edges(&cfg, 13), [(EdgeKind::Fallthrough, Some(15))]);
+		assert_eq!(
+			edges(&cfg, 15),
+			[
+				(EdgeKind::Branch, Some(15)),
+				(EdgeKind::Fallthrough, Some(26))
+			]
+		);
+		assert_eq!(edges(&cfg, 26), [(EdgeKind::Return, None)]);
+		assert_eq!(cfg.block(26).unwrap().end(), 31);
+
+		assert!(cfg.is_code(8) && cfg.is_code(30));
+		assert!(!cfg.is_code(10) && !cfg.is_code(31));
+
+		let main = cfg.subroutine_at(0).unwrap();
+		assert_eq!(main.blocks.keys().copied().collect::<Vec<_>>(), [0, 9]);
+		assert_eq!(main.blocks[&9], Some(10));
+		let sub = cfg.subroutine_at(13).unwrap();
+		assert_eq!(sub.frame, Some(2));
+		assert_eq!(sub.callers.iter().copied().collect::<Vec<_>>(), [6]);
+		assert_eq!(
+			sub.blocks.iter().map(|(&b, &o)| (b, o)).collect::<Vec<_>>(),
+			[(13, Some(0)), (15, Some(2)), (26, Some(2))]
+		);
+	}
+
+	#[test]
+	fn data_flow() {
+		let cfg = Cfg::build(&PROGRAM, 0);
+		let block = cfg.block(15).unwrap();
+		assert_eq!(block.reads.iter().copied().collect::<Vec<_>>(), [32, 33]);
+		assert_eq!(block.writes.iter().copied().collect::<Vec<_>>(), [32, 33]);
+		assert_eq!(cfg.patches(), []);
+
+		// Writes its input over the operand of the OUT, then maybe jumps
+		// to the address in [10]
+		#[rustfmt::skip]
+		let program = [
+			3, 3,     // 0: IN [3]
+			4, 0,     // 2: OUT [0]
+			5, 9, 10, // 4: JIT [9], [10]
+			99,       // 7: HALT
+			99,       // 8: never reached
+			1, 0,
+		];
+		let cfg = Cfg::build(&program, 0);
+		let block = cfg.block(0).unwrap();
+		assert_eq!(
+			edges(&cfg, 0),
+			[(EdgeKind::Branch, None), (EdgeKind::Fallthrough, Some(7))]
+		);
+		assert_eq!(block.reads.iter().copied().collect::<Vec<_>>(), [0, 9, 10]);
+		assert_eq!(block.writes.iter().copied().collect::<Vec<_>>(), [3]);
+		assert_eq!(cfg.patches(), [(0, 3)]);
+		assert!(!cfg.is_code(8));
+		assert!(cfg
+			.to_dot()
+			.contains("\tb0 -> unknown [color=darkgreen];\n"));
+	}
+
+	#[test]
+	fn overflowing_constants() {
+		let program = [1101, i64::MAX, 1, 0, 1105, 1, 0];
+		let cfg = Cfg::build(&program, 0);
+		assert_eq!(cfg.patches(), [(0, 0)]);
+		assert!(cfg.is_code(4));
+		assert_eq!(crate::disasm::disassemble_reachable(&program, 0).len(), 2);
+
+		// A subroutine whose relative base overflows
+		#[rustfmt::skip]
+		let program = [
+			21101, 7, 0, 0,   //  0: ADD #7, #0, rb+0
+			1106, 0, 8,       //  4: JIF #0, #8      call 8
+			99,               //  7: HALT
+			109, i64::MAX,    //  8: ARB #MAX
+			109, 1,           // 10: ARB #1
+			2105, 1, 0,       // 12: JIT #1, rb+0    return
+		];
+		let cfg = Cfg::build(&program, 0);
+		assert_eq!(cfg.subroutine_at(8).unwrap().frame, Some(i64::MAX));
+	}
+
+	#[test]
+	fn exports_dot() {
+		let dot = Cfg::build(&PROGRAM, 0).to_dot();
+		assert!(dot.starts_with("digraph intcode {\n"));
+		assert!(dot.contains("subgraph cluster_13 {\n\t\tlabel=\"sub_13, frame 2, 1 callers\";"));
+		assert!(dot.contains("\t\tb15 [label=\"   15: ADD [32], #1, [32]\\l   19: EQ [32], #3, [33]\\l   23: JIF [33], #15\\l\", xlabel=\"rb+2\"];\n"));
+		assert!(dot.contains("\tb0 -> b13 [style=dashed, label=\"call\"];\n"));
+		assert!(dot.contains("\tb15 -> b15 [color=darkgreen];\n"));
+		assert!(dot.contains("return\\l\", peripheries=2"));
+		assert!(!dot.contains("unknown"));
+	}
+
+	#[test]
+	fn analyses_puzzles() {
+		let program = crate::parse_program(include_str!("../../../input/2019/day25.txt")).unwrap();
+		let cfg = Cfg::build(&program, 0);
+		let print = cfg.subroutine_at(1234).unwrap();
+		assert_eq!(print.frame, Some(2));
+		assert!(print.callers.len() > 5);
+		assert!(cfg.patches().contains(&(1176, 1181)));
+		// Calls through a function pointer
+		assert!(cfg.blocks().any(|block| block.edges.first()
+			== Some(&Edge {
+				kind: EdgeKind::Call,
+				target: None
+			})));
+		assert!(cfg
+			.to_dot()
+			.contains("unknown [label=\"?\", shape=circle];"));
+	}
+}
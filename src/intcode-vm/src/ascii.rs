@@ -0,0 +1,188 @@
+/*
+	--- Intcode VM: ASCII adapter ---
+*/
+
+use crate::{Status, VmError, VM};
+use std::io::{self, BufRead, Write};
+
+// Everything a program printed between two prompts
+#[derive(Clone, Debug, PartialEq)]
+pub struct AsciiOutput {
+	pub status: Status,   // Why the program stopped printing
+	pub text: String,     // Every output in the ASCII range
+	pub values: Vec<i64>, // Outputs outside the ASCII range, usually the puzzle answer
+}
+
+// Encodes a line of text as character codes, terminated by a newline
+pub fn encode_line(line: &str) -> Vec<i64> {
+	return line
+		.bytes()
+		.chain(std::iter::once(b'\n'))
+		.map(i64::from)
+		.collect();
+}
+
+// Splits raw output into text and out-of-range values
+pub fn decode(output: &[i64]) -> (String, Vec<i64>) {
+	let mut text = String::new();
+	let mut values = Vec::new();
+	for &value in output {
+		if (0..128).contains(&value) {
+			text.push(value as u8 as char);
+		} else {
+			values.push(value);
+		}
+	}
+	return (text, values);
+}
+
+// Wraps a VM running a program that talks in ASCII, e.g. the springdroid or the
+// text adventure
+pub struct AsciiTerminal {
+	vm: VM,
+}
+
+impl AsciiTerminal {
+	pub fn new(vm: VM) -> Self {
+		AsciiTerminal { vm }
+	}
+
+	pub fn from_memory(memory: &[i64]) -> Self {
+		AsciiTerminal::new(VM::from_memory(memory))
+	}
+
+	pub fn vm(&self) -> &VM {
+		return &self.vm;
+	}
+
+	pub fn vm_mut(&mut self) -> &mut VM {
+		return &mut self.vm;
+	}
+
+	pub fn into_inner(self) -> VM {
+		return self.vm;
+	}
+
+	// Queues a line of text, the trailing newline is added automatically
+	pub fn send_line(&mut self, line: &str) {
+		for value in encode_line(line) {
+			self.vm.queue_input(value);
+		}
+	}
+
+	// Runs until the program wants input or halts
+	pub fn read(&mut self) -> Result<AsciiOutput, VmError> {
+		let result = self.vm.run_until_input()?;
+		let (text, values) = decode(&result.output);
+		return Ok(AsciiOutput {
+			status: result.status,
+			text,
+			values,
+		});
+	}
+
+	// Runs until the printed text ends with `prompt`, the program wants input or halts
+	pub fn read_until(&mut self, prompt: &str) -> Result<AsciiOutput, VmError> {
+		let mut output = AsciiOutput {
+			status: Status::NewOutput,
+			text: String::new(),
+			values: Vec::new(),
+		};
+		loop {
+			let result = self.vm.run_until_outputs(1)?;
+			let (text, mut values) = decode(&result.output);
+			output.text.push_str(&text);
+			output.values.append(&mut values);
+			output.status = result.status;
+			if result.status != Status::NewOutput || output.text.ends_with(prompt) {
+				return Ok(output);
+			}
+		}
+	}
+
+	// Sends a line and reads the response up to the next prompt
+	pub fn command(&mut self, line: &str) -> Result<AsciiOutput, VmError> {
+		self.send_line(line);
+		return self.read();
+	}
+
+	// Plays the program interactively, e.g. over stdin/stdout, until it halts or
+	// the input runs out
+	pub fn run_interactive<R: BufRead, W: Write>(
+		&mut self,
+		mut input: R,
+		mut output: W,
+	) -> Result<Status, VmError> {
+		loop {
+			let response = self.read()?;
+			let _ = write!(output, "{}", response.text);
+			for value in &response.values {
+				let _ = writeln!(output, "{}", value);
+			}
+			let _ = output.flush();
+			if response.status == Status::Halt {
+				return Ok(Status::Halt);
+			}
+
+			let mut line = String::new();
+			match input.read_line(&mut line) {
+				Ok(0) | Err(_) => return Ok(response.status), // End of input
+				Ok(_) => self.send_line(line.trim_end_matches(&['\r', '\n'][..])),
+			}
+		}
+	}
+
+	pub fn run_stdio(&mut self) -> Result<Status, VmError> {
+		let stdin = io::stdin();
+		return self.run_interactive(stdin.lock(), io::stdout());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Echoes each input back until it reads a zero, then prints 1000 and halts
+	const ECHO: [i64; 16] = [
+		3, 100, 1006, 100, 11, 4, 100, 1105, 1, 0, 99, 104, 1000, 99, 0, 0,
+	];
+
+	#[test]
+	fn encoding() {
+		assert_eq!(encode_line("A,B"), [65, 44, 66, 10]);
+		assert_eq!(
+			decode(&[72, 105, 10, 19349974]),
+			(String::from("Hi\n"), vec![19349974])
+		);
+	}
+
+	#[test]
+	fn terminal() {
+		let mut terminal = AsciiTerminal::from_memory(&ECHO);
+		let response = terminal.command("hello").unwrap();
+		assert_eq!(response.status, Status::WaitForInput);
+		assert_eq!(response.text, "hello\n");
+
+		terminal.send_line("one");
+		let response = terminal.read_until("o").unwrap();
+		assert_eq!(response.status, Status::NewOutput);
+		assert_eq!(response.text, "o");
+
+		terminal.vm_mut().queue_input(0);
+		let response = terminal.read().unwrap();
+		assert_eq!(response.status, Status::Halt);
+		assert_eq!(response.text, "ne\n");
+		assert_eq!(response.values, [1000]);
+	}
+
+	#[test]
+	fn interactive() {
+		let mut terminal = AsciiTerminal::from_memory(&ECHO);
+		let mut output = Vec::new();
+		let status = terminal
+			.run_interactive(&b"ab\ncd\n"[..], &mut output)
+			.unwrap();
+		assert_eq!(status, Status::WaitForInput);
+		assert_eq!(String::from_utf8(output).unwrap(), "ab\ncd\n");
+	}
+}
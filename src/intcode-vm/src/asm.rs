@@ -0,0 +1,441 @@
+/*
+	--- Intcode VM: assembler ---
+
+	Source is one instruction per line, using the same syntax as the
+	disassembler so listings can be edited and rebuilt:
+
+		; Echo inputs until a zero is read
+		loop:   IN [value]
+				JIF [value], #done
+				OUT [value]
+				JIT #1, #loop
+		done:   HALT
+		value:  .data 0
+
+	Operands are `#imm`, `[addr]` or `rb+n`, where values may be numbers,
+	labels or sums of them such as `table+2`. `.data a, b, ...` (or `DATA`)
+	emits raw words and `.zero n` emits n zeroes. Address prefixes such as
+	`  12:` from a listing are ignored.
+*/
+
+use crate::disasm::{Instruction, Operand};
+use crate::{MemMode, Opcode};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AsmError {
+	pub line: usize,   // 1-based source line
+	pub column: usize, // 1-based column
+	pub message: String,
+}
+
+impl fmt::Display for AsmError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(
+			f,
+			"line {}, column {}: {}",
+			self.line, self.column, self.message
+		)
+	}
+}
+
+impl Error for AsmError {}
+
+// A value that may refer to labels, resolved once every label is known
+#[derive(Clone, Debug)]
+struct Expr {
+	terms: Vec<(i64, Term)>, // Sign and term
+}
+
+#[derive(Clone, Debug)]
+enum Term {
+	Number(i64, usize),   // Value and column
+	Label(String, usize), // Name and column
+}
+
+// A word of output waiting for its labels to be resolved
+struct Word {
+	line: usize,
+	value: Expr,
+}
+
+// Tracks where we are in the source for error messages
+#[derive(Clone, Copy)]
+struct Position {
+	line: usize,
+	column: usize,
+}
+
+impl Position {
+	fn error<S: Into<String>>(&self, message: S) -> AsmError {
+		AsmError {
+			line: self.line,
+			column: self.column,
+			message: message.into(),
+		}
+	}
+
+	fn offset(&self, chars: usize) -> Position {
+		Position {
+			line: self.line,
+			column: self.column + chars,
+		}
+	}
+}
+
+fn is_label(name: &str) -> bool {
+	let mut chars = name.chars();
+	return match chars.next() {
+		Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+			chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+		}
+		_ => false,
+	};
+}
+
+// Parses `a+b-3` style expressions. A leading sign is allowed.
+fn parse_expr(text: &str, at: Position) -> Result<Expr, AsmError> {
+	let mut terms = Vec::new();
+	let mut sign = 1;
+	let mut start = 0;
+	let bytes = text.as_bytes();
+	let mut i = 0;
+	if !bytes.is_empty() && (bytes[0] == b'+' || bytes[0] == b'-') {
+		sign = if bytes[0] == b'-' { -1 } else { 1 };
+		i = 1;
+		start = 1;
+	}
+	loop {
+		while i < bytes.len() && bytes[i] != b'+' && bytes[i] != b'-' {
+			i += 1;
+		}
+		let raw = &text[start..i];
+		let token = raw.trim();
+		let column = at.offset(start + (raw.len() - raw.trim_start().len()));
+		if token.is_empty() {
+			return Err(column.error("expected a number or label"));
+		}
+		// Numbers are parsed with their sign so that i64::MIN is in range
+		let number = match sign {
+			-1 => format!("-{}", token).parse::<i64>(),
+			_ => token.parse::<i64>(),
+		};
+		match number {
+			Ok(number) => terms.push((1, Term::Number(number, column.column))),
+			Err(_) if is_label(token) => {
+				terms.push((sign, Term::Label(token.to_string(), column.column)))
+			}
+			Err(_) => return Err(column.error(format!("invalid value '{}'", token))),
+		}
+		if i >= bytes.len() {
+			return Ok(Expr { terms });
+		}
+		sign = if bytes[i] == b'-' { -1 } else { 1 };
+		i += 1;
+		start = i;
+	}
+}
+
+// Parses an operand, returning its mode as an unresolved operand and the value
+fn parse_operand(text: &str, at: Position) -> Result<(Operand, Expr), AsmError> {
+	if let Some(rest) = text.strip_prefix('#') {
+		return Ok((Operand::Immediate(0), parse_expr(rest, at.offset(1))?));
+	}
+	if let Some(rest) = text.strip_prefix('[') {
+		let inner = match rest.strip_suffix(']') {
+			Some(inner) => inner,
+			None => return Err(at.offset(text.chars().count()).error("expected ']'")),
+		};
+		return Ok((Operand::Address(0), parse_expr(inner, at.offset(1))?));
+	}
+	if let Some(rest) = text.strip_prefix("rb") {
+		let value = match rest.trim_start() {
+			"" => Expr {
+				terms: vec![(1, Term::Number(0, at.column))],
+			},
+			offset if offset.starts_with('+') || offset.starts_with('-') => {
+				parse_expr(offset, at.offset(text.len() - offset.len()))?
+			}
+			_ => return Err(at.offset(2).error("expected '+' or '-' after 'rb'")),
+		};
+		return Ok((Operand::Relative(0), value));
+	}
+	return Err(at.error(format!(
+		"invalid operand '{}', expected #value, [address] or rb+offset",
+		text
+	)));
+}
+
+// Splits a comma separated list, keeping track of each item's column
+fn split_list(text: &str, at: Position) -> Vec<(&str, Position)> {
+	let mut items = Vec::new();
+	let mut offset = 0;
+	for raw in text.split(',') {
+		let leading = raw.len() - raw.trim_start().len();
+		items.push((raw.trim(), at.offset(offset + leading)));
+		offset += raw.len() + 1;
+	}
+	return items;
+}
+
+pub fn assemble(source: &str) -> Result<Vec<i64>, AsmError> {
+	let mut labels: HashMap<String, (usize, Position)> = HashMap::new();
+	let mut words: Vec<Word> = Vec::new();
+
+	for (index, full_line) in source.lines().enumerate() {
+		let code = match full_line.find(';') {
+			Some(comment) => &full_line[..comment],
+			None => full_line,
+		};
+		let mut rest = code;
+		let mut column = 1;
+
+		// Peel off labels and listing addresses
+		loop {
+			let trimmed = rest.trim_start();
+			column += rest.len() - trimmed.len();
+			rest = trimmed;
+			let colon = match rest.find(':') {
+				Some(colon) => colon,
+				None => break,
+			};
+			let name = &rest[..colon];
+			let at = Position {
+				line: index + 1,
+				column,
+			};
+			if name.parse::<usize>().is_err() {
+				if !is_label(name) {
+					break;
+				}
+				if let Some((_, previous)) = labels.get(name) {
+					return Err(at.error(format!(
+						"label '{}' is already defined on line {}",
+						name, previous.line
+					)));
+				}
+				labels.insert(name.to_string(), (words.len(), at));
+			}
+			rest = &rest[colon + 1..];
+			column += colon + 1;
+		}
+
+		let rest = rest.trim_end();
+		if rest.is_empty() {
+			continue;
+		}
+		let at = Position {
+			line: index + 1,
+			column,
+		};
+		let (mnemonic, operands) = match rest.find(char::is_whitespace) {
+			Some(space) => (&rest[..space], &rest[space..]),
+			None => (rest, ""),
+		};
+		let operands_at = at.offset(mnemonic.len());
+		let operands: Vec<(&str, Position)> = if operands.trim().is_empty() {
+			Vec::new()
+		} else {
+			split_list(operands, operands_at)
+		};
+
+		match mnemonic.to_ascii_uppercase().as_str() {
+			".DATA" | "DATA" => {
+				if operands.is_empty() {
+					return Err(at.error("expected at least one value"));
+				}
+				for (text, at) in operands {
+					words.push(Word {
+						line: at.line,
+						value: parse_expr(text, at)?,
+					});
+				}
+			}
+			".ZERO" => {
+				let count = match operands[..] {
+					[(text, _)] => text.parse::<usize>().ok(),
+					_ => None,
+				};
+				let count = count.ok_or_else(|| at.error("expected a count"))?;
+				for _ in 0..count {
+					words.push(Word {
+						line: at.line,
+						value: Expr {
+							terms: vec![(1, Term::Number(0, at.column))],
+						},
+					});
+				}
+			}
+			name => {
+				let opcode = Opcode::ALL
+					.iter()
+					.copied()
+					.find(|opcode| opcode.mnemonic() == name)
+					.ok_or_else(|| at.error(format!("unknown mnemonic '{}'", mnemonic)))?;
+				if operands.len() != opcode.param_count() {
+					return Err(at.error(format!(
+						"{} takes {} operand(s), found {}",
+						opcode.mnemonic(),
+						opcode.param_count(),
+						operands.len()
+					)));
+				}
+				let mut modes = Vec::new();
+				let mut values = Vec::new();
+				for (i, &(text, at)) in operands.iter().enumerate() {
+					let (mode, value) = parse_operand(text, at)?;
+					let last = i == operands.len() - 1;
+					if opcode.writes() && last && mode.mode() == MemMode::Immediate {
+						return Err(at.error("cannot write to an immediate operand"));
+					}
+					modes.push(mode);
+					values.push(value);
+				}
+				let instruction = Instruction {
+					opcode,
+					operands: modes,
+				};
+				words.push(Word {
+					line: at.line,
+					value: Expr {
+						terms: vec![(1, Term::Number(instruction.encode()[0], at.column))],
+					},
+				});
+				for value in values {
+					words.push(Word {
+						line: at.line,
+						value,
+					});
+				}
+			}
+		}
+	}
+
+	// Second pass: resolve labels
+	let mut program = Vec::with_capacity(words.len());
+	for word in words {
+		let mut value: i64 = 0;
+		for (sign, term) in word.value.terms {
+			let (term, column) = match term {
+				Term::Number(number, column) => (number, column),
+				Term::Label(name, column) => match labels.get(&name) {
+					Some((address, _)) => (*address as i64, column),
+					None => {
+						let at = Position {
+							line: word.line,
+							column,
+						};
+						return Err(at.error(format!("undefined label '{}'", name)));
+					}
+				},
+			};
+			value = match sign
+				.checked_mul(term)
+				.and_then(|term| value.checked_add(term))
+			{
+				Some(value) => value,
+				None => {
+					let at = Position {
+						line: word.line,
+						column,
+					};
+					return Err(at.error("value out of range"));
+				}
+			};
+		}
+		program.push(value);
+	}
+	return Ok(program);
+}
+
+// Formats a program as comma separated intcode
+pub fn format_program(program: &[i64]) -> String {
+	let words: Vec<String> = program.iter().map(|x| x.to_string()).collect();
+	return words.join(",");
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::disasm;
+	use crate::{Status, VM};
+
+	const ECHO: &str = "
+		; Echo inputs until a zero is read
+		loop:   IN [value]
+		        JIF [value], #done
+		        OUT [value]
+		        JIT #1, #loop
+		done:   HALT
+		value:  .data 0
+	";
+
+	#[test]
+	fn assembles_and_runs() {
+		let program = assemble(ECHO).unwrap();
+		assert_eq!(program, [3, 11, 1006, 11, 10, 4, 11, 1105, 1, 0, 99, 0]);
+		let mut vm = VM::from_memory(&program);
+		let result = vm.run_until_halt(&[4, 2, 0]).unwrap();
+		assert_eq!(result.status, Status::Halt);
+		assert_eq!(result.output, [4, 2]);
+	}
+
+	#[test]
+	fn expressions_and_directives() {
+		let source = "ARB #table+1\nOUT rb-1\nout rb\nHALT\ntable: DATA 7, -3, table\n.zero 2";
+		assert_eq!(
+			assemble(source).unwrap(),
+			[109, 8, 204, -1, 204, 0, 99, 7, -3, 7, 0, 0]
+		);
+		assert_eq!(format_program(&[1, -2, 3]), "1,-2,3");
+	}
+
+	#[test]
+	fn round_trips_with_disassembler() {
+		let program = crate::parse_program(include_str!("../../../input/2019/day9.txt")).unwrap();
+		let listing = disasm::format_listing(&disasm::disassemble(&program, 0));
+		assert_eq!(assemble(&listing).unwrap(), program);
+		// The most negative value only fits if its sign is parsed with it
+		let program = [99, i64::MIN, i64::MAX];
+		let listing = disasm::format_listing(&disasm::disassemble(&program, 0));
+		assert_eq!(assemble(&listing).unwrap(), program);
+	}
+
+	#[test]
+	fn errors() {
+		let error = assemble("HALT\n  FOO #1").unwrap_err();
+		assert_eq!(
+			error.to_string(),
+			"line 2, column 3: unknown mnemonic 'FOO'"
+		);
+
+		let error = assemble("ADD #1, #2, #3").unwrap_err();
+		assert_eq!((error.line, error.column), (1, 13));
+
+		let error = assemble("OUT [missing+1]").unwrap_err();
+		assert_eq!(
+			error.to_string(),
+			"line 1, column 6: undefined label 'missing'"
+		);
+
+		let error = assemble("JIT #1").unwrap_err();
+		assert_eq!(error.message, "JIT takes 2 operand(s), found 1");
+
+		let error = assemble("a: HALT\na: HALT").unwrap_err();
+		assert_eq!(
+			error.to_string(),
+			"line 2, column 1: label 'a' is already defined on line 1"
+		);
+
+		let error = assemble("OUT 5").unwrap_err();
+		assert_eq!(error.column, 5);
+
+		let error = assemble(".data 9223372036854775807+1").unwrap_err();
+		assert_eq!(error.to_string(), "line 1, column 27: value out of range");
+		let error = assemble(".data 9223372036854775808").unwrap_err();
+		assert_eq!(error.message, "invalid value '9223372036854775808'");
+		assert_eq!(assemble(".data -9223372036854775807-1"), Ok(vec![i64::MIN]));
+	}
+}
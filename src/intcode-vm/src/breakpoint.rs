@@ -0,0 +1,336 @@
+/*
+	--- Intcode VM: breakpoints and watchpoints ---
+
+	Breakpoints are checked before an instruction executes, so the VM stops
+	with the pc on the instruction that triggered it and nothing changed yet.
+	Running again executes that instruction without stopping on it a second
+	time, then breakpoints apply as normal. Conditions only trigger when they
+	change from false to true, so they don't stop every instruction after.
+*/
+
+use crate::disasm::Operand;
+use crate::{Memory, Opcode, VM};
+use std::collections::BTreeSet;
+
+// Why the VM stopped with Status::Breakpoint
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BreakReason {
+	Address(usize),   // The pc reached a breakpoint
+	Read(usize),      // The instruction is about to read a watched cell
+	Write(usize),     // The instruction is about to write a watched cell
+	Opcode(Opcode),   // The instruction has a watched opcode
+	Condition(usize), // The condition with this id became true
+}
+
+pub type Condition = Box<dyn Fn(&Memory) -> bool + Send>;
+
+struct Watch {
+	id: usize,
+	condition: Condition,
+	held: bool, // Result of the last check, to trigger on changes only
+}
+
+#[derive(Default)]
+pub struct Breakpoints {
+	addresses: BTreeSet<usize>,       // Instruction addresses to stop at
+	reads: BTreeSet<usize>,           // Cells to stop before reading
+	writes: BTreeSet<usize>,          // Cells to stop before writing
+	opcodes: Vec<Opcode>,             // Opcodes to stop before executing
+	conditions: Vec<Watch>,           // Conditions on memory
+	next_id: usize,                   // Id for the next condition
+	pub(crate) resume: Option<usize>, // Instruction to run without stopping again
+}
+
+impl Breakpoints {
+	pub fn new() -> Self {
+		Breakpoints::default()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		return self.addresses.is_empty()
+			&& self.reads.is_empty()
+			&& self.writes.is_empty()
+			&& self.opcodes.is_empty()
+			&& self.conditions.is_empty();
+	}
+
+	// Removes every breakpoint, watchpoint and condition
+	pub fn clear(&mut self) {
+		*self = Breakpoints {
+			next_id: self.next_id,
+			..Breakpoints::default()
+		};
+	}
+
+	pub fn add(&mut self, address: usize) {
+		self.addresses.insert(address);
+	}
+
+	pub fn remove(&mut self, address: usize) -> bool {
+		return self.addresses.remove(&address);
+	}
+
+	pub fn addresses(&self) -> impl Iterator<Item = usize> + '_ {
+		return self.addresses.iter().copied();
+	}
+
+	pub fn watch_read(&mut self, address: usize) {
+		self.reads.insert(address);
+	}
+
+	pub fn watch_write(&mut self, address: usize) {
+		self.writes.insert(address);
+	}
+
+	// Stops on both reads and writes of the cell
+	pub fn watch(&mut self, address: usize) {
+		self.watch_read(address);
+		self.watch_write(address);
+	}
+
+	// Removes read and write watchpoints on the cell
+	pub fn unwatch(&mut self, address: usize) -> bool {
+		let read = self.reads.remove(&address);
+		let write = self.writes.remove(&address);
+		return read || write;
+	}
+
+	pub fn read_watches(&self) -> impl Iterator<Item = usize> + '_ {
+		return self.reads.iter().copied();
+	}
+
+	pub fn write_watches(&self) -> impl Iterator<Item = usize> + '_ {
+		return self.writes.iter().copied();
+	}
+
+	pub fn break_on_opcode(&mut self, opcode: Opcode) {
+		if !self.opcodes.contains(&opcode) {
+			self.opcodes.push(opcode);
+		}
+	}
+
+	pub fn remove_opcode(&mut self, opcode: Opcode) -> bool {
+		let count = self.opcodes.len();
+		self.opcodes.retain(|&other| other != opcode);
+		return self.opcodes.len() != count;
+	}
+
+	pub fn opcodes(&self) -> &[Opcode] {
+		return &self.opcodes;
+	}
+
+	// Stops when `condition` becomes true, returning an id for BreakReason::Condition
+	pub fn add_condition<F>(&mut self, condition: F) -> usize
+	where
+		F: Fn(&Memory) -> bool + Send + 'static,
+	{
+		let id = self.next_id;
+		self.next_id += 1;
+		self.conditions.push(Watch {
+			id,
+			condition: Box::new(condition),
+			held: false,
+		});
+		return id;
+	}
+
+	pub fn remove_condition(&mut self, id: usize) -> bool {
+		let count = self.conditions.len();
+		self.conditions.retain(|watch| watch.id != id);
+		return self.conditions.len() != count;
+	}
+
+	// Checks conditions, updating their state. Only the first change is
+	// reported, the rest are picked up on the next check.
+	fn check_conditions(&mut self, memory: &Memory) -> Option<BreakReason> {
+		for watch in self.conditions.iter_mut() {
+			let holds = (watch.condition)(memory);
+			let changed = holds && !watch.held;
+			watch.held = holds;
+			if changed {
+				return Some(BreakReason::Condition(watch.id));
+			}
+		}
+		return None;
+	}
+}
+
+impl VM {
+	pub fn breakpoints(&self) -> &Breakpoints {
+		return &self.breakpoints;
+	}
+
+	pub fn breakpoints_mut(&mut self) -> &mut Breakpoints {
+		return &mut self.breakpoints;
+	}
+
+	// Decides whether to stop before executing the instruction at the pc
+	pub(crate) fn check_breakpoints(&mut self) -> Option<BreakReason> {
+		if let Some(reason) = self.breakpoints.check_conditions(&self.ram) {
+			return Some(reason);
+		}
+		let pc = self.pc.get();
+		if self.breakpoints.resume == Some(pc) {
+			return None; // Already stopped here, so carry on
+		}
+		let reason = self.instruction_breakpoint(pc);
+		if reason.is_some() {
+			self.breakpoints.resume = Some(pc);
+		}
+		return reason;
+	}
+
+	fn instruction_breakpoint(&self, pc: usize) -> Option<BreakReason> {
+		let breakpoints = &self.breakpoints;
+		if breakpoints.addresses.contains(&pc) {
+			return Some(BreakReason::Address(pc));
+		}
+		if breakpoints.opcodes.is_empty()
+			&& breakpoints.reads.is_empty()
+			&& breakpoints.writes.is_empty()
+		{
+			return None;
+		}
+		let instruction = self.decode_at(pc)?;
+		if breakpoints.opcodes.contains(&instruction.opcode) {
+			return Some(BreakReason::Opcode(instruction.opcode));
+		}
+		let count = instruction.operands.len();
+		for (i, operand) in instruction.operands.iter().enumerate() {
+			let address = match self.operand_address(operand) {
+				Some(address) => address,
+				None => continue,
+			};
+			if instruction.opcode.writes() && i == count - 1 {
+				if breakpoints.writes.contains(&address) {
+					return Some(BreakReason::Write(address));
+				}
+			} else if breakpoints.reads.contains(&address) {
+				return Some(BreakReason::Read(address));
+			}
+		}
+		return None;
+	}
+
+	// The cell an operand refers to, if it's a valid memory operand
+	pub(crate) fn operand_address(&self, operand: &Operand) -> Option<usize> {
+		let address = match *operand {
+			Operand::Immediate(_) => return None,
+			Operand::Address(address) => address,
+			Operand::Relative(offset) => self.relative_base.wrapping_add(offset),
+		};
+		if address < 0 {
+			return None;
+		}
+		return Some(address as usize);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{Status, VmError};
+
+	// Counts the cell at address 9 down from 3 to 0, then halts
+	const COUNTDOWN: [i64; 10] = [101, -1, 9, 9, 1005, 9, 0, 99, 0, 3];
+
+	#[test]
+	fn address_breakpoints() {
+		let mut vm = VM::from_memory(&COUNTDOWN);
+		vm.breakpoints_mut().add(4);
+		for remaining in [2, 1, 0] {
+			let status = vm.run_intcode();
+			assert_eq!(status, Ok(Status::Breakpoint(BreakReason::Address(4))));
+			assert_eq!(vm.pc(), 4);
+			assert_eq!(vm.memory().read(9), remaining);
+		}
+		assert!(vm.breakpoints_mut().remove(4));
+		assert_eq!(vm.run_intcode(), Ok(Status::Halt));
+		assert_eq!(vm.instruction_count(), 6);
+	}
+
+	#[test]
+	fn watchpoints() {
+		let mut vm = VM::from_memory(&COUNTDOWN);
+		vm.breakpoints_mut().watch_write(9);
+		assert_eq!(
+			vm.run_intcode(),
+			Ok(Status::Breakpoint(BreakReason::Write(9)))
+		);
+		assert_eq!((vm.pc(), vm.memory().read(9)), (0, 3));
+
+		// The ADD also reads the cell, but it's stepped past as a whole
+		vm.breakpoints_mut().watch_read(9);
+		assert_eq!(vm.step(), Ok(None));
+		assert_eq!(
+			vm.step(),
+			Ok(Some(Status::Breakpoint(BreakReason::Read(9))))
+		);
+		assert_eq!(vm.pc(), 4);
+
+		assert!(vm.breakpoints_mut().unwatch(9));
+		vm.breakpoints_mut().break_on_opcode(Opcode::HALT);
+		assert_eq!(
+			vm.run_intcode(),
+			Ok(Status::Breakpoint(BreakReason::Opcode(Opcode::HALT)))
+		);
+		assert_eq!(vm.run_intcode(), Ok(Status::Halt));
+	}
+
+	#[test]
+	fn stray_mode_digits() {
+		// The VM ignores the modes past OUT's only parameter, so this still stops
+		let mut vm = VM::from_memory(&[11104, 7, 99]);
+		vm.breakpoints_mut().break_on_opcode(Opcode::OUT);
+		assert_eq!(
+			vm.run_intcode(),
+			Ok(Status::Breakpoint(BreakReason::Opcode(Opcode::OUT)))
+		);
+
+		// A relative base that wraps round is left for the VM to report
+		let mut vm = VM::from_memory(&[109, i64::MAX, 204, 1, 99]);
+		vm.breakpoints_mut().watch_read(0);
+		assert_eq!(
+			vm.run_intcode(),
+			Err(VmError::NegativeAddress {
+				pc: 2,
+				intcode: 204,
+				address: i64::MIN
+			})
+		);
+	}
+
+	#[test]
+	fn conditions() {
+		let mut vm = VM::from_memory(&COUNTDOWN);
+		let id = vm
+			.breakpoints_mut()
+			.add_condition(|memory| memory.read(9) < 2);
+		assert_eq!(
+			vm.run_intcode(),
+			Ok(Status::Breakpoint(BreakReason::Condition(id)))
+		);
+		assert_eq!(vm.memory().read(9), 1);
+
+		// Still true, but it doesn't trigger again until it changes
+		assert_eq!(vm.run_intcode(), Ok(Status::Halt));
+		assert!(vm.breakpoints_mut().remove_condition(id));
+		assert!(vm.breakpoints().is_empty());
+	}
+
+	#[test]
+	fn resumes_after_waiting_for_input() {
+		let mut vm = VM::from_memory(&[3, 5, 4, 5, 99, 0]);
+		vm.breakpoints_mut().add(0);
+		assert_eq!(
+			vm.run_intcode(),
+			Ok(Status::Breakpoint(BreakReason::Address(0)))
+		);
+		assert_eq!(vm.run_intcode(), Ok(Status::WaitForInput));
+
+		// Retrying the IN doesn't stop on the breakpoint again
+		let result = vm.run_until_halt(&[8]).unwrap();
+		assert_eq!(result.output, [8]);
+		assert!(result.halted());
+	}
+}
@@ -0,0 +1,348 @@
+/*
+	--- Intcode VM: decode cache ---
+
+	Decoding an instruction means splitting its intcode into an opcode and
+	parameter modes, then fetching each parameter. Programs spend almost all
+	of their time in small loops, so the VM decodes an instruction the first
+	time it runs and keeps the result, with its parameters already fetched,
+	for next time. Programs can modify themselves, so any write to a cell an
+	instruction was decoded from throws that instruction away.
+
+	Only the contiguous part of memory, which holds the program, is cached,
+	so the cache never grows past the memory the program already uses. It
+	also survives reloading the program, e.g. to try another noun and verb,
+	keeping every instruction whose cells didn't change.
+*/
+
+use crate::{Memory, Opcode, Status, VmError, VM};
+
+// A parameter as it was fetched from memory
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Param {
+	Immediate(i64),
+	Address(i64),  // Not yet checked for being negative
+	Relative(i64), // Offset from the relative base
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Decoded {
+	intcode: i64, // For reporting errors
+	opcode: Opcode,
+	params: [Param; 3], // Only the first param_count() are used
+}
+
+impl Decoded {
+	// Decodes the instruction at `address`, or returns None if it's invalid,
+	// leaving the error for the interpreter to report
+	fn decode(memory: &Memory, address: usize) -> Option<Decoded> {
+		let intcode = memory.read(address);
+		let opcode = Opcode::from_intcode(intcode)?;
+		let mut params = [Param::Immediate(0); 3];
+		let mut modes = intcode / 100;
+		for (i, param) in params.iter_mut().enumerate().take(opcode.param_count()) {
+			let value = memory.read(address + 1 + i);
+			*param = match modes % 10 {
+				0 => Param::Address(value),
+				1 => Param::Immediate(value),
+				2 => Param::Relative(value),
+				_ => return None,
+			};
+			modes /= 10;
+		}
+		return Some(Decoded {
+			intcode,
+			opcode,
+			params,
+		});
+	}
+
+	fn size(&self) -> usize {
+		return 1 + self.opcode.param_count();
+	}
+
+	// Whether the cells at `address` still hold this instruction
+	fn matches(&self, memory: &Memory, address: usize) -> bool {
+		if memory.read(address) != self.intcode {
+			return false;
+		}
+		return self.params[..self.size() - 1]
+			.iter()
+			.enumerate()
+			.all(|(i, param)| match *param {
+				Param::Immediate(value) | Param::Address(value) | Param::Relative(value) => {
+					memory.read(address + 1 + i) == value
+				}
+			});
+	}
+}
+
+pub(crate) struct DecodeCache {
+	entries: Vec<Option<Decoded>>, // Indexed by address
+}
+
+impl DecodeCache {
+	pub(crate) fn new() -> Self {
+		DecodeCache {
+			entries: Vec::new(),
+		}
+	}
+
+	// Forgets every instruction that isn't in `memory` any more
+	fn revalidate(&mut self, memory: &Memory) {
+		self.entries.truncate(memory.dense().len());
+		for (address, entry) in self.entries.iter_mut().enumerate() {
+			if entry.is_some_and(|decoded| !decoded.matches(memory, address)) {
+				*entry = None;
+			}
+		}
+	}
+
+	// Forgets every instruction that includes `address`
+	pub(crate) fn invalidate(&mut self, address: usize) {
+		let end = (address + 1).min(self.entries.len());
+		for entry in &mut self.entries[address.saturating_sub(3).min(end)..end] {
+			*entry = None;
+		}
+	}
+
+	fn get(&mut self, memory: &Memory, address: usize) -> Option<Decoded> {
+		if let Some(&Some(decoded)) = self.entries.get(address) {
+			return Some(decoded);
+		}
+		let decoded = Decoded::decode(memory, address)?;
+		if address < memory.dense().len() {
+			if address >= self.entries.len() {
+				self.entries.resize(address + 1, None);
+			}
+			self.entries[address] = Some(decoded);
+		}
+		return Some(decoded);
+	}
+
+	pub(crate) fn len(&self) -> usize {
+		return self.entries.iter().filter(|entry| entry.is_some()).count();
+	}
+}
+
+impl VM {
+	// Turns the decode cache on or off. It's on by default; turning it off
+	// decodes every instruction as it executes, which is slower but handy for
+	// checking the cache gives the same results.
+	pub fn set_decode_cache(&mut self, enabled: bool) {
+		self.cache = if enabled {
+			Some(DecodeCache::new())
+		} else {
+			None
+		};
+	}
+
+	// Keeps only the cached instructions still in memory, after it's replaced
+	pub(crate) fn revalidate_cache(&mut self) {
+		if let Some(cache) = self.cache.as_mut() {
+			cache.revalidate(&self.ram);
+		}
+	}
+
+	// Number of instructions currently decoded
+	pub fn decoded_instructions(&self) -> usize {
+		return self.cache.as_ref().map_or(0, |cache| cache.len());
+	}
+
+	// Executes the instruction at instruction_pc from the cache, or returns
+	// None if it can't be decoded and the interpreter should report why
+	pub(crate) fn execute_decoded(&mut self) -> Option<Result<Option<Status>, VmError>> {
+		let address = self.instruction_pc;
+		let decoded = self.cache.as_mut()?.get(&self.ram, address)?;
+		self.pc.set(address + decoded.size());
+		return Some(self.run_decoded(&decoded));
+	}
+
+	fn run_decoded(&mut self, decoded: &Decoded) -> Result<Option<Status>, VmError> {
+		match decoded.opcode {
+			Opcode::ADD => {
+				let value = self.load(decoded, 0)?.wrapping_add(self.load(decoded, 1)?);
+				self.store(decoded, 2, value)?;
+			}
+			Opcode::MUL => {
+				let value = self.load(decoded, 0)?.wrapping_mul(self.load(decoded, 1)?);
+				self.store(decoded, 2, value)?;
+			}
+			Opcode::IN => {
+				// Check the target first so a fault doesn't lose the input
+				let address = self.store_address(decoded, 0)?;
+				let input = match self.read_input() {
+					Some(input) => input,
+					None => return Ok(Some(Status::WaitForInput)),
+				};
+				self.write_cell(decoded.intcode, address, input)?;
+			}
+			Opcode::OUT => {
+				let value = self.load(decoded, 0)?;
+				self.write_output(value);
+				return Ok(Some(Status::NewOutput));
+			}
+			Opcode::JIT | Opcode::JIF => {
+				let condition = self.load(decoded, 0)?;
+				let target = self.load(decoded, 1)?;
+				if (condition != 0) == (decoded.opcode == Opcode::JIT) {
+					self.pc.set(self.to_address(decoded.intcode, target)?);
+				}
+			}
+			Opcode::LT => {
+				let value = self.load(decoded, 0)? < self.load(decoded, 1)?;
+				self.store(decoded, 2, value as i64)?;
+			}
+			Opcode::EQ => {
+				let value = self.load(decoded, 0)? == self.load(decoded, 1)?;
+				self.store(decoded, 2, value as i64)?;
+			}
+			Opcode::ARB => {
+				self.relative_base = self.relative_base.wrapping_add(self.load(decoded, 0)?);
+			}
+			Opcode::HALT => return Ok(Some(Status::Halt)),
+		}
+		return Ok(None);
+	}
+
+	fn load(&self, decoded: &Decoded, index: usize) -> Result<i64, VmError> {
+		let address = match decoded.params[index] {
+			Param::Immediate(value) => return Ok(value),
+			Param::Address(address) => address,
+			Param::Relative(offset) => self.relative_base.wrapping_add(offset),
+		};
+		return Ok(self.ram.read(self.to_address(decoded.intcode, address)?));
+	}
+
+	fn store(&mut self, decoded: &Decoded, index: usize, value: i64) -> Result<(), VmError> {
+		let address = self.store_address(decoded, index)?;
+		return self.write_cell(decoded.intcode, address, value);
+	}
+
+	// The address a write parameter refers to
+	fn store_address(&self, decoded: &Decoded, index: usize) -> Result<usize, VmError> {
+		let address = match decoded.params[index] {
+			Param::Immediate(_) => {
+				let (pc, intcode) = self.fault(decoded.intcode);
+				return Err(VmError::ImmediateWrite { pc, intcode });
+			}
+			Param::Address(address) => address,
+			Param::Relative(offset) => self.relative_base.wrapping_add(offset),
+		};
+		return self.to_address(decoded.intcode, address);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn decodes_once() {
+		// Counts the cell at address 9 down from 3 to 0, then halts
+		let mut vm = VM::from_memory(&[101, -1, 9, 9, 1005, 9, 0, 99, 0, 3]);
+		assert_eq!(vm.run_intcode(), Ok(Status::Halt));
+		assert_eq!(vm.instruction_count(), 6);
+		assert_eq!(vm.decoded_instructions(), 3);
+
+		vm.set_decode_cache(false);
+		vm.reset(&[99]);
+		assert_eq!(vm.run_intcode(), Ok(Status::Halt));
+		assert_eq!(vm.decoded_instructions(), 0);
+	}
+
+	#[test]
+	fn survives_reloading() {
+		// Adds the cells at 9 and 10 into 11, like day 2
+		let program = [1, 9, 10, 11, 99, 0, 0, 0, 0, 3, 4, 0];
+		let mut vm = VM::from_memory(&program);
+		assert_eq!(vm.run_intcode(), Ok(Status::Halt));
+		assert_eq!(vm.decoded_instructions(), 2);
+
+		// Only the result changed, so both instructions are kept
+		vm.reset(&program);
+		assert_eq!(vm.decoded_instructions(), 2);
+		vm.write_memory(9, 7).unwrap();
+		assert_eq!(vm.decoded_instructions(), 2);
+		vm.write_memory(1, 10).unwrap();
+		assert_eq!(vm.decoded_instructions(), 1);
+		assert_eq!(vm.run_intcode(), Ok(Status::Halt));
+		assert_eq!(vm.memory().read(11), 8);
+
+		vm.reset(&[99]);
+		assert_eq!(vm.decoded_instructions(), 0);
+	}
+
+	#[test]
+	fn caches_only_contiguous_memory() {
+		// Jumps to a HALT far past the program, in a sparse page
+		let mut vm = VM::from_memory(&[1105, 1, 100_000]);
+		vm.write_memory(100_000, 99).unwrap();
+		assert_eq!(vm.run_intcode(), Ok(Status::Halt));
+		assert_eq!(vm.pc(), 100_000);
+		assert_eq!(vm.decoded_instructions(), 1);
+	}
+
+	#[test]
+	fn invalidates_modified_code() {
+		// Outputs 5, rewrites the first instruction to load 7 instead, then
+		// runs it again
+		#[rustfmt::skip]
+		let program = [
+			1101, 5, 0, 23, // ADD #5, #0, [23]
+			4, 23,          // OUT [23]
+			1005, 24, 20,   // JIT [24], #20
+			1101, 7, 0, 1,  // ADD #7, #0, [1]
+			1101, 1, 0, 24, // ADD #1, #0, [24]
+			1105, 1, 0,     // JIT #1, #0
+			99, 0, 0, 0, 0,
+		];
+		let mut vm = VM::from_memory(&program);
+		assert_eq!(vm.run_until_halt(&[]).unwrap().output, [5, 7]);
+
+		let mut uncached = VM::from_memory(&program);
+		uncached.set_decode_cache(false);
+		assert_eq!(uncached.run_until_halt(&[]).unwrap().output, [5, 7]);
+		assert_eq!(vm.memory().dense(), uncached.memory().dense());
+		assert_eq!(vm.instruction_count(), uncached.instruction_count());
+	}
+
+	#[test]
+	fn matches_the_interpreter() {
+		let program = crate::parse_program(include_str!("../../../input/2019/day9.txt")).unwrap();
+		for input in 1..=2 {
+			let mut cached = VM::from_memory(&program);
+			let mut uncached = VM::from_memory(&program);
+			uncached.set_decode_cache(false);
+			let expected = uncached.run_until_halt(&[input]).unwrap();
+			assert_eq!(cached.run_until_halt(&[input]).unwrap(), expected);
+			assert_eq!(cached.instruction_count(), uncached.instruction_count());
+			assert_eq!(cached.memory().regions(), uncached.memory().regions());
+		}
+
+		// Overflow wraps the same way
+		for program in [
+			&[1101, i64::MAX, 1, 0, 99][..],
+			&[109, i64::MAX, 209, 1, 99],
+		] {
+			let mut cached = VM::from_memory(program);
+			let mut uncached = VM::from_memory(program);
+			uncached.set_decode_cache(false);
+			assert_eq!(cached.run_intcode(), uncached.run_intcode());
+			assert_eq!(cached.memory().read(0), uncached.memory().read(0));
+			assert_eq!(cached.relative_base(), uncached.relative_base());
+		}
+
+		// Errors are still reported by the interpreter
+		let mut vm = VM::from_memory(&[1, 0, 0, 0, 1, -1, 0, 0]);
+		let error = vm.run_intcode().unwrap_err();
+		assert_eq!(
+			error,
+			VmError::NegativeAddress {
+				pc: 4,
+				intcode: 1,
+				address: -1
+			}
+		);
+		assert_eq!(vm.pc(), 4);
+	}
+}
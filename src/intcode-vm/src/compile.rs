@@ -0,0 +1,850 @@
+/*
+	--- Intcode VM: compiled execution ---
+
+	CompiledVM runs a program as basic blocks: straight-line runs of
+	instructions ending in a jump, I/O or HALT. The first time execution
+	reaches an address, the block starting there is compiled into a list of
+	closures, each specialised for its opcode and parameter modes, so running
+	it again doesn't decode anything or look up an opcode per instruction.
+
+	It behaves exactly like the interpreter and implements the same Engine
+	trait, so a solver can switch by changing `VM::from_memory` to
+	`CompiledVM::from_memory`, and a network can run on it with
+	`Network::<CompiledVM>::with_engine`. Anything the compiler can't handle runs on the
+	interpreter instead, one instruction at a time:
+
+		- instructions that would fault, so the interpreter reports the error
+		- code the program has overwritten since it was compiled
+		- everything, while the VM is traced, has breakpoints or keeps history
+*/
+
+use crate::{Engine, Memory, Opcode, RunResult, Status, VmError, VM};
+use std::collections::{HashMap, VecDeque};
+
+// Longest run of instructions compiled into one block
+const MAX_BLOCK_LENGTH: usize = 256;
+
+// One compiled instruction. Returns the address it wrote to, if any.
+type Op = Box<dyn Fn(&mut VM) -> Result<Option<usize>, VmError> + Send + Sync>;
+
+// Parameters, specialised by mode
+trait Source: Copy + Send + Sync + 'static {
+	fn read(self, vm: &VM, intcode: i64) -> Result<i64, VmError>;
+}
+
+trait Target: Copy + Send + Sync + 'static {
+	fn address(self, vm: &VM, intcode: i64) -> Result<usize, VmError>;
+}
+
+#[derive(Clone, Copy)]
+struct Immediate(i64);
+
+#[derive(Clone, Copy)]
+struct Absolute(usize); // Known not to be negative
+
+#[derive(Clone, Copy)]
+struct Relative(i64);
+
+impl Source for Immediate {
+	fn read(self, _: &VM, _: i64) -> Result<i64, VmError> {
+		return Ok(self.0);
+	}
+}
+
+impl Source for Absolute {
+	fn read(self, vm: &VM, _: i64) -> Result<i64, VmError> {
+		return Ok(vm.ram.read(self.0));
+	}
+}
+
+impl Source for Relative {
+	fn read(self, vm: &VM, intcode: i64) -> Result<i64, VmError> {
+		return Ok(vm.ram.read(self.address(vm, intcode)?));
+	}
+}
+
+impl Target for Absolute {
+	fn address(self, _: &VM, _: i64) -> Result<usize, VmError> {
+		return Ok(self.0);
+	}
+}
+
+impl Target for Relative {
+	fn address(self, vm: &VM, intcode: i64) -> Result<usize, VmError> {
+		return vm.to_address(intcode, vm.relative_base.wrapping_add(self.0));
+	}
+}
+
+// A parameter before specialisation
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Param {
+	Immediate(i64),
+	Absolute(usize),
+	Relative(i64),
+}
+
+impl Param {
+	// Negative addresses are left to the interpreter to report
+	fn decode(mode: i64, value: i64) -> Option<Param> {
+		match mode {
+			0 if value >= 0 => Some(Param::Absolute(value as usize)),
+			1 => Some(Param::Immediate(value)),
+			2 => Some(Param::Relative(value)),
+			_ => None,
+		}
+	}
+
+	fn read(self, vm: &VM, intcode: i64) -> Result<i64, VmError> {
+		match self {
+			Param::Immediate(value) => Immediate(value).read(vm, intcode),
+			Param::Absolute(address) => Absolute(address).read(vm, intcode),
+			Param::Relative(offset) => Relative(offset).read(vm, intcode),
+		}
+	}
+
+	fn address(self, vm: &VM, intcode: i64) -> Result<usize, VmError> {
+		match self {
+			Param::Immediate(_) => unreachable!("Immediate parameters aren't compiled as targets"),
+			Param::Absolute(address) => Absolute(address).address(vm, intcode),
+			Param::Relative(offset) => Relative(offset).address(vm, intcode),
+		}
+	}
+}
+
+// Instantiates `$make` with the parameter type matching each mode
+macro_rules! specialise_source {
+	($param:expr, |$source:ident| $make:expr) => {
+		match $param {
+			Param::Immediate(value) => {
+				let $source = Immediate(value);
+				$make
+			}
+			Param::Absolute(address) => {
+				let $source = Absolute(address);
+				$make
+			}
+			Param::Relative(offset) => {
+				let $source = Relative(offset);
+				$make
+			}
+		}
+	};
+}
+
+// The same for the parameter written to. Immediate targets can't be compiled,
+// so they give None.
+macro_rules! specialise_target {
+	($param:expr, |$target:ident| $make:expr) => {
+		match $param {
+			Param::Immediate(_) => None,
+			Param::Absolute(address) => {
+				let $target = Absolute(address);
+				Some($make)
+			}
+			Param::Relative(offset) => {
+				let $target = Relative(offset);
+				Some($make)
+			}
+		}
+	};
+}
+
+// ADD, MUL, LT and EQ: combines two values and stores the result
+fn binary<F>(f: F, params: [Param; 3], intcode: i64) -> Option<Op>
+where
+	F: Fn(i64, i64) -> i64 + Copy + Send + Sync + 'static,
+{
+	return specialise_source!(params[0], |a| {
+		specialise_source!(params[1], |b| {
+			specialise_target!(params[2], |target| binary_op(f, a, b, target, intcode))
+		})
+	});
+}
+
+fn binary_op<F, A, B, T>(f: F, a: A, b: B, target: T, intcode: i64) -> Op
+where
+	F: Fn(i64, i64) -> i64 + Copy + Send + Sync + 'static,
+	A: Source,
+	B: Source,
+	T: Target,
+{
+	return Box::new(move |vm: &mut VM| {
+		let value = f(a.read(vm, intcode)?, b.read(vm, intcode)?);
+		let address = target.address(vm, intcode)?;
+		vm.write_cell(intcode, address, value)?;
+		return Ok(Some(address));
+	});
+}
+
+fn adjust_relative_base(param: Param, intcode: i64) -> Op {
+	return specialise_source!(param, |offset| {
+		Box::new(move |vm: &mut VM| {
+			vm.relative_base = vm.relative_base.wrapping_add(offset.read(vm, intcode)?);
+			return Ok(None);
+		})
+	});
+}
+
+// How a block hands over to whatever runs next
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Exit {
+	Next, // Carry on at the end of the block
+	Jump {
+		condition: Param,
+		target: Param,
+		when: bool, // Jump if the condition is non-zero, or if it's zero
+	},
+	Input(Param),
+	Output(Param),
+	Halt,
+}
+
+struct Block {
+	ops: Vec<(usize, Op)>, // Address of each instruction and its code
+	exit: Exit,
+	exit_address: usize, // Address of the exit instruction, or the next one for Exit::Next
+	exit_intcode: i64,
+	end: usize, // First address after the block
+}
+
+// What running a block led to
+enum Outcome {
+	Continue,
+	Status(Status),
+	Overwrote(usize), // The block wrote to compiled code and stopped after it
+}
+
+impl Block {
+	fn run(&self, vm: &mut VM, code: &[bool], remaining: &mut u64) -> Result<Outcome, VmError> {
+		for (i, (address, op)) in self.ops.iter().enumerate() {
+			if *remaining == 0 {
+				vm.pc.set(*address);
+				return Ok(Outcome::Continue);
+			}
+			vm.instruction_pc = *address;
+			let written = op(vm).inspect_err(|_| vm.pc.set(*address))?;
+			vm.instructions += 1;
+			*remaining -= 1;
+			if let Some(written) = written.filter(|&written| code.get(written) == Some(&true)) {
+				let next = self
+					.ops
+					.get(i + 1)
+					.map_or(self.exit_address, |(next, _)| *next);
+				vm.pc.set(next);
+				return Ok(Outcome::Overwrote(written));
+			}
+		}
+		vm.pc.set(self.exit_address);
+		if self.exit == Exit::Next || *remaining == 0 {
+			return Ok(Outcome::Continue);
+		}
+		vm.instruction_pc = self.exit_address;
+		let outcome = self.run_exit(vm, code)?;
+		if let Outcome::Status(Status::WaitForInput) | Outcome::Status(Status::Halt) = outcome {
+			return Ok(outcome);
+		}
+		vm.instructions += 1;
+		*remaining -= 1;
+		return Ok(outcome);
+	}
+
+	// Runs the exit instruction, leaving the pc on it if it fails or waits
+	fn run_exit(&self, vm: &mut VM, code: &[bool]) -> Result<Outcome, VmError> {
+		let intcode = self.exit_intcode;
+		match self.exit {
+			Exit::Next => unreachable!("Handled by run()"),
+			Exit::Halt => return Ok(Outcome::Status(Status::Halt)),
+			Exit::Jump {
+				condition,
+				target,
+				when,
+			} => {
+				let condition = condition.read(vm, intcode)?;
+				let target = target.read(vm, intcode)?;
+				if (condition != 0) == when {
+					vm.pc.set(vm.to_address(intcode, target)?);
+				} else {
+					vm.pc.set(self.end);
+				}
+				return Ok(Outcome::Continue);
+			}
+			Exit::Input(target) => {
+				let input = match vm.read_input() {
+					Some(input) => input,
+					None => return Ok(Outcome::Status(Status::WaitForInput)),
+				};
+				let address = target.address(vm, intcode)?;
+				vm.write_cell(intcode, address, input)?;
+				vm.pc.set(self.end);
+				if code.get(address) == Some(&true) {
+					return Ok(Outcome::Overwrote(address));
+				}
+				return Ok(Outcome::Continue);
+			}
+			Exit::Output(source) => {
+				let value = source.read(vm, intcode)?;
+				vm.write_output(value);
+				vm.pc.set(self.end);
+				return Ok(Outcome::Status(Status::NewOutput));
+			}
+		}
+	}
+}
+
+pub struct CompiledVM {
+	vm: VM,
+	blocks: Vec<Block>,
+	entries: HashMap<usize, Option<usize>>, // Block starting at each address, None if it can't
+	code: Vec<bool>,                        // Cells that are part of a compiled block
+	overwritten: Vec<bool>,                 // Code cells the program changed, left to the interpreter
+}
+
+impl CompiledVM {
+	pub fn new(vm: VM) -> Self {
+		CompiledVM {
+			vm,
+			blocks: Vec::new(),
+			entries: HashMap::new(),
+			code: Vec::new(),
+			overwritten: Vec::new(),
+		}
+	}
+
+	pub fn from_memory(memory: &[i64]) -> Self {
+		return CompiledVM::new(VM::from_memory(memory));
+	}
+
+	pub fn vm(&self) -> &VM {
+		return &self.vm;
+	}
+
+	// Gives access to the underlying machine, e.g. to attach devices or write
+	// memory. The compiled code is thrown away in case memory changes.
+	pub fn vm_mut(&mut self) -> &mut VM {
+		self.discard();
+		self.overwritten.clear();
+		return &mut self.vm;
+	}
+
+	pub fn into_vm(self) -> VM {
+		return self.vm;
+	}
+
+	// Number of blocks compiled so far
+	pub fn block_count(&self) -> usize {
+		return self.blocks.len();
+	}
+
+	pub fn reset(&mut self, memory: &[i64]) {
+		self.vm_mut().reset(memory);
+	}
+
+	pub fn queue_input(&mut self, value: i64) {
+		self.vm.queue_input(value);
+	}
+
+	pub fn input(&self) -> &VecDeque<i64> {
+		return self.vm.input();
+	}
+
+	pub fn output(&self) -> &VecDeque<i64> {
+		return self.vm.output();
+	}
+
+	pub fn drain_output(&mut self) -> Vec<i64> {
+		return self.vm.drain_output();
+	}
+
+	pub fn memory(&self) -> &Memory {
+		return self.vm.memory();
+	}
+
+	pub fn pc(&self) -> usize {
+		return self.vm.pc();
+	}
+
+	pub fn relative_base(&self) -> i64 {
+		return self.vm.relative_base();
+	}
+
+	pub fn instruction_count(&self) -> u64 {
+		return self.vm.instruction_count();
+	}
+
+	// Executes a single instruction on the interpreter
+	pub fn step(&mut self) -> Result<Option<Status>, VmError> {
+		return self.interpret(&mut 1);
+	}
+
+	// Runs at most `budget` instructions
+	pub fn run_for(&mut self, budget: u64) -> Result<Status, VmError> {
+		let mut remaining = budget;
+		while remaining > 0 {
+			if let Some(status) = self.advance(&mut remaining)? {
+				return Ok(status);
+			}
+		}
+		return Ok(Status::BudgetExhausted);
+	}
+
+	pub fn run_intcode(&mut self) -> Result<Status, VmError> {
+		let mut unlimited = u64::MAX;
+		loop {
+			if let Some(status) = self.advance(&mut unlimited)? {
+				return Ok(status);
+			}
+		}
+	}
+
+	// Queues `input` and runs until the program halts or needs more input
+	pub fn run_until_halt(&mut self, input: &[i64]) -> Result<RunResult, VmError> {
+		self.vm.input.extend(input);
+		return self.run_until(None);
+	}
+
+	// Runs until `count` values have been output, or the program halts or needs input
+	pub fn run_until_outputs(&mut self, count: usize) -> Result<RunResult, VmError> {
+		return self.run_until(Some(count));
+	}
+
+	// Runs until the program halts or needs input, collecting everything it outputs
+	pub fn run_until_input(&mut self) -> Result<RunResult, VmError> {
+		return self.run_until(None);
+	}
+
+	fn run_until(&mut self, max_outputs: Option<usize>) -> Result<RunResult, VmError> {
+		let mut output = self.vm.drain_output();
+		let mut count = output.len();
+		loop {
+			if max_outputs.is_some_and(|max| count >= max) {
+				return Ok(RunResult {
+					status: Status::NewOutput,
+					output,
+				});
+			}
+			let status = self.run_intcode()?;
+			output.extend(self.vm.output.drain(..));
+			if status != Status::NewOutput {
+				return Ok(RunResult { status, output });
+			}
+			count += 1;
+		}
+	}
+
+	// Runs one block, or one instruction on the interpreter
+	fn advance(&mut self, remaining: &mut u64) -> Result<Option<Status>, VmError> {
+		let vm = &self.vm;
+		if vm.tracer.is_some() || vm.history.is_some() || !vm.breakpoints.is_empty() {
+			return self.interpret(remaining);
+		}
+		let block = match self.block_at(self.vm.pc()) {
+			Some(block) => block,
+			None => return self.interpret(remaining),
+		};
+		match self.blocks[block].run(&mut self.vm, &self.code, remaining)? {
+			Outcome::Continue => return Ok(None),
+			Outcome::Status(status) => return Ok(Some(status)),
+			Outcome::Overwrote(address) => {
+				self.overwrote(address);
+				return Ok(None);
+			}
+		}
+	}
+
+	// Executes one instruction on the interpreter, watching for it changing
+	// compiled code
+	fn interpret(&mut self, remaining: &mut u64) -> Result<Option<Status>, VmError> {
+		let written = self.vm.decode_at(self.vm.pc()).and_then(|instruction| {
+			let target = instruction
+				.operands
+				.last()
+				.filter(|_| instruction.opcode.writes())?;
+			return self.vm.operand_address(target);
+		});
+		let before = self.vm.instruction_count();
+		let status = self.vm.step()?;
+		if self.vm.instruction_count() > before {
+			*remaining -= 1;
+			if let Some(address) = written.filter(|&address| self.code.get(address) == Some(&true))
+			{
+				self.overwrote(address);
+			}
+		}
+		return Ok(status);
+	}
+
+	fn overwrote(&mut self, address: usize) {
+		if address >= self.overwritten.len() {
+			self.overwritten.resize(address + 1, false);
+		}
+		self.overwritten[address] = true;
+		self.discard();
+	}
+
+	fn discard(&mut self) {
+		self.blocks.clear();
+		self.entries.clear();
+		self.code.clear();
+	}
+
+	// The block starting at `address`, compiling it if need be
+	fn block_at(&mut self, address: usize) -> Option<usize> {
+		if let Some(&entry) = self.entries.get(&address) {
+			return entry;
+		}
+		let entry = self.compile(address).map(|block| {
+			for cell in address..block.end {
+				if cell >= self.code.len() {
+					self.code.resize(cell + 1, false);
+				}
+				self.code[cell] = true;
+			}
+			self.blocks.push(block);
+			return self.blocks.len() - 1;
+		});
+		self.entries.insert(address, entry);
+		return entry;
+	}
+
+	fn compile(&self, start: usize) -> Option<Block> {
+		let mut ops = Vec::new();
+		let mut address = start;
+		while let Some((intcode, opcode, params)) = self.decode(address) {
+			let exit = match opcode {
+				Opcode::ADD | Opcode::MUL | Opcode::LT | Opcode::EQ | Opcode::ARB => None,
+				Opcode::JIT | Opcode::JIF => Some(Exit::Jump {
+					condition: params[0],
+					target: params[1],
+					when: opcode == Opcode::JIT,
+				}),
+				Opcode::IN => Some(Exit::Input(params[0])),
+				Opcode::OUT => Some(Exit::Output(params[0])),
+				Opcode::HALT => Some(Exit::Halt),
+			};
+			let size = 1 + opcode.param_count();
+			if let Some(exit) = exit {
+				return Some(Block {
+					ops,
+					exit,
+					exit_address: address,
+					exit_intcode: intcode,
+					end: address + size,
+				});
+			}
+			let op = match opcode {
+				Opcode::ADD => binary(i64::wrapping_add, params, intcode),
+				Opcode::MUL => binary(i64::wrapping_mul, params, intcode),
+				Opcode::LT => binary(|a, b| (a < b) as i64, params, intcode),
+				Opcode::EQ => binary(|a, b| (a == b) as i64, params, intcode),
+				_ => Some(adjust_relative_base(params[0], intcode)),
+			};
+			match op {
+				Some(op) => ops.push((address, op)),
+				None => break,
+			}
+			address += size;
+			if ops.len() == MAX_BLOCK_LENGTH {
+				break;
+			}
+		}
+		if ops.is_empty() {
+			return None;
+		}
+		return Some(Block {
+			ops,
+			exit: Exit::Next,
+			exit_address: address,
+			exit_intcode: 0,
+			end: address,
+		});
+	}
+
+	// Decodes an instruction that can be compiled: one that hasn't been
+	// overwritten and can't fault because of its opcode or parameters, so that
+	// the interpreter reports those errors
+	fn decode(&self, address: usize) -> Option<(i64, Opcode, [Param; 3])> {
+		let intcode = self.vm.ram.read(address);
+		let opcode = Opcode::from_intcode(intcode)?;
+		let count = opcode.param_count();
+		if (address..=address + count).any(|cell| self.overwritten.get(cell) == Some(&true)) {
+			return None;
+		}
+		let mut params = [Param::Immediate(0); 3];
+		let mut modes = intcode / 100;
+		for (i, param) in params.iter_mut().enumerate().take(count) {
+			*param = Param::decode(modes % 10, self.vm.ram.read(address + 1 + i))?;
+			modes /= 10;
+		}
+		if opcode.writes() && matches!(params[count - 1], Param::Immediate(_)) {
+			return None;
+		}
+		return Some((intcode, opcode, params));
+	}
+}
+
+impl Engine for CompiledVM {
+	fn from_memory(memory: &[i64]) -> Self {
+		return CompiledVM::from_memory(memory);
+	}
+
+	fn reset(&mut self, memory: &[i64]) {
+		CompiledVM::reset(self, memory);
+	}
+
+	fn queue_input(&mut self, value: i64) {
+		CompiledVM::queue_input(self, value);
+	}
+
+	fn input(&self) -> &VecDeque<i64> {
+		return CompiledVM::input(self);
+	}
+
+	fn output(&self) -> &VecDeque<i64> {
+		return CompiledVM::output(self);
+	}
+
+	fn drain_output(&mut self) -> Vec<i64> {
+		return CompiledVM::drain_output(self);
+	}
+
+	fn memory(&self) -> &Memory {
+		return CompiledVM::memory(self);
+	}
+
+	fn pc(&self) -> usize {
+		return CompiledVM::pc(self);
+	}
+
+	fn relative_base(&self) -> i64 {
+		return CompiledVM::relative_base(self);
+	}
+
+	fn instruction_count(&self) -> u64 {
+		return CompiledVM::instruction_count(self);
+	}
+
+	fn step(&mut self) -> Result<Option<Status>, VmError> {
+		return CompiledVM::step(self);
+	}
+
+	fn run_for(&mut self, budget: u64) -> Result<Status, VmError> {
+		return CompiledVM::run_for(self, budget);
+	}
+
+	fn run_intcode(&mut self) -> Result<Status, VmError> {
+		return CompiledVM::run_intcode(self);
+	}
+
+	fn run_until_halt(&mut self, input: &[i64]) -> Result<RunResult, VmError> {
+		return CompiledVM::run_until_halt(self, input);
+	}
+
+	fn run_until_outputs(&mut self, count: usize) -> Result<RunResult, VmError> {
+		return CompiledVM::run_until_outputs(self, count);
+	}
+
+	fn run_until_input(&mut self) -> Result<RunResult, VmError> {
+		return CompiledVM::run_until_input(self);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Runs the interpreter and the compiled VM side by side in slices of an
+	// odd size, so blocks get cut short, feeding both the next of `inputs`
+	// whenever the program asks. They have to agree at every stop.
+	fn compare(name: &str, source: &str, inputs: &[i64]) {
+		let program = crate::parse_program(source).unwrap();
+		let mut interpreted = VM::from_memory(&program);
+		let mut compiled = CompiledVM::from_memory(&program);
+		let mut inputs = inputs.iter().cycle();
+		for slice in 0..200 {
+			let expected = interpreted.run_for(5003);
+			assert_eq!(compiled.run_for(5003), expected, "{} slice {}", name, slice);
+			assert_eq!(compiled.pc(), interpreted.pc(), "{}", name);
+			assert_eq!(
+				compiled.relative_base(),
+				interpreted.relative_base(),
+				"{}",
+				name
+			);
+			assert_eq!(
+				compiled.instruction_count(),
+				interpreted.instruction_count(),
+				"{}",
+				name
+			);
+			assert_eq!(
+				compiled.drain_output(),
+				interpreted.drain_output(),
+				"{}",
+				name
+			);
+			match expected {
+				Ok(Status::Halt) | Err(_) => break,
+				Ok(Status::WaitForInput) => {
+					let input = *inputs.next().expect("No input for the program");
+					interpreted.queue_input(input);
+					compiled.queue_input(input);
+				}
+				_ => (),
+			}
+		}
+		assert_eq!(
+			compiled.memory().regions(),
+			interpreted.memory().regions(),
+			"{}",
+			name
+		);
+	}
+
+	#[test]
+	fn matches_the_interpreter() {
+		let ascii = |text: &str| text.bytes().map(i64::from).collect::<Vec<i64>>();
+		compare("day2", include_str!("../../../input/2019/day2.txt"), &[]);
+		compare("day5", include_str!("../../../input/2019/day5.txt"), &[5]);
+		compare(
+			"day7",
+			include_str!("../../../input/2019/day7.txt"),
+			&[9, 0, 5, 7],
+		);
+		compare("day9", include_str!("../../../input/2019/day9.txt"), &[2]);
+		compare(
+			"day11",
+			include_str!("../../../input/2019/day11.txt"),
+			&[1, 0, 0, 1, 1],
+		);
+		compare(
+			"day13",
+			include_str!("../../../input/2019/day13.txt"),
+			&[0, -1, 1],
+		);
+		compare(
+			"day15",
+			include_str!("../../../input/2019/day15.txt"),
+			&[1, 4, 2, 3, 3],
+		);
+		compare("day17", include_str!("../../../input/2019/day17.txt"), &[]);
+		compare(
+			"day19",
+			include_str!("../../../input/2019/day19.txt"),
+			&[3, 4, 20, 17],
+		);
+		compare(
+			"day21",
+			include_str!("../../../input/2019/day21.txt"),
+			&ascii("NOT A J\nWALK\n"),
+		);
+		compare(
+			"day23",
+			include_str!("../../../input/2019/day23.txt"),
+			&[0, -1, -1],
+		);
+		compare(
+			"day25",
+			include_str!("../../../input/2019/day25.txt"),
+			&ascii("north\nsouth\n"),
+		);
+	}
+
+	#[test]
+	fn solves_puzzles() {
+		let program = crate::parse_program(include_str!("../../../input/2019/day9.txt")).unwrap();
+		let mut compiled = CompiledVM::from_memory(&program);
+		let mut interpreted = VM::from_memory(&program);
+		let expected = interpreted.run_until_halt(&[2]).unwrap();
+		assert_eq!(compiled.run_until_halt(&[2]).unwrap(), expected);
+		assert!(compiled.block_count() > 0);
+	}
+
+	#[test]
+	fn falls_back_for_modified_code() {
+		// Outputs 5, rewrites the first instruction to load 7 instead, then
+		// runs it again
+		#[rustfmt::skip]
+		let program = [
+			1101, 5, 0, 23, // ADD #5, #0, [23]
+			4, 23,          // OUT [23]
+			1005, 24, 20,   // JIT [24], #20
+			1101, 7, 0, 1,  // ADD #7, #0, [1]
+			1101, 1, 0, 24, // ADD #1, #0, [24]
+			1105, 1, 0,     // JIT #1, #0
+			99, 0, 0, 0, 0,
+		];
+		let mut vm = CompiledVM::from_memory(&program);
+		assert_eq!(vm.run_until_halt(&[]).unwrap().output, [5, 7]);
+		assert_eq!(vm.instruction_count(), 9);
+		assert_eq!(vm.overwritten.iter().position(|&cell| cell), Some(1));
+	}
+
+	#[test]
+	fn reports_errors_like_the_interpreter() {
+		let programs: [&[i64]; 5] = [
+			&[1, 0, 0, 0, 1, -1, 0, 0], // Negative address
+			&[109, -5, 2201, 0, 0, 0],  // Negative relative address
+			&[11101, 1, 1, 5, 99],      // Writes to an immediate
+			&[1105, 1, -1],             // Jumps to a negative address
+			&[3, 2, 0],                 // Reads its next instruction, which then faults
+		];
+		for program in &programs {
+			let mut interpreted = VM::from_memory(program);
+			interpreted.queue_input(1101);
+			let mut compiled = CompiledVM::from_memory(program);
+			compiled.queue_input(1101);
+			assert_eq!(compiled.run_intcode(), interpreted.run_intcode());
+			assert_eq!(compiled.pc(), interpreted.pc());
+			assert_eq!(
+				compiled.instruction_count(),
+				interpreted.instruction_count()
+			);
+		}
+	}
+
+	#[test]
+	fn wraps_like_the_interpreter() {
+		let programs: [&[i64]; 4] = [
+			&[1101, i64::MAX, 1, 0, 99],                  // ADD past the maximum
+			&[1102, i64::MIN, -1, 0, 99],                 // MUL past the maximum
+			&[109, i64::MAX, 209, 1, 99],                 // Relative base past the maximum
+			&[109, i64::MAX, 109, 1, 21101, 0, 0, 0, 99], // Relative address past it
+		];
+		for program in &programs {
+			let mut interpreted = VM::from_memory(program);
+			interpreted.set_decode_cache(false);
+			let mut compiled = CompiledVM::from_memory(program);
+			assert_eq!(compiled.run_intcode(), interpreted.run_intcode());
+			assert_eq!(compiled.pc(), interpreted.pc());
+			assert_eq!(compiled.relative_base(), interpreted.relative_base());
+			assert_eq!(compiled.memory().regions(), interpreted.memory().regions());
+		}
+	}
+
+	// Day 9 part 2, written once for any engine
+	fn sensor_boost<E: Engine>(program: &[i64]) -> Option<i64> {
+		return E::from_memory(program)
+			.run_until_halt(&[2])
+			.unwrap()
+			.last_output();
+	}
+
+	#[test]
+	fn shares_the_engine_api() {
+		let program = crate::parse_program(include_str!("../../../input/2019/day9.txt")).unwrap();
+		assert_eq!(
+			sensor_boost::<CompiledVM>(&program),
+			sensor_boost::<VM>(&program)
+		);
+	}
+
+	#[test]
+	fn interprets_while_debugging() {
+		let mut vm = CompiledVM::from_memory(&[3, 9, 102, 2, 9, 9, 4, 9, 99, 0]);
+		vm.vm_mut().enable_history(10);
+		assert_eq!(vm.run_until_halt(&[21]).unwrap().output, [42]);
+		assert_eq!(vm.block_count(), 0);
+		assert!(vm.vm_mut().step_back());
+		assert_eq!(vm.pc(), 6);
+	}
+}
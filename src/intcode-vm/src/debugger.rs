@@ -0,0 +1,851 @@
+/*
+	--- Intcode VM: debugger core ---
+
+	A debugging session that doesn't depend on any front end. A GUI can call
+	the methods directly and read the state back every frame, while text
+	front ends feed it commands such as `break 12`, `x/16 100` or `continue`,
+	listed in HELP.
+*/
+
+use crate::disasm::{self, Line};
+use crate::{ascii, BreakReason, Opcode, Status, VmError, VM};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+// Instructions kept for stepping backwards
+pub const DEFAULT_HISTORY: usize = 100_000;
+// Most cells `x` examines, or instructions `disas` lists, in one command
+pub const MAX_LISTING: usize = 10_000;
+// Most instructions `next` runs waiting for a call to return
+pub const STEP_OVER_BUDGET: u64 = 1_000_000;
+
+pub const HELP: &str = "\
+step [N]            Execute N instructions (s)
+next                Step over calls, stopping at the next instruction (n)
+continue [N]        Run until something stops the program, or N instructions (c)
+back [N]            Undo the last N instructions
+restart             Reload the program, keeping breakpoints
+break ADDR|OPCODE   Stop at an address, or before an opcode such as OUT (b)
+delete ADDR|OPCODE  Remove a breakpoint (d)
+watch ADDR          Stop before ADDR is written
+rwatch ADDR         Stop before ADDR is read
+unwatch ADDR        Remove watchpoints on ADDR
+breakpoints         List breakpoints and watchpoints
+x[/N] ADDR          Examine N memory cells from ADDR
+set mem ADDR VALUE  Change a memory cell
+set pc ADDR         Move the program counter
+set rb VALUE        Change the relative base
+info                Show registers, status and the input queue (i)
+disas [ADDR] [N]    Disassemble N instructions, around the pc by default
+input VALUE,...     Queue input values, or a line of text in quotes
+output              Show everything the program has output
+help                List commands
+quit                Leave the debugger (q)";
+
+// Where a breakpoint goes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Location {
+	Address(usize),
+	Opcode(Opcode),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+	Step(u64),
+	Next,
+	Continue(Option<u64>), // Optional instruction budget
+	Back(u64),
+	Restart,
+	Break(Location),
+	Delete(Location),
+	Watch(usize),
+	ReadWatch(usize),
+	Unwatch(usize),
+	Breakpoints,
+	Examine {
+		address: usize,
+		count: usize,
+	},
+	SetMemory {
+		address: usize,
+		value: i64,
+	},
+	SetPc(usize),
+	SetRelativeBase(i64),
+	Info,
+	Disassemble {
+		address: Option<usize>,
+		count: Option<usize>,
+	},
+	Input(Vec<i64>),
+	Output,
+	Help,
+	Quit, // Handled by the front end
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CommandError(pub String);
+
+impl fmt::Display for CommandError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.0)
+	}
+}
+
+impl Error for CommandError {}
+
+fn error<S: Into<String>>(message: S) -> CommandError {
+	CommandError(message.into())
+}
+
+fn number<T: FromStr>(word: Option<&str>, what: &str) -> Result<T, CommandError> {
+	let word = word.ok_or_else(|| error(format!("expected {}", what)))?;
+	return word
+		.parse::<T>()
+		.map_err(|_| error(format!("invalid {} '{}'", what, word)));
+}
+
+fn location(word: Option<&str>) -> Result<Location, CommandError> {
+	if let Some(opcode) = word.and_then(|word| {
+		let word = word.to_ascii_uppercase();
+		Opcode::ALL
+			.iter()
+			.copied()
+			.find(|opcode| opcode.mnemonic() == word)
+	}) {
+		return Ok(Location::Opcode(opcode));
+	}
+	return number(word, "address").map(Location::Address);
+}
+
+// Parses `1,2,3`, `1 2 3` or a quoted line of ASCII text
+fn input_values(text: &str) -> Result<Vec<i64>, CommandError> {
+	let text = text.trim();
+	if let Some(quoted) = text.strip_prefix('"') {
+		let line = quoted
+			.strip_suffix('"')
+			.ok_or_else(|| error("unterminated string"))?;
+		return Ok(ascii::encode_line(line));
+	}
+	let values: Result<Vec<i64>, CommandError> = text
+		.split(|c: char| c == ',' || c.is_whitespace())
+		.filter(|word| !word.is_empty())
+		.map(|word| number(Some(word), "input value"))
+		.collect();
+	let values = values?;
+	if values.is_empty() {
+		return Err(error("expected input values"));
+	}
+	return Ok(values);
+}
+
+impl Command {
+	pub fn parse(line: &str) -> Result<Command, CommandError> {
+		let line = line.trim();
+		let (name, rest) = match line.find(char::is_whitespace) {
+			Some(space) => (&line[..space], line[space..].trim()),
+			None => (line, ""),
+		};
+		let mut args = rest.split_whitespace();
+		let command = match name {
+			"s" | "step" => Command::Step(match args.next() {
+				Some(count) => number(Some(count), "count")?,
+				None => 1,
+			}),
+			"n" | "next" => Command::Next,
+			"c" | "continue" => Command::Continue(match args.next() {
+				Some(budget) => Some(number(Some(budget), "budget")?),
+				None => None,
+			}),
+			"back" => Command::Back(match args.next() {
+				Some(count) => number(Some(count), "count")?,
+				None => 1,
+			}),
+			"restart" => Command::Restart,
+			"b" | "break" => Command::Break(location(args.next())?),
+			"d" | "delete" => Command::Delete(location(args.next())?),
+			"watch" => Command::Watch(number(args.next(), "address")?),
+			"rwatch" => Command::ReadWatch(number(args.next(), "address")?),
+			"unwatch" => Command::Unwatch(number(args.next(), "address")?),
+			"breakpoints" => Command::Breakpoints,
+			"set" => match args.next() {
+				Some("mem") => Command::SetMemory {
+					address: number(args.next(), "address")?,
+					value: number(args.next(), "value")?,
+				},
+				Some("pc") => Command::SetPc(number(args.next(), "address")?),
+				Some("rb") => Command::SetRelativeBase(number(args.next(), "value")?),
+				_ => return Err(error("expected 'set mem', 'set pc' or 'set rb'")),
+			},
+			"i" | "info" => Command::Info,
+			"disas" => Command::Disassemble {
+				address: args
+					.next()
+					.map(|word| number(Some(word), "address"))
+					.transpose()?,
+				count: args
+					.next()
+					.map(|word| number(Some(word), "count"))
+					.transpose()?,
+			},
+			"input" => return Ok(Command::Input(input_values(rest)?)),
+			"output" => Command::Output,
+			"help" => Command::Help,
+			"q" | "quit" => Command::Quit,
+			_ if name == "x" || name.starts_with("x/") => Command::Examine {
+				count: match name.strip_prefix("x/") {
+					Some(count) => number(Some(count), "count")?,
+					None => 8,
+				},
+				address: number(args.next(), "address")?,
+			},
+			"" => return Err(error("expected a command")),
+			_ => return Err(error(format!("unknown command '{}', try 'help'", name))),
+		};
+		if let Some(extra) = args.next() {
+			return Err(error(format!("unexpected argument '{}'", extra)));
+		}
+		return Ok(command);
+	}
+}
+
+// Why a run command gave control back
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stop {
+	Stepped,        // Finished the requested steps
+	Status(Status), // The program stopped, e.g. at a breakpoint or waiting for input
+	Error(VmError), // The instruction at the pc faulted
+}
+
+impl fmt::Display for Stop {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Stop::Stepped => write!(f, "stepped"),
+			Stop::Status(Status::Halt) => write!(f, "halted"),
+			Stop::Status(Status::WaitForInput) => write!(f, "waiting for input"),
+			Stop::Status(Status::NewOutput) => write!(f, "new output"),
+			Stop::Status(Status::BudgetExhausted) => write!(f, "instruction budget exhausted"),
+			Stop::Status(Status::Breakpoint(reason)) => match reason {
+				BreakReason::Address(address) => write!(f, "breakpoint at {}", address),
+				BreakReason::Read(address) => write!(f, "about to read [{}]", address),
+				BreakReason::Write(address) => write!(f, "about to write [{}]", address),
+				BreakReason::Opcode(opcode) => write!(f, "breakpoint on {}", opcode.mnemonic()),
+				BreakReason::Condition(id) => write!(f, "condition {} holds", id),
+			},
+			Stop::Error(e) => write!(f, "error: {}", e),
+		}
+	}
+}
+
+pub struct Session {
+	vm: VM,
+	program: Vec<i64>,  // Memory to go back to on restart
+	output: Vec<i64>,   // Everything output since the last restart
+	stop: Option<Stop>, // Why the last run command stopped
+}
+
+impl Session {
+	pub fn new(program: &[i64]) -> Self {
+		return Session::attach(VM::from_memory(program));
+	}
+
+	// Debugs a machine that may already be part way through a run, e.g. one
+	// restored from a snapshot. Restarting goes back to its current memory.
+	pub fn attach(mut vm: VM) -> Self {
+		vm.enable_history(DEFAULT_HISTORY);
+		let mut output = Vec::new();
+		output.extend(vm.drain_output());
+		Session {
+			program: vm.memory().dense().to_vec(),
+			vm,
+			output,
+			stop: None,
+		}
+	}
+
+	pub fn vm(&self) -> &VM {
+		return &self.vm;
+	}
+
+	pub fn vm_mut(&mut self) -> &mut VM {
+		return &mut self.vm;
+	}
+
+	pub fn output(&self) -> &[i64] {
+		return &self.output;
+	}
+
+	pub fn last_stop(&self) -> Option<Stop> {
+		return self.stop;
+	}
+
+	pub fn restart(&mut self) {
+		self.vm.reset(&self.program);
+		self.output.clear();
+		self.stop = None;
+	}
+
+	// Executes up to `count` instructions. Output doesn't stop stepping.
+	pub fn step(&mut self, count: u64) -> Stop {
+		if count == 0 {
+			return Stop::Stepped;
+		}
+		let mut remaining = count;
+		return self.advance(|_| {
+			remaining -= 1;
+			remaining == 0
+		});
+	}
+
+	// Steps over the current instruction. If it's a call, a jump just after
+	// an instruction that stored the address following it, runs until it
+	// returns, i.e. execution reaches that address with the same relative
+	// base, or STEP_OVER_BUDGET instructions have executed.
+	pub fn step_over(&mut self) -> Stop {
+		let pc = self.vm.pc();
+		let instruction = match self.vm.decode_at(pc) {
+			Some(instruction) => instruction,
+			None => return self.step(1),
+		};
+		let next = pc + instruction.size();
+		let stored = self
+			.vm
+			.last_write()
+			.map(|address| self.vm.memory().read(address));
+		let is_jump = instruction.opcode == Opcode::JIT || instruction.opcode == Opcode::JIF;
+		if !is_jump || stored != Some(next as i64) {
+			return self.step(1);
+		}
+
+		let relative_base = self.vm.relative_base();
+		let mut remaining = STEP_OVER_BUDGET;
+		let stop = self.advance(|vm| {
+			remaining -= 1;
+			(vm.pc() == next && vm.relative_base() == relative_base) || remaining == 0
+		});
+		if stop == Stop::Stepped && remaining == 0 && self.vm.pc() != next {
+			self.stop = Some(Stop::Status(Status::BudgetExhausted));
+			return Stop::Status(Status::BudgetExhausted);
+		}
+		return stop;
+	}
+
+	// Runs until the program halts, needs input, hits a breakpoint or faults,
+	// or `budget` instructions have executed
+	pub fn run(&mut self, budget: Option<u64>) -> Stop {
+		let mut remaining = budget;
+		let stop = match budget {
+			Some(0) => Stop::Stepped,
+			_ => self.advance(|_| match remaining.as_mut() {
+				Some(remaining) => {
+					*remaining -= 1;
+					*remaining == 0
+				}
+				None => false,
+			}),
+		};
+		if stop == Stop::Stepped {
+			self.stop = Some(Stop::Status(Status::BudgetExhausted));
+			return Stop::Status(Status::BudgetExhausted);
+		}
+		return stop;
+	}
+
+	// Undoes up to `count` instructions, returning how many were undone
+	pub fn back(&mut self, count: u64) -> u64 {
+		let mut undone = 0;
+		while undone < count && self.vm.step_back() {
+			undone += 1;
+		}
+		if undone > 0 {
+			self.stop = Some(Stop::Stepped);
+		}
+		return undone;
+	}
+
+	// Steps the VM until `done` says to stop after an instruction, or the
+	// program stops by itself. Output is collected as it goes.
+	fn advance<F: FnMut(&VM) -> bool>(&mut self, mut done: F) -> Stop {
+		let stop = loop {
+			match self.vm.step() {
+				Ok(None) | Ok(Some(Status::NewOutput)) => {
+					self.output.extend(self.vm.drain_output());
+					if done(&self.vm) {
+						break Stop::Stepped;
+					}
+				}
+				Ok(Some(status)) => break Stop::Status(status),
+				Err(e) => break Stop::Error(e),
+			}
+		};
+		self.stop = Some(stop);
+		return stop;
+	}
+
+	// Disassembles `before` instructions leading up to `address` and `after`
+	// instructions from it. Instructions can't be decoded backwards reliably,
+	// so this sweeps forwards from a little earlier, using the first start
+	// that lines up with `address`.
+	pub fn disassemble_around(&self, address: usize, before: usize, after: usize) -> Vec<Line> {
+		let (before, after) = (before.min(MAX_LISTING), after.min(MAX_LISTING));
+		let start = address.saturating_sub(before * 4);
+		let end = address.saturating_add(after * 4 + 4);
+		let words: Vec<i64> = (start..end).map(|a| self.vm.memory().read(a)).collect();
+		let mut lines = Vec::new();
+		for offset in 0..address - start {
+			let sweep = sweep(&words, start, offset, address);
+			if sweep
+				.last()
+				.is_some_and(|line| line.address() + line.size() == address)
+			{
+				let skip = sweep.len().saturating_sub(before);
+				lines.extend(sweep.into_iter().skip(skip));
+				break;
+			}
+		}
+		lines.extend(
+			sweep(&words, start, address - start, end)
+				.into_iter()
+				.take(after),
+		);
+		return lines;
+	}
+
+	pub fn execute_line(&mut self, line: &str) -> Result<String, CommandError> {
+		let command = Command::parse(line)?;
+		return self.execute(&command);
+	}
+
+	// Runs a command, returning the text to show for it
+	pub fn execute(&mut self, command: &Command) -> Result<String, CommandError> {
+		match *command {
+			Command::Step(count) => {
+				let printed = self.output.len();
+				let stop = self.step(count);
+				return Ok(self.report(printed, stop));
+			}
+			Command::Next => {
+				let printed = self.output.len();
+				let stop = self.step_over();
+				return Ok(self.report(printed, stop));
+			}
+			Command::Continue(budget) => {
+				let printed = self.output.len();
+				let stop = self.run(budget);
+				return Ok(self.report(printed, stop));
+			}
+			Command::Back(count) => {
+				let undone = self.back(count);
+				if undone == 0 {
+					return Err(error("no history to step back through"));
+				}
+				return Ok(format!(
+					"undid {} instruction(s)\n{}",
+					undone,
+					self.current_line()
+				));
+			}
+			Command::Restart => {
+				self.restart();
+				return Ok(self.current_line());
+			}
+			Command::Break(Location::Address(address)) => self.vm.breakpoints_mut().add(address),
+			Command::Break(Location::Opcode(opcode)) => {
+				self.vm.breakpoints_mut().break_on_opcode(opcode)
+			}
+			Command::Delete(location) => {
+				let removed = match location {
+					Location::Address(address) => self.vm.breakpoints_mut().remove(address),
+					Location::Opcode(opcode) => self.vm.breakpoints_mut().remove_opcode(opcode),
+				};
+				if !removed {
+					return Err(error("no such breakpoint"));
+				}
+			}
+			Command::Watch(address) => self.vm.breakpoints_mut().watch_write(address),
+			Command::ReadWatch(address) => self.vm.breakpoints_mut().watch_read(address),
+			Command::Unwatch(address) => {
+				if !self.vm.breakpoints_mut().unwatch(address) {
+					return Err(error(format!("no watchpoint on {}", address)));
+				}
+			}
+			Command::Breakpoints => return Ok(self.list_breakpoints()),
+			Command::Examine { address, count } => {
+				check_range(address, count, 1)?;
+				return Ok(self.examine(address, count));
+			}
+			Command::SetMemory { address, value } => {
+				self.vm.write_memory(address, value).map_err(|e| {
+					error(format!(
+						"memory cap of {} cells exceeded writing to {}",
+						e.limit, e.address
+					))
+				})?;
+			}
+			Command::SetPc(address) => {
+				// Past anything a jump can reach, and too close to the end to step
+				if address > i64::MAX as usize {
+					return Err(error(format!("pc {} is out of range", address)));
+				}
+				self.vm.set_pc(address);
+				return Ok(self.current_line());
+			}
+			Command::SetRelativeBase(value) => self.vm.set_relative_base(value),
+			Command::Info => return Ok(self.info()),
+			Command::Disassemble { address, count } => {
+				let count = count.unwrap_or(10);
+				let lines = match address {
+					Some(address) => {
+						check_range(address, count, 4)?;
+						self.disassemble_around(address, 0, count)
+					}
+					None => self.disassemble_around(self.vm.pc(), 5, count.min(MAX_LISTING)),
+				};
+				let text: Vec<String> = lines.iter().map(|line| self.format_line(line)).collect();
+				return Ok(text.join("\n"));
+			}
+			Command::Input(ref values) => {
+				values.iter().for_each(|&value| self.vm.queue_input(value))
+			}
+			Command::Output => return Ok(format_output(&self.output)),
+			Command::Help => return Ok(HELP.to_string()),
+			Command::Quit => (),
+		}
+		return Ok(String::new());
+	}
+
+	// Output printed by a run command, why it stopped and where it is now
+	fn report(&self, printed: usize, stop: Stop) -> String {
+		let mut text = String::new();
+		if self.output.len() > printed {
+			text.push_str(&format_output(&self.output[printed..]));
+			text.push('\n');
+		}
+		if stop != Stop::Stepped {
+			text.push_str(&stop.to_string());
+			text.push('\n');
+		}
+		text.push_str(&self.current_line());
+		return text;
+	}
+
+	fn current_line(&self) -> String {
+		let pc = self.vm.pc();
+		let line = match self.vm.decode_at(pc) {
+			Some(instruction) => Line::Code {
+				address: pc,
+				instruction,
+			},
+			None => Line::Data {
+				address: pc,
+				value: self.vm.memory().read(pc),
+			},
+		};
+		return self.format_line(&line);
+	}
+
+	// Marks the pc with `=>` and breakpoints with `*`
+	fn format_line(&self, line: &Line) -> String {
+		let address = line.address();
+		let current = if address == self.vm.pc() { "=>" } else { "  " };
+		let breakpoint = if self.vm.breakpoints().addresses().any(|a| a == address) {
+			"*"
+		} else {
+			" "
+		};
+		return format!("{}{}{}", current, breakpoint, line);
+	}
+
+	fn list_breakpoints(&self) -> String {
+		let breakpoints = self.vm.breakpoints();
+		let mut lines: Vec<String> = Vec::new();
+		lines.extend(
+			breakpoints
+				.addresses()
+				.map(|address| format!("break {}", address)),
+		);
+		for opcode in breakpoints.opcodes() {
+			lines.push(format!("break {}", opcode.mnemonic()));
+		}
+		lines.extend(
+			breakpoints
+				.write_watches()
+				.map(|address| format!("watch {}", address)),
+		);
+		lines.extend(
+			breakpoints
+				.read_watches()
+				.map(|address| format!("rwatch {}", address)),
+		);
+		if lines.is_empty() {
+			return String::from("no breakpoints");
+		}
+		return lines.join("\n");
+	}
+
+	fn examine(&self, address: usize, count: usize) -> String {
+		let end = address.saturating_add(count.min(MAX_LISTING));
+		let mut lines = Vec::new();
+		for row in (address..end).step_by(8) {
+			let end = row.saturating_add(8).min(end);
+			let values: Vec<String> = (row..end)
+				.map(|a| self.vm.memory().read(a).to_string())
+				.collect();
+			lines.push(format!("{:>5}: {}", row, values.join(" ")));
+		}
+		return lines.join("\n");
+	}
+
+	fn info(&self) -> String {
+		let input: Vec<String> = self.vm.input().iter().map(|x| x.to_string()).collect();
+		let status = match self.stop {
+			Some(stop) => stop.to_string(),
+			None => String::from("not started"),
+		};
+		return format!(
+			"pc {}  rb {}  instructions {}\nstatus: {}\ninput: [{}]\noutput: {} value(s)",
+			self.vm.pc(),
+			self.vm.relative_base(),
+			self.vm.instruction_count(),
+			status,
+			input.join(", "),
+			self.output.len()
+		);
+	}
+}
+
+// Checks that `count` items of up to `size` cells each fit in memory from
+// `address`, and aren't too many to list
+fn check_range(address: usize, count: usize, size: usize) -> Result<(), CommandError> {
+	if count > MAX_LISTING {
+		return Err(error(format!(
+			"count {} is more than the limit of {}",
+			count, MAX_LISTING
+		)));
+	}
+	if address.checked_add(count * size).is_none() {
+		return Err(error(format!(
+			"address {} is too close to the end of memory",
+			address
+		)));
+	}
+	return Ok(());
+}
+
+// Linear sweep over `words`, which start at `base`, from `offset` up to `end`
+fn sweep(words: &[i64], base: usize, offset: usize, end: usize) -> Vec<Line> {
+	let mut lines = Vec::new();
+	let mut i = offset;
+	while base + i < end && i < words.len() {
+		let line = match disasm::decode(words, i) {
+			Some(instruction) => Line::Code {
+				address: base + i,
+				instruction,
+			},
+			None => Line::Data {
+				address: base + i,
+				value: words[i],
+			},
+		};
+		i += line.size();
+		lines.push(line);
+	}
+	return lines;
+}
+
+// Shows output as text if it's all ASCII, otherwise as numbers
+fn format_output(output: &[i64]) -> String {
+	let (text, values) = ascii::decode(output);
+	if values.is_empty() && text.contains('\n') {
+		return text.trim_end().to_string();
+	}
+	let values: Vec<String> = output.iter().map(|x| x.to_string()).collect();
+	return format!("output: {}", values.join(", "));
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Counts the cell at address 9 down from 3 to 0, then halts
+	const COUNTDOWN: [i64; 10] = [101, -1, 9, 9, 1005, 9, 0, 99, 0, 3];
+
+	// Calls a subroutine at 14 that outputs its argument doubled, for each input
+	const CALLER: [i64; 24] = [
+		109, 200, // 0: set up the stack
+		3, 100, // 2: IN [100]
+		21101, 11, 0, 0, // 4: store the return address in rb+0
+		1105, 1, 14, // 8: call 14
+		1105, 1, 2, // 11: loop
+		102, 2, 100, 101, // 14: MUL #2, [100], [101]
+		4, 101, // 18: OUT [101]
+		2105, 1, 0, // 20: return
+		99,
+	];
+
+	#[test]
+	fn parses_commands() {
+		assert_eq!(Command::parse("s 5"), Ok(Command::Step(5)));
+		assert_eq!(Command::parse("  continue  "), Ok(Command::Continue(None)));
+		assert_eq!(
+			Command::parse("b out"),
+			Ok(Command::Break(Location::Opcode(Opcode::OUT)))
+		);
+		assert_eq!(
+			Command::parse("x/16 100"),
+			Ok(Command::Examine {
+				address: 100,
+				count: 16
+			})
+		);
+		assert_eq!(
+			Command::parse("set mem 5 -3"),
+			Ok(Command::SetMemory {
+				address: 5,
+				value: -3
+			})
+		);
+		assert_eq!(
+			Command::parse("input 1, 2 3"),
+			Ok(Command::Input(vec![1, 2, 3]))
+		);
+		assert_eq!(
+			Command::parse("input \"hi\""),
+			Ok(Command::Input(vec![104, 105, 10]))
+		);
+		assert_eq!(
+			Command::parse("frobnicate").unwrap_err().to_string(),
+			"unknown command 'frobnicate', try 'help'"
+		);
+		assert_eq!(
+			Command::parse("break -1"),
+			Err(error("invalid address '-1'"))
+		);
+		assert_eq!(
+			Command::parse("step 1 2"),
+			Err(error("unexpected argument '2'"))
+		);
+	}
+
+	#[test]
+	fn steps_and_breaks() {
+		let mut session = Session::new(&COUNTDOWN);
+		assert_eq!(
+			session.execute_line("step").unwrap(),
+			"=>     4: JIT [9], #0"
+		);
+		session.execute_line("break 0").unwrap();
+		assert_eq!(
+			session.execute_line("c").unwrap(),
+			"breakpoint at 0\n=>*    0: ADD #-1, [9], [9]"
+		);
+		assert_eq!(session.execute_line("x/2 8").unwrap(), "    8: 0 2");
+
+		session.execute_line("delete 0").unwrap();
+		session.execute_line("watch 9").unwrap();
+		assert_eq!(session.execute_line("breakpoints").unwrap(), "watch 9");
+		session.execute_line("c").unwrap();
+		let stop = Stop::Status(Status::Breakpoint(BreakReason::Write(9)));
+		assert_eq!(session.last_stop(), Some(stop));
+
+		session.execute_line("unwatch 9").unwrap();
+		assert_eq!(session.execute_line("c").unwrap(), "halted\n=>     7: HALT");
+		assert_eq!(
+			session.execute_line("back 2").unwrap(),
+			"undid 2 instruction(s)\n=>     0: ADD #-1, [9], [9]"
+		);
+		assert_eq!(session.vm().memory().read(9), 1);
+
+		session.execute_line("restart").unwrap();
+		assert_eq!(session.run(Some(3)), Stop::Status(Status::BudgetExhausted));
+		assert!(session.execute_line("delete 4").is_err());
+	}
+
+	#[test]
+	fn steps_over_calls() {
+		let mut session = Session::new(&CALLER);
+		assert_eq!(
+			session.execute_line("c").unwrap(),
+			"waiting for input\n=>     2: IN [100]"
+		);
+		session.execute_line("input 21").unwrap();
+		session.step(2);
+		assert_eq!(session.vm().pc(), 8);
+		assert_eq!(
+			session.execute_line("next").unwrap(),
+			"output: 42\n=>    11: JIT #1, #2"
+		);
+		assert_eq!(session.execute_line("output").unwrap(), "output: 42");
+
+		session.execute_line("set pc 20").unwrap();
+		session.execute_line("set rb 50").unwrap();
+		session.execute_line("set mem 50 23").unwrap();
+		assert_eq!(session.execute_line("s").unwrap(), "=>    23: HALT");
+
+		// Manual changes clear the history
+		assert!(session
+			.execute_line("back 2")
+			.unwrap()
+			.starts_with("undid 1 instruction(s)"));
+	}
+
+	#[test]
+	fn steps_over_jumps() {
+		// A jump back to the top of a loop isn't a call, so `next` just takes it
+		let mut session = Session::new(&[1105, 1, 0]);
+		assert_eq!(session.step_over(), Stop::Stepped);
+		assert_eq!(session.vm().pc(), 0);
+
+		// Calls a subroutine at 8 that never returns
+		let mut session = Session::new(&[1101, 7, 0, 100, 1105, 1, 8, 99, 1105, 1, 8]);
+		session.step(1);
+		assert_eq!(session.step_over(), Stop::Status(Status::BudgetExhausted));
+		assert_eq!(session.vm().pc(), 8);
+		assert_eq!(session.vm().instruction_count(), 1 + STEP_OVER_BUDGET);
+	}
+
+	#[test]
+	fn disassembles_around_pc() {
+		let mut session = Session::new(&CALLER);
+		session.execute_line("input 1").unwrap();
+		session.step(4);
+		session.execute_line("b 18").unwrap();
+		let listing = session.execute_line("disas").unwrap();
+		let expected = [
+			"       0: ARB #200",
+			"       2: IN [100]",
+			"       4: ADD #11, #0, rb+0",
+			"       8: JIT #1, #14",
+			"      11: JIT #1, #2",
+			"=>    14: MUL #2, [100], [101]",
+			"  *   18: OUT [101]",
+			"      20: JIT #1, rb+0",
+		];
+		assert!(listing.starts_with(&expected.join("\n")), "{}", listing);
+		assert!(session
+			.execute_line("info")
+			.unwrap()
+			.starts_with("pc 14  rb 200  instructions 4"));
+	}
+
+	#[test]
+	fn rejects_huge_ranges() {
+		let mut session = Session::new(&COUNTDOWN);
+		let error = session
+			.execute_line("x/8 18446744073709551615")
+			.unwrap_err();
+		assert_eq!(
+			error.to_string(),
+			"address 18446744073709551615 is too close to the end of memory"
+		);
+		assert!(session.execute_line("disas 18446744073709551615").is_err());
+		assert!(session.execute_line("set pc 18446744073709551615").is_err());
+		let error = session.execute_line("disas 0 1000000000").unwrap_err();
+		assert_eq!(
+			error.to_string(),
+			"count 1000000000 is more than the limit of 10000"
+		);
+		assert!(session.disassemble_around(usize::MAX, 5, usize::MAX).len() <= 5);
+	}
+}
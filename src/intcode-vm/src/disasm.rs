@@ -0,0 +1,268 @@
+/*
+	--- Intcode VM: disassembler ---
+*/
+
+use crate::analysis::Cfg;
+use crate::{MemMode, Opcode};
+use num_traits::FromPrimitive;
+use std::collections::BTreeSet;
+use std::fmt;
+
+// A decoded instruction parameter
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operand {
+	Address(i64),   // [12]
+	Immediate(i64), // #5
+	Relative(i64),  // rb+3
+}
+
+impl Operand {
+	pub fn new(mode: MemMode, value: i64) -> Self {
+		match mode {
+			MemMode::Address => Operand::Address(value),
+			MemMode::Immediate => Operand::Immediate(value),
+			MemMode::Relative => Operand::Relative(value),
+		}
+	}
+
+	pub fn mode(&self) -> MemMode {
+		match self {
+			Operand::Address(_) => MemMode::Address,
+			Operand::Immediate(_) => MemMode::Immediate,
+			Operand::Relative(_) => MemMode::Relative,
+		}
+	}
+
+	pub fn value(&self) -> i64 {
+		match *self {
+			Operand::Address(value) | Operand::Immediate(value) | Operand::Relative(value) => value,
+		}
+	}
+}
+
+impl fmt::Display for Operand {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			Operand::Address(address) => write!(f, "[{}]", address),
+			Operand::Immediate(value) => write!(f, "#{}", value),
+			Operand::Relative(offset) if offset < 0 => write!(f, "rb{}", offset),
+			Operand::Relative(offset) => write!(f, "rb+{}", offset),
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Instruction {
+	pub opcode: Opcode,
+	pub operands: Vec<Operand>,
+}
+
+impl Instruction {
+	// Number of memory cells taken up by the instruction
+	pub fn size(&self) -> usize {
+		return 1 + self.operands.len();
+	}
+
+	// Re-encodes the instruction as intcode
+	pub fn encode(&self) -> Vec<i64> {
+		let mut intcode = self.opcode as i64;
+		let mut scale = 100;
+		for operand in &self.operands {
+			intcode += operand.mode() as i64 * scale;
+			scale *= 10;
+		}
+		let mut words = vec![intcode];
+		words.extend(self.operands.iter().map(|operand| operand.value()));
+		return words;
+	}
+
+	// The jump target if it's known statically, i.e. an immediate parameter
+	pub fn jump_target(&self) -> Option<i64> {
+		match self.opcode {
+			Opcode::JIT | Opcode::JIF => match self.operands[1] {
+				Operand::Immediate(target) => Some(target),
+				_ => None,
+			},
+			_ => None,
+		}
+	}
+
+	// Whether execution can never continue with the next instruction
+	pub fn is_terminator(&self) -> bool {
+		match (self.opcode, self.operands.first()) {
+			(Opcode::HALT, _) => true,
+			(Opcode::JIT, Some(Operand::Immediate(condition))) => *condition != 0,
+			(Opcode::JIF, Some(Operand::Immediate(condition))) => *condition == 0,
+			_ => false,
+		}
+	}
+}
+
+impl fmt::Display for Instruction {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.opcode.mnemonic())?;
+		for (i, operand) in self.operands.iter().enumerate() {
+			write!(f, "{}{}", if i == 0 { " " } else { ", " }, operand)?;
+		}
+		return Ok(());
+	}
+}
+
+// Decodes the instruction at `address`, or None if the word there isn't a
+// valid instruction the VM could execute
+pub fn decode(memory: &[i64], address: usize) -> Option<Instruction> {
+	let intcode = *memory.get(address)?;
+	if intcode < 0 {
+		return None;
+	}
+	let opcode = Opcode::from_intcode(intcode)?;
+	let count = opcode.param_count();
+	if intcode / (100 * 10_i64.pow(count as u32)) != 0 {
+		return None; // Modes for parameters that don't exist
+	}
+	let mut operands = Vec::with_capacity(count);
+	for i in 0..count {
+		let mode = MemMode::from_i64((intcode / (100 * 10_i64.pow(i as u32))) % 10)?;
+		if mode == MemMode::Immediate && opcode.writes() && i == count - 1 {
+			return None;
+		}
+		operands.push(Operand::new(mode, *memory.get(address + 1 + i)?));
+	}
+	return Some(Instruction { opcode, operands });
+}
+
+// One line of a listing
+#[derive(Clone, Debug, PartialEq)]
+pub enum Line {
+	Code {
+		address: usize,
+		instruction: Instruction,
+	},
+	Data {
+		address: usize,
+		value: i64,
+	},
+}
+
+impl Line {
+	pub fn address(&self) -> usize {
+		match *self {
+			Line::Code { address, .. } | Line::Data { address, .. } => address,
+		}
+	}
+
+	pub fn size(&self) -> usize {
+		match self {
+			Line::Code { instruction, .. } => instruction.size(),
+			Line::Data { .. } => 1,
+		}
+	}
+}
+
+impl fmt::Display for Line {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Line::Code {
+				address,
+				instruction,
+			} => write!(f, "{:>5}: {}", address, instruction),
+			Line::Data { address, value } => write!(f, "{:>5}: DATA {}", address, value),
+		}
+	}
+}
+
+// Linear sweep from `start`, decoding everything that looks like an instruction
+pub fn disassemble(memory: &[i64], start: usize) -> Vec<Line> {
+	return listing(memory, start, |_| true);
+}
+
+// Follows control flow from `start` and only treats reachable words as code.
+// Computed jumps can't be followed, but the common call pattern of storing a
+// return address and then jumping is recognised so the caller carries on after
+// the jump.
+pub fn disassemble_reachable(memory: &[i64], start: usize) -> Vec<Line> {
+	let code = reachable(memory, start);
+	return listing(memory, start, |address| code.contains(&address));
+}
+
+// Addresses of every instruction reachable from `start`
+pub fn reachable(memory: &[i64], start: usize) -> BTreeSet<usize> {
+	let cfg = Cfg::build(memory, start);
+	return cfg.instructions().map(|(address, _)| address).collect();
+}
+
+fn listing<F: Fn(usize) -> bool>(memory: &[i64], start: usize, is_code: F) -> Vec<Line> {
+	let mut lines = Vec::new();
+	let mut address = start;
+	while address < memory.len() {
+		let line = match decode(memory, address) {
+			Some(instruction) if is_code(address) => Line::Code {
+				address,
+				instruction,
+			},
+			_ => Line::Data {
+				address,
+				value: memory[address],
+			},
+		};
+		address += line.size();
+		lines.push(line);
+	}
+	return lines;
+}
+
+// Renders lines as text, one per line
+pub fn format_listing(lines: &[Line]) -> String {
+	let mut text = String::new();
+	for line in lines {
+		text.push_str(&line.to_string());
+		text.push('\n');
+	}
+	return text;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn operands() {
+		let instruction = decode(&[21201, 12, 5, -3], 0).unwrap();
+		assert_eq!(instruction.to_string(), "ADD rb+12, #5, rb-3");
+		assert_eq!(instruction.encode(), [21201, 12, 5, -3]);
+		assert_eq!(decode(&[1101, 1, 2], 0), None); // Truncated
+		assert_eq!(decode(&[11101, 1, 2, 3], 0), None); // Immediate write
+		assert_eq!(decode(&[10099], 0), None); // Stray mode digit
+		assert_eq!(decode(&[42], 0), None);
+	}
+
+	#[test]
+	fn linear_listing() {
+		let listing = format_listing(&disassemble(&[1002, 4, 3, 4, 33, 104, -7, 99, 0, -1], 0));
+		assert_eq!(
+			listing,
+			"    0: MUL [4], #3, [4]\n    4: DATA 33\n    5: OUT #-7\n    7: HALT\n    8: DATA 0\n    9: DATA -1\n"
+		);
+	}
+
+	#[test]
+	fn follows_jumps() {
+		let program = [
+			1105, 1, 7, // 0: jump over the data
+			1, 2, 3, 4, // 3: looks like code, but is never executed
+			21101, 14, 0, 0, // 7: store the return address
+			1105, 1, 15, // 11: call 15
+			99, // 14: halt
+			4, 3, 2105, 1, 0, // 15: output and return
+		];
+		let lines = disassemble_reachable(&program, 0);
+		let code: Vec<usize> = lines
+			.iter()
+			.filter_map(|line| match line {
+				Line::Code { address, .. } => Some(*address),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(code, [0, 7, 11, 14, 15, 17]);
+	}
+}
@@ -0,0 +1,77 @@
+/*
+	--- Intcode VM: errors ---
+*/
+
+use std::error::Error;
+use std::fmt;
+
+// Everything that can go wrong while executing a program. Each variant carries
+// the address of the faulting instruction and its raw intcode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VmError {
+	UnknownOpcode {
+		pc: usize,
+		intcode: i64,
+	},
+	InvalidMode {
+		pc: usize,
+		intcode: i64,
+		mode: i64, // The offending mode digit
+	},
+	NegativeAddress {
+		pc: usize,
+		intcode: i64,
+		address: i64, // The resolved address or jump target
+	},
+	ImmediateWrite {
+		pc: usize,
+		intcode: i64,
+	},
+	MemoryCapExceeded {
+		pc: usize,
+		intcode: i64,
+		address: usize,
+		limit: usize,
+	},
+}
+
+impl VmError {
+	pub fn pc(&self) -> usize {
+		match *self {
+			VmError::UnknownOpcode { pc, .. } => pc,
+			VmError::InvalidMode { pc, .. } => pc,
+			VmError::NegativeAddress { pc, .. } => pc,
+			VmError::ImmediateWrite { pc, .. } => pc,
+			VmError::MemoryCapExceeded { pc, .. } => pc,
+		}
+	}
+
+	pub fn intcode(&self) -> i64 {
+		match *self {
+			VmError::UnknownOpcode { intcode, .. } => intcode,
+			VmError::InvalidMode { intcode, .. } => intcode,
+			VmError::NegativeAddress { intcode, .. } => intcode,
+			VmError::ImmediateWrite { intcode, .. } => intcode,
+			VmError::MemoryCapExceeded { intcode, .. } => intcode,
+		}
+	}
+}
+
+impl fmt::Display for VmError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			VmError::UnknownOpcode { .. } => write!(f, "unknown opcode")?,
+			VmError::InvalidMode { mode, .. } => write!(f, "invalid parameter mode {}", mode)?,
+			VmError::NegativeAddress { address, .. } => write!(f, "negative address {}", address)?,
+			VmError::ImmediateWrite { .. } => write!(f, "write in immediate mode")?,
+			VmError::MemoryCapExceeded { address, limit, .. } => write!(
+				f,
+				"memory cap of {} cells exceeded writing to {}",
+				limit, address
+			)?,
+		}
+		write!(f, " (instruction {} at pc {})", self.intcode(), self.pc())
+	}
+}
+
+impl Error for VmError {}
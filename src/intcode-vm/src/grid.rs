@@ -0,0 +1,395 @@
+/*
+	--- Intcode VM: output grids ---
+
+	Many programs draw a world through their output, either as `x, y, tile`
+	triples (the painting robot, the arcade cabinet, the repair droid) or as
+	lines of ASCII art (the scaffolding camera). A Grid turns that output into
+	tiles, and a Palette decides how each tile value is drawn, so front ends
+	only have to paint the cells.
+*/
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+
+pub const MAX_EXTENT: usize = 1024; // Most columns or rows a grid draws
+
+// How output values become tiles
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Decoder {
+	Triples, // x, y, value
+	Ascii,   // Character codes, one row per line
+}
+
+impl fmt::Display for Decoder {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Decoder::Triples => write!(f, "triples"),
+			Decoder::Ascii => write!(f, "ascii"),
+		}
+	}
+}
+
+// How to draw one tile value
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tile {
+	pub symbol: char,
+	pub colour: [u8; 3], // RGB
+}
+
+const WHITE: [u8; 3] = [255, 255, 255];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+	tiles: HashMap<i64, Tile>,
+	unknown: Tile, // For values that aren't in the palette or printable
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PaletteError(pub String);
+
+impl fmt::Display for PaletteError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.0)
+	}
+}
+
+impl Error for PaletteError {}
+
+impl Palette {
+	// Draws ASCII output as the characters themselves
+	pub fn new() -> Self {
+		Palette {
+			tiles: HashMap::new(),
+			unknown: Tile {
+				symbol: '?',
+				colour: WHITE,
+			},
+		}
+	}
+
+	// The arcade cabinet's tiles, which also suit the painting robot
+	pub fn tiles() -> Self {
+		let mut palette = Palette::new();
+		palette.set(0, ' ', [0, 0, 0]); // Empty, or black paint
+		palette.set(1, '#', WHITE); // Wall, or white paint
+		palette.set(2, '%', [200, 120, 40]); // Block
+		palette.set(3, '=', [80, 160, 255]); // Paddle
+		palette.set(4, 'o', [255, 220, 60]); // Ball
+		return palette;
+	}
+
+	pub fn set(&mut self, value: i64, symbol: char, colour: [u8; 3]) {
+		self.tiles.insert(value, Tile { symbol, colour });
+	}
+
+	pub fn tile(&self, value: i64) -> Tile {
+		if let Some(&tile) = self.tiles.get(&value) {
+			return tile;
+		}
+		match value {
+			32..=126 => Tile {
+				symbol: value as u8 as char,
+				colour: WHITE,
+			},
+			_ => self.unknown,
+		}
+	}
+
+	// Reads a palette written as comma separated `VALUE=SYMBOL` entries, each
+	// optionally followed by an RGB colour, e.g. `0= ,1=##ffffff,2=%#c87828`
+	pub fn parse(source: &str) -> Result<Palette, PaletteError> {
+		let mut palette = Palette::new();
+		for entry in source
+			.split(',')
+			.map(str::trim_start)
+			.filter(|entry| !entry.is_empty())
+		{
+			let invalid = || PaletteError(format!("invalid palette entry '{}'", entry));
+			let (value, tile) = entry.split_once('=').ok_or_else(invalid)?;
+			let value = value.trim().parse::<i64>().map_err(|_| invalid())?;
+			let mut chars = tile.chars();
+			let symbol = chars.next().ok_or_else(invalid)?;
+			let colour = match chars.as_str() {
+				"" => WHITE,
+				rgb => parse_colour(rgb).ok_or_else(invalid)?,
+			};
+			palette.set(value, symbol, colour);
+		}
+		return Ok(palette);
+	}
+}
+
+impl Default for Palette {
+	fn default() -> Self {
+		Palette::new()
+	}
+}
+
+fn parse_colour(source: &str) -> Option<[u8; 3]> {
+	let hex = source.strip_prefix('#')?;
+	if hex.len() != 6 {
+		return None;
+	}
+	let rgb = u32::from_str_radix(hex, 16).ok()?;
+	return Some([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8]);
+}
+
+pub struct Grid {
+	decoder: Decoder,
+	tiles: HashMap<(i64, i64), i64>,
+	status: Option<i64>, // Last value that isn't part of the picture, e.g. a score
+	pending: Vec<i64>,   // Start of an incomplete triple
+	cursor: (i64, i64),  // Where the next ASCII character goes
+	consumed: usize,     // Output values decoded by follow()
+}
+
+impl Grid {
+	pub fn new(decoder: Decoder) -> Self {
+		Grid {
+			decoder,
+			tiles: HashMap::new(),
+			status: None,
+			pending: Vec::new(),
+			cursor: (0, 0),
+			consumed: 0,
+		}
+	}
+
+	pub fn decoder(&self) -> Decoder {
+		return self.decoder;
+	}
+
+	pub fn clear(&mut self) {
+		self.tiles.clear();
+		self.status = None;
+		self.pending.clear();
+		self.cursor = (0, 0);
+		self.consumed = 0;
+	}
+
+	// Decodes one output value
+	pub fn push(&mut self, value: i64) {
+		match self.decoder {
+			Decoder::Triples => {
+				self.pending.push(value);
+				if let [x, y, tile] = self.pending[..] {
+					self.pending.clear();
+					if (x, y) == (-1, 0) {
+						self.status = Some(tile); // The arcade cabinet's score
+					} else {
+						self.tiles.insert((x, y), tile);
+					}
+				}
+			}
+			Decoder::Ascii => match value {
+				10 if self.cursor.0 == 0 && !self.tiles.is_empty() => {
+					// A blank line ends the frame, and the next one redraws it
+					self.tiles.clear();
+					self.cursor = (0, 0);
+				}
+				10 => self.cursor = (0, self.cursor.1 + 1),
+				0..=127 => {
+					self.tiles.insert(self.cursor, value);
+					self.cursor.0 += 1;
+				}
+				_ => self.status = Some(value),
+			},
+		}
+	}
+
+	pub fn extend(&mut self, values: &[i64]) {
+		for &value in values {
+			self.push(value);
+		}
+	}
+
+	// Keeps the grid in step with the whole output of a program, decoding
+	// just the new values each time. If the output got shorter, e.g. because
+	// the program was restarted, the grid is rebuilt.
+	pub fn follow(&mut self, output: &[i64]) {
+		if output.len() < self.consumed {
+			self.clear();
+		}
+		self.extend(&output[self.consumed..]);
+		self.consumed = output.len();
+	}
+
+	// Switches decoder, redrawing what follow() has seen so far
+	pub fn set_decoder(&mut self, decoder: Decoder, output: &[i64]) {
+		self.decoder = decoder;
+		self.clear();
+		self.follow(output);
+	}
+
+	pub fn get(&self, x: i64, y: i64) -> Option<i64> {
+		return self.tiles.get(&(x, y)).copied();
+	}
+
+	pub fn status(&self) -> Option<i64> {
+		return self.status;
+	}
+
+	pub fn len(&self) -> usize {
+		return self.tiles.len();
+	}
+
+	pub fn is_empty(&self) -> bool {
+		return self.tiles.is_empty();
+	}
+
+	// Smallest and largest coordinates drawn, as ((min x, min y), (max x, max y))
+	pub fn bounds(&self) -> Option<((i64, i64), (i64, i64))> {
+		let mut positions = self.tiles.keys();
+		let &first = positions.next()?;
+		return Some(positions.fold((first, first), |(min, max), &(x, y)| {
+			((min.0.min(x), min.1.min(y)), (max.0.max(x), max.1.max(y)))
+		}));
+	}
+
+	// Columns and rows drawn from the top left of bounds(), at most
+	// MAX_EXTENT each so a stray coordinate can't make the picture huge
+	pub fn extent(&self) -> Option<((i64, i64), (usize, usize))> {
+		let ((min_x, min_y), (max_x, max_y)) = self.bounds()?;
+		let span = |min: i64, max: i64| {
+			let span = max
+				.checked_sub(min)
+				.and_then(|span| usize::try_from(span).ok());
+			return span.map_or(MAX_EXTENT, |span| span.saturating_add(1).min(MAX_EXTENT));
+		};
+		return Some(((min_x, min_y), (span(min_x, max_x), span(min_y, max_y))));
+	}
+
+	// The tiles inside extent(), as ((column, row), value)
+	pub fn cells(&self) -> impl Iterator<Item = ((usize, usize), i64)> + '_ {
+		let ((min_x, min_y), (width, height)) = self.extent().unwrap_or(((0, 0), (0, 0)));
+		return self.tiles.iter().filter_map(move |(&(x, y), &value)| {
+			let column = offset(min_x, x, width)?;
+			let row = offset(min_y, y, height)?;
+			return Some(((column, row), value));
+		});
+	}
+
+	// Draws the grid as text, with missing tiles left blank
+	pub fn render(&self, palette: &Palette) -> String {
+		let (_, (width, height)) = match self.extent() {
+			Some(extent) => extent,
+			None => return String::new(),
+		};
+		let mut rows = vec![vec![' '; width]; height];
+		for ((column, row), value) in self.cells() {
+			rows[row][column] = palette.tile(value).symbol;
+		}
+		let mut text = String::new();
+		for row in rows {
+			text.push_str(row.into_iter().collect::<String>().trim_end());
+			text.push('\n');
+		}
+		return text;
+	}
+}
+
+// How far a coordinate is past the origin, if that's within the extent
+fn offset(origin: i64, coordinate: i64, extent: usize) -> Option<usize> {
+	let offset = usize::try_from(coordinate.checked_sub(origin)?).ok()?;
+	return Some(offset).filter(|&offset| offset < extent);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn decodes_triples() {
+		let mut grid = Grid::new(Decoder::Triples);
+		grid.extend(&[1, 2, 3, 6, 5, 4, -1, 0, 12345, 2, 3]);
+		assert_eq!(grid.len(), 2);
+		assert_eq!(grid.get(1, 2), Some(3));
+		assert_eq!(grid.get(2, 3), None); // Incomplete
+		assert_eq!(grid.status(), Some(12345));
+		assert_eq!(grid.bounds(), Some(((1, 2), (6, 5))));
+
+		grid.push(0);
+		assert_eq!(grid.get(2, 3), Some(0));
+		assert_eq!(grid.render(&Palette::tiles()), "=\n\n\n     o\n");
+	}
+
+	#[test]
+	fn decodes_ascii_frames() {
+		let mut grid = Grid::new(Decoder::Ascii);
+		grid.extend(&crate::ascii::encode_line("#.^"));
+		grid.extend(&crate::ascii::encode_line("..#"));
+		assert_eq!(grid.render(&Palette::new()), "#.^\n..#\n");
+
+		// A blank line, then the next frame
+		grid.extend(&[10, 35, 10, 1234]);
+		assert_eq!(grid.render(&Palette::new()), "#\n");
+		assert_eq!(grid.status(), Some(1234));
+	}
+
+	#[test]
+	fn caps_the_extent() {
+		let mut grid = Grid::new(Decoder::Triples);
+		grid.extend(&[i64::MIN, 0, 1, i64::MAX, i64::MAX, 1, i64::MIN + 2, 1, 4]);
+		assert_eq!(
+			grid.extent(),
+			Some(((i64::MIN, 0), (MAX_EXTENT, MAX_EXTENT)))
+		);
+		assert_eq!(grid.cells().count(), 2);
+		let text = grid.render(&Palette::tiles());
+		assert!(text.starts_with("#\n  o\n\n"));
+		assert_eq!(text.len(), 6 + MAX_EXTENT - 2);
+	}
+
+	#[test]
+	fn follows_output() {
+		let mut grid = Grid::new(Decoder::Triples);
+		let mut output = vec![0, 0, 1, 1];
+		grid.follow(&output);
+		output.extend(&[0, 2]);
+		grid.follow(&output);
+		assert_eq!((grid.get(0, 0), grid.get(1, 0)), (Some(1), Some(2)));
+
+		// Restarting truncates the output
+		grid.follow(&[5, 5, 4]);
+		assert_eq!((grid.len(), grid.get(5, 5)), (1, Some(4)));
+
+		grid.set_decoder(Decoder::Ascii, &[5, 5, 4]);
+		assert_eq!(grid.render(&Palette::parse("5=@").unwrap()), "@@?\n");
+	}
+
+	#[test]
+	fn parses_palettes() {
+		let palette = Palette::parse("0= , 1=##ffffff,2=%#c87828").unwrap();
+		assert_eq!(
+			palette.tile(0),
+			Tile {
+				symbol: ' ',
+				colour: WHITE
+			}
+		);
+		assert_eq!(
+			palette.tile(1),
+			Tile {
+				symbol: '#',
+				colour: WHITE
+			}
+		);
+		assert_eq!(
+			palette.tile(2),
+			Tile {
+				symbol: '%',
+				colour: [200, 120, 40]
+			}
+		);
+		assert_eq!(palette.tile(65).symbol, 'A');
+		assert_eq!(palette.tile(-3).symbol, '?');
+
+		assert_eq!(
+			Palette::parse("1=#,x=.").unwrap_err().to_string(),
+			"invalid palette entry 'x=.'"
+		);
+		assert!(Palette::parse("1=#12").is_err());
+	}
+}
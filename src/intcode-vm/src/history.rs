@@ -0,0 +1,226 @@
+/*
+	--- Intcode VM: execution history ---
+
+	With history enabled the VM logs what each instruction changed: the cell
+	it wrote and the value it overwrote, the relative base, where it started
+	and any input it consumed. Undoing those changes in reverse order walks
+	the machine backwards one instruction at a time, without needing a
+	snapshot per step. Output can't be taken back, so stepping backwards over
+	an OUT leaves the value wherever it was sent.
+*/
+
+use crate::{Status, VmError, VM};
+use std::collections::VecDeque;
+
+// Everything needed to undo one instruction
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Change {
+	pc: usize,                   // Address of the instruction
+	relative_base: i64,          // Relative base before it executed
+	write: Option<(usize, i64)>, // Cell written and its previous value
+	input: Option<i64>,          // Input value consumed
+}
+
+pub(crate) struct History {
+	capacity: usize,           // Maximum number of instructions kept
+	changes: VecDeque<Change>, // Oldest first
+	pending: Change,           // Changes of the instruction being executed
+}
+
+impl History {
+	fn new(capacity: usize) -> Self {
+		History {
+			capacity,
+			changes: VecDeque::with_capacity(capacity.min(1 << 16)),
+			pending: Change::default(),
+		}
+	}
+
+	pub(crate) fn clear(&mut self) {
+		self.changes.clear();
+	}
+
+	pub(crate) fn begin(&mut self, pc: usize, relative_base: i64) {
+		self.pending = Change {
+			pc,
+			relative_base,
+			write: None,
+			input: None,
+		};
+	}
+
+	pub(crate) fn record_write(&mut self, address: usize, old: i64) {
+		self.pending.write = Some((address, old));
+	}
+
+	pub(crate) fn record_input(&mut self, value: i64) {
+		self.pending.input = Some(value);
+	}
+
+	// The instruction completed, so keep its changes
+	pub(crate) fn commit(&mut self) {
+		if self.capacity == 0 {
+			return;
+		}
+		if self.changes.len() == self.capacity {
+			self.changes.pop_front();
+		}
+		self.changes.push_back(self.pending);
+	}
+}
+
+impl VM {
+	// Starts logging changes, keeping the last `capacity` instructions
+	pub fn enable_history(&mut self, capacity: usize) {
+		self.history = Some(History::new(capacity));
+	}
+
+	pub fn disable_history(&mut self) {
+		self.history = None;
+	}
+
+	// How many instructions can currently be stepped back
+	pub fn history_len(&self) -> usize {
+		return self
+			.history
+			.as_ref()
+			.map_or(0, |history| history.changes.len());
+	}
+
+	// The cell written by the last instruction, if it wrote one and history
+	// is enabled
+	pub fn last_write(&self) -> Option<usize> {
+		let change = self.history.as_ref()?.changes.back()?;
+		return change.write.map(|(address, _)| address);
+	}
+
+	// Undoes the last instruction, returning false if there's no history left
+	pub fn step_back(&mut self) -> bool {
+		let change = match self
+			.history
+			.as_mut()
+			.and_then(|history| history.changes.pop_back())
+		{
+			Some(change) => change,
+			None => return false,
+		};
+		if let Some((address, old)) = change.write {
+			// The cell was written before, so it's already allocated
+			self.ram
+				.write(address, old)
+				.expect("Undo of an allocated cell");
+			if let Some(cache) = self.cache.as_mut() {
+				cache.invalidate(address);
+			}
+		}
+		if let Some(value) = change.input {
+			self.input.push_front(value);
+		}
+		self.relative_base = change.relative_base;
+		self.pc.set(change.pc);
+		self.instruction_pc = change.pc;
+		self.instructions -= 1;
+		// Going forwards again shouldn't stop on a breakpoint we're already at
+		self.breakpoints.resume = Some(change.pc);
+		return true;
+	}
+
+	// Steps back to just before the most recent instruction that wrote to
+	// `address`. Returns false and leaves the VM alone if there isn't one in
+	// the history.
+	pub fn run_back_to_write(&mut self, address: usize) -> bool {
+		let found = self.history.as_ref().and_then(|history| {
+			history
+				.changes
+				.iter()
+				.rposition(|change| change.write.is_some_and(|(written, _)| written == address))
+				.map(|index| history.changes.len() - index)
+		});
+		let steps = match found {
+			Some(steps) => steps,
+			None => return false,
+		};
+		for _ in 0..steps {
+			self.step_back();
+		}
+		return true;
+	}
+
+	// Moves to the point where `count` instructions have executed, stepping
+	// back through the history or running forwards. Going back stops at the
+	// oldest recorded instruction; going forwards stops early with the status
+	// if the program halts, waits for input, outputs or hits a breakpoint.
+	pub fn seek(&mut self, count: u64) -> Result<Option<Status>, VmError> {
+		while self.instructions > count {
+			if !self.step_back() {
+				return Ok(None);
+			}
+		}
+		while self.instructions < count {
+			if let Some(status) = self.step()? {
+				return Ok(Some(status));
+			}
+		}
+		return Ok(None);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Counts the cell at address 9 down from 3 to 0, then halts
+	const COUNTDOWN: [i64; 10] = [101, -1, 9, 9, 1005, 9, 0, 99, 0, 3];
+
+	#[test]
+	fn steps_backwards() {
+		let mut vm = VM::from_memory(&COUNTDOWN);
+		vm.enable_history(100);
+		assert_eq!(vm.run_intcode(), Ok(Status::Halt));
+		assert_eq!(vm.history_len(), 6);
+
+		assert!(vm.step_back());
+		assert_eq!((vm.pc(), vm.instruction_count()), (4, 5));
+		assert!(vm.step_back());
+		assert_eq!((vm.pc(), vm.memory().read(9)), (0, 1));
+
+		while vm.step_back() {}
+		assert_eq!(vm.memory().dense(), COUNTDOWN);
+		assert_eq!((vm.pc(), vm.instruction_count()), (0, 0));
+		assert_eq!(vm.run_intcode(), Ok(Status::Halt));
+	}
+
+	#[test]
+	fn runs_back_to_writes_and_seeks() {
+		let mut vm = VM::from_memory(&COUNTDOWN);
+		vm.enable_history(3);
+		assert_eq!(vm.run_intcode(), Ok(Status::Halt));
+		assert_eq!(vm.history_len(), 3);
+
+		// The last write left 0, so undoing it lands before the ADD
+		assert!(vm.run_back_to_write(9));
+		assert_eq!(
+			(vm.pc(), vm.instruction_count(), vm.memory().read(9)),
+			(0, 4, 1)
+		);
+		assert!(!vm.run_back_to_write(9)); // Earlier writes were forgotten
+		assert!(!vm.run_back_to_write(5));
+
+		assert_eq!(vm.seek(1), Ok(None));
+		assert_eq!(vm.instruction_count(), 3); // As far back as the history goes
+		assert_eq!(vm.seek(5), Ok(None));
+		assert_eq!((vm.pc(), vm.memory().read(9)), (4, 0));
+		assert_eq!(vm.seek(100), Ok(Some(Status::Halt)));
+	}
+
+	#[test]
+	fn restores_input() {
+		let mut vm = VM::from_memory(&[3, 5, 4, 5, 99, 0]);
+		vm.enable_history(10);
+		assert_eq!(vm.run_until_halt(&[7]).unwrap().output, [7]);
+		vm.seek(0).unwrap();
+		assert_eq!(vm.input(), &[7]);
+		assert_eq!(vm.memory().read(5), 0);
+		assert_eq!(vm.run_until_halt(&[]).unwrap().output, [7]);
+	}
+}
@@ -0,0 +1,249 @@
+/*
+	--- Intcode VM: I/O devices ---
+*/
+
+use crate::ascii;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+// Something the VM can pull input values from
+pub trait InputSource {
+	// The next input value, or None if the VM should wait for input
+	fn read_input(&mut self) -> Option<i64>;
+}
+
+// Something the VM can push output values to
+pub trait OutputSink {
+	fn write_output(&mut self, value: i64);
+}
+
+// A device that both feeds and consumes values, e.g. a robot or a screen
+pub trait IoDevice: InputSource + OutputSink {}
+
+impl<T: InputSource + OutputSink> IoDevice for T {}
+
+// The default queue used by the VM for its own input and output
+impl InputSource for VecDeque<i64> {
+	fn read_input(&mut self) -> Option<i64> {
+		return self.pop_front();
+	}
+}
+
+impl OutputSink for VecDeque<i64> {
+	fn write_output(&mut self, value: i64) {
+		self.push_back(value);
+	}
+}
+
+impl OutputSink for Vec<i64> {
+	fn write_output(&mut self, value: i64) {
+		self.push(value);
+	}
+}
+
+// A queue that can be shared between two VMs, one writing and one reading
+pub type SharedQueue = Arc<Mutex<VecDeque<i64>>>;
+
+pub fn shared_queue() -> SharedQueue {
+	return Arc::new(Mutex::new(VecDeque::new()));
+}
+
+impl<T: InputSource + ?Sized> InputSource for Arc<Mutex<T>> {
+	fn read_input(&mut self) -> Option<i64> {
+		return self.lock().expect("Poisoned I/O device").read_input();
+	}
+}
+
+impl<T: OutputSink + ?Sized> OutputSink for Arc<Mutex<T>> {
+	fn write_output(&mut self, value: i64) {
+		self.lock()
+			.expect("Poisoned I/O device")
+			.write_output(value);
+	}
+}
+
+impl InputSource for Receiver<i64> {
+	fn read_input(&mut self) -> Option<i64> {
+		return self.try_recv().ok();
+	}
+}
+
+impl OutputSink for Sender<i64> {
+	fn write_output(&mut self, value: i64) {
+		// A hung up receiver just means nobody is listening any more
+		let _ = self.send(value);
+	}
+}
+
+// Adapts a closure into an input source
+pub struct FnInput<F>(pub F);
+
+impl<F: FnMut() -> Option<i64>> InputSource for FnInput<F> {
+	fn read_input(&mut self) -> Option<i64> {
+		return (self.0)();
+	}
+}
+
+// Adapts a closure into an output sink
+pub struct FnOutput<F>(pub F);
+
+impl<F: FnMut(i64)> OutputSink for FnOutput<F> {
+	fn write_output(&mut self, value: i64) {
+		(self.0)(value);
+	}
+}
+
+// How values are encoded on a terminal
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IoMode {
+	Numeric, // One integer per line
+	Ascii,   // Characters as their character codes
+}
+
+// Reads input values from stdin, blocking until a line is available
+pub struct StdinInput {
+	mode: IoMode,
+	pending: VecDeque<i64>, // Values from the last line that haven't been read yet
+}
+
+impl StdinInput {
+	pub fn new(mode: IoMode) -> Self {
+		StdinInput {
+			mode,
+			pending: VecDeque::new(),
+		}
+	}
+
+	fn read_line(&mut self) -> bool {
+		let stdin = io::stdin();
+		let mut line = String::new();
+		match stdin.lock().read_line(&mut line) {
+			Ok(0) | Err(_) => return false, // End of input
+			Ok(_) => (),
+		}
+		match self.mode {
+			IoMode::Numeric => {
+				let (values, errors) = parse_numbers(&line);
+				for error in errors {
+					eprintln!("error: ignoring {} of input", error);
+				}
+				self.pending.extend(values);
+			}
+			IoMode::Ascii => {
+				let line = line.trim_end_matches(&['\r', '\n'][..]);
+				self.pending.extend(ascii::encode_line(line));
+			}
+		}
+		return true;
+	}
+}
+
+// Parses a line of numbers separated by commas or whitespace, along with an
+// error message for each token that isn't a number
+fn parse_numbers(line: &str) -> (Vec<i64>, Vec<String>) {
+	let mut values = Vec::new();
+	let mut errors = Vec::new();
+	for token in line.split(|c: char| c == ',' || c.is_whitespace()) {
+		if token.is_empty() {
+			continue;
+		}
+		match token.parse::<i64>() {
+			Ok(value) => values.push(value),
+			Err(_) => {
+				let offset = token.as_ptr() as usize - line.as_ptr() as usize;
+				errors.push(format!("invalid value '{}' at byte {}", token, offset));
+			}
+		}
+	}
+	return (values, errors);
+}
+
+impl InputSource for StdinInput {
+	fn read_input(&mut self) -> Option<i64> {
+		while self.pending.is_empty() {
+			if !self.read_line() {
+				return None;
+			}
+		}
+		return self.pending.pop_front();
+	}
+}
+
+// Writes output values to stdout
+pub struct StdoutOutput {
+	mode: IoMode,
+}
+
+impl StdoutOutput {
+	pub fn new(mode: IoMode) -> Self {
+		StdoutOutput { mode }
+	}
+}
+
+impl OutputSink for StdoutOutput {
+	fn write_output(&mut self, value: i64) {
+		let stdout = io::stdout();
+		let mut stdout = stdout.lock();
+		let _ = match self.mode {
+			IoMode::Ascii => match ascii::decode(&[value]) {
+				(text, _) if !text.is_empty() => write!(stdout, "{}", text),
+				_ => writeln!(stdout, "{}", value),
+			},
+			IoMode::Numeric => writeln!(stdout, "{}", value),
+		};
+		let _ = stdout.flush();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::mpsc;
+
+	#[test]
+	fn queues_and_closures() {
+		let mut queue: VecDeque<i64> = VecDeque::new();
+		queue.write_output(1);
+		queue.write_output(2);
+		assert_eq!(queue.read_input(), Some(1));
+
+		let mut counter = 0;
+		let mut input = FnInput(move || {
+			counter += 1;
+			Some(counter)
+		});
+		assert_eq!(input.read_input(), Some(1));
+		assert_eq!(input.read_input(), Some(2));
+	}
+
+	#[test]
+	fn channels_and_shared_queues() {
+		let (tx, mut rx) = mpsc::channel();
+		let mut tx = tx;
+		assert_eq!(rx.read_input(), None);
+		tx.write_output(5);
+		assert_eq!(rx.read_input(), Some(5));
+
+		let queue = shared_queue();
+		let mut writer = queue.clone();
+		let mut reader = queue;
+		writer.write_output(3);
+		assert_eq!(reader.read_input(), Some(3));
+		assert_eq!(reader.read_input(), None);
+	}
+
+	#[test]
+	fn reports_bad_numbers() {
+		let (values, errors) = parse_numbers("1, x 3,-4,5y\n");
+		assert_eq!(values, [1, 3, -4]);
+		assert_eq!(
+			errors,
+			[
+				"invalid value 'x' at byte 3",
+				"invalid value '5y' at byte 10"
+			]
+		);
+	}
+}
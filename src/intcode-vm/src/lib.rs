@@ -1,19 +1,56 @@
-use env_logger;
+#![allow(clippy::needless_return, clippy::upper_case_acronyms)]
+
+use cache::DecodeCache;
+use disasm::Instruction;
+use history::History;
 use log::LevelFilter;
 
 use num_derive::FromPrimitive; // For converting intcode into enums
 use num_traits::FromPrimitive;
 use std::cell::Cell; // For multiple mutable references // For converting intcode into enumss
+use std::collections::VecDeque;
+
+pub mod analysis;
+pub mod ascii;
+pub mod asm;
+pub mod breakpoint;
+mod cache;
+pub mod compile;
+pub mod debugger;
+pub mod disasm;
+mod error;
+pub mod grid;
+mod history;
+pub mod io;
+mod memory;
+pub mod network;
+mod parser;
+pub mod repl;
+pub mod snapshot;
+mod solver;
+pub mod symbolic;
+pub mod threaded;
+pub mod trace;
+pub use ascii::AsciiTerminal;
+pub use breakpoint::{BreakReason, Breakpoints};
+pub use error::VmError;
+pub use io::{InputSource, IoDevice, OutputSink};
+pub use memory::{Memory, MemoryLimitExceeded, DEFAULT_MEMORY_LIMIT};
+pub use parser::{load_program, parse_program, read_program, ParseError};
+pub use snapshot::{Snapshot, SnapshotError};
+pub use trace::{TraceRecord, Tracer};
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Status {
 	WaitForInput,
 	NewOutput,
 	Halt,
+	BudgetExhausted,         // run_for() executed its full instruction budget
+	Breakpoint(BreakReason), // Stopped before the instruction at the pc
 }
 
-#[derive(FromPrimitive)]
-enum Opcode {
+#[derive(Clone, Copy, Debug, PartialEq, FromPrimitive)]
+pub enum Opcode {
 	ADD = 1,   // Addition
 	MUL = 2,   // Multiplication
 	IN = 3,    // Read input
@@ -22,21 +59,134 @@ enum Opcode {
 	JIF = 6,   // Jump If False
 	LT = 7,    // Less Than check
 	EQ = 8,    // Equal check
+	ARB = 9,   // Adjust Relative Base
 	HALT = 99, // End of program
 }
 
+impl Opcode {
+	pub const ALL: [Opcode; 10] = [
+		Opcode::ADD,
+		Opcode::MUL,
+		Opcode::IN,
+		Opcode::OUT,
+		Opcode::JIT,
+		Opcode::JIF,
+		Opcode::LT,
+		Opcode::EQ,
+		Opcode::ARB,
+		Opcode::HALT,
+	];
+
+	// Decodes the opcode part of an intcode, ignoring the parameter modes
+	pub fn from_intcode(intcode: i64) -> Option<Opcode> {
+		return Opcode::from_i64(intcode % 100);
+	}
+
+	pub fn param_count(self) -> usize {
+		match self {
+			Opcode::ADD | Opcode::MUL | Opcode::LT | Opcode::EQ => 3,
+			Opcode::JIT | Opcode::JIF => 2,
+			Opcode::IN | Opcode::OUT | Opcode::ARB => 1,
+			Opcode::HALT => 0,
+		}
+	}
+
+	// Whether the last parameter is an address the instruction writes to
+	pub fn writes(self) -> bool {
+		return matches!(
+			self,
+			Opcode::ADD | Opcode::MUL | Opcode::IN | Opcode::LT | Opcode::EQ
+		);
+	}
+
+	pub fn mnemonic(self) -> &'static str {
+		match self {
+			Opcode::ADD => "ADD",
+			Opcode::MUL => "MUL",
+			Opcode::IN => "IN",
+			Opcode::OUT => "OUT",
+			Opcode::JIT => "JIT",
+			Opcode::JIF => "JIF",
+			Opcode::LT => "LT",
+			Opcode::EQ => "EQ",
+			Opcode::ARB => "ARB",
+			Opcode::HALT => "HALT",
+		}
+	}
+}
+
+// Outcome of one of the high level run_until_* helpers
+#[derive(Clone, Debug, PartialEq)]
+pub struct RunResult {
+	pub status: Status,   // Why execution stopped
+	pub output: Vec<i64>, // Every value output during the run
+}
+
+impl RunResult {
+	pub fn halted(&self) -> bool {
+		return self.status == Status::Halt;
+	}
+
+	// The final output is usually the answer to the puzzle
+	pub fn last_output(&self) -> Option<i64> {
+		return self.output.last().copied();
+	}
+}
+
+// The Status-based API shared by the interpreter and compiled::CompiledVM, so
+// solvers and networks can run on either
+pub trait Engine {
+	fn from_memory(memory: &[i64]) -> Self
+	where
+		Self: Sized;
+	fn reset(&mut self, memory: &[i64]);
+	fn queue_input(&mut self, value: i64);
+	fn input(&self) -> &VecDeque<i64>;
+	fn output(&self) -> &VecDeque<i64>;
+	fn drain_output(&mut self) -> Vec<i64>;
+	fn memory(&self) -> &Memory;
+	fn pc(&self) -> usize;
+	fn relative_base(&self) -> i64;
+	fn instruction_count(&self) -> u64;
+	fn step(&mut self) -> Result<Option<Status>, VmError>;
+	fn run_for(&mut self, budget: u64) -> Result<Status, VmError>;
+	fn run_intcode(&mut self) -> Result<Status, VmError>;
+	fn run_until_halt(&mut self, input: &[i64]) -> Result<RunResult, VmError>;
+	fn run_until_outputs(&mut self, count: usize) -> Result<RunResult, VmError>;
+	fn run_until_input(&mut self) -> Result<RunResult, VmError>;
+}
+
 // Memory access modes
-#[derive(FromPrimitive)]
-enum MemMode {
+#[derive(Clone, Copy, Debug, PartialEq, FromPrimitive)]
+pub enum MemMode {
 	Address = 0,
 	Immediate = 1,
+	Relative = 2,
 }
 
+pub type InputDevice = Box<dyn InputSource + Send>;
+pub type OutputDevice = Box<dyn OutputSink + Send>;
+
 pub struct VM {
-	pub input: Vec<i64>,  // Queue of input values
-	pub output: Vec<i64>, // Queue of output values
-	pc: Cell<usize>,      // Program counter, keeps track of execution
-	ram: Vec<i64>,        // Internal memory of the machine
+	input: VecDeque<i64>,                   // Queue of input values
+	output: VecDeque<i64>,                  // Queue of output values
+	input_device: Option<InputDevice>,      // Read from once the input queue is empty
+	output_device: Option<OutputDevice>,    // Receives output instead of the output queue
+	pc: Cell<usize>,                        // Program counter, keeps track of execution
+	relative_base: i64,                     // Base offset for MemMode::Relative parameters
+	ram: Memory,                            // Internal memory of the machine
+	instruction_pc: usize,                  // Address of the instruction being executed
+	instructions: u64,                      // Number of instructions executed so far
+	tracer: Option<Box<dyn Tracer + Send>>, // Receives a record per executed instruction
+	breakpoints: Breakpoints,               // Where to stop before executing
+	history: Option<History>,               // Undo log, if enabled
+	cache: Option<DecodeCache>,             // Instructions already decoded, if enabled
+}
+
+impl Default for VM {
+	fn default() -> Self {
+		VM::new()
+	}
 }
 
 pub fn enable_logging() -> bool {
@@ -51,173 +201,542 @@ pub fn enable_logging() -> bool {
 impl VM {
 	pub fn new() -> Self {
 		VM {
-			input: Vec::new(),
-			output: Vec::new(),
+			input: VecDeque::new(),
+			output: VecDeque::new(),
+			input_device: None,
+			output_device: None,
 			pc: Cell::new(0),
-			ram: Vec::new(),
+			relative_base: 0,
+			ram: Memory::new(),
+			instruction_pc: 0,
+			instructions: 0,
+			tracer: None,
+			breakpoints: Breakpoints::new(),
+			history: None,
+			cache: Some(DecodeCache::new()),
 		}
 	}
 
-	pub fn from_memory(memory: &Vec<i64>) -> VM {
+	pub fn from_memory(memory: &[i64]) -> VM {
 		let vm: VM = VM::new();
 		VM {
-			ram: memory.clone(),
+			ram: Memory::from_slice(memory),
 			..vm // Update syntax: only update 'ram'
 		}
 	}
 
-	pub fn reset(&mut self, memory: &Vec<i64>) {
+	pub fn reset(&mut self, memory: &[i64]) {
 		self.input.clear();
 		self.output.clear();
 		self.pc = Cell::new(0);
-		self.ram = memory.clone();
+		self.relative_base = 0;
+		self.instruction_pc = 0;
+		self.instructions = 0;
+		self.edited();
+		let limit = self.ram.limit();
+		self.ram = Memory::from_slice(memory);
+		self.ram.set_limit(limit.max(memory.len()));
+		self.revalidate_cache();
+	}
+
+	pub fn queue_input(&mut self, value: i64) {
+		self.input.push_back(value);
+	}
+
+	// Input values that have been queued but not yet consumed
+	pub fn input(&self) -> &VecDeque<i64> {
+		return &self.input;
+	}
+
+	// Output values that haven't been drained yet
+	pub fn output(&self) -> &VecDeque<i64> {
+		return &self.output;
+	}
+
+	// Connects an input device, consulted whenever the input queue runs dry
+	pub fn attach_input<T: InputSource + Send + 'static>(&mut self, device: T) {
+		self.input_device = Some(Box::new(device));
+	}
+
+	// Connects an output device, which then receives all output instead of the queue
+	pub fn attach_output<T: OutputSink + Send + 'static>(&mut self, device: T) {
+		self.output_device = Some(Box::new(device));
+	}
+
+	pub fn detach_input(&mut self) -> Option<InputDevice> {
+		return self.input_device.take();
+	}
+
+	pub fn detach_output(&mut self) -> Option<OutputDevice> {
+		return self.output_device.take();
+	}
+
+	pub fn relative_base(&self) -> i64 {
+		return self.relative_base;
+	}
+
+	pub fn memory(&self) -> &Memory {
+		return &self.ram;
+	}
+
+	// Caps how many memory cells the program may allocate
+	pub fn set_memory_limit(&mut self, cells: usize) {
+		self.ram.set_limit(cells);
+	}
+
+	pub fn pc(&self) -> usize {
+		return self.pc.get();
+	}
+
+	// Manual changes to the machine state, e.g. from a debugger. They can't be
+	// undone, so they clear the history.
+	pub fn set_pc(&mut self, pc: usize) {
+		self.pc.set(pc);
+		self.edited();
+	}
+
+	pub fn set_relative_base(&mut self, relative_base: i64) {
+		self.relative_base = relative_base;
+		self.edited();
+	}
+
+	pub fn write_memory(&mut self, address: usize, value: i64) -> Result<(), MemoryLimitExceeded> {
+		self.ram.write(address, value)?;
+		if let Some(cache) = self.cache.as_mut() {
+			cache.invalidate(address);
+		}
+		self.edited();
+		return Ok(());
+	}
+
+	pub(crate) fn edited(&mut self) {
+		self.breakpoints.resume = None;
+		if let Some(history) = self.history.as_mut() {
+			history.clear();
+		}
+	}
+
+	// Number of instructions executed, not counting HALT or a blocked IN
+	pub fn instruction_count(&self) -> u64 {
+		return self.instructions;
 	}
 
-	pub fn process_input(&mut self) {
-		self.opcode_in();
+	pub fn process_input(&mut self) -> Result<(), VmError> {
+		// The VM is parked on the IN instruction, so simply retry it
+		self.step()?;
+		return Ok(());
 	}
-	fn get_param_modes(intcode: i64, count: u32) -> Vec<MemMode> {
+
+	// Decodes the instruction at `address` without executing it. Unlike
+	// disasm::decode, mode digits past the last parameter are ignored, the
+	// same as when the VM executes it.
+	pub(crate) fn decode_at(&self, address: usize) -> Option<Instruction> {
+		let intcode = self.ram.read(address);
+		let count = Opcode::from_intcode(intcode)?.param_count();
+		let mut words = vec![intcode % (100 * 10_i64.pow(count as u32))];
+		words.extend((1..=count).map(|i| self.ram.read(address + i)));
+		return disasm::decode(&words, 0);
+	}
+
+	fn fault(&self, intcode: i64) -> (usize, i64) {
+		return (self.instruction_pc, intcode);
+	}
+
+	fn get_param_modes(&self, intcode: i64, count: u32) -> Result<Vec<MemMode>, VmError> {
 		(0..count)
 			.map(|i| {
-				let m = (intcode / ((10 as i64).pow(i + 2))) % 10;
-				MemMode::from_i64(m).expect("Bad MemMode")
+				let m = (intcode / (10_i64.pow(i + 2))) % 10;
+				MemMode::from_i64(m).ok_or_else(|| {
+					let (pc, intcode) = self.fault(intcode);
+					VmError::InvalidMode {
+						pc,
+						intcode,
+						mode: m,
+					}
+				})
 			})
 			.collect()
 	}
 
-	fn mem_read(&self, index: usize, mode: &MemMode) -> i64 {
+	fn to_address(&self, intcode: i64, address: i64) -> Result<usize, VmError> {
+		if address < 0 {
+			let (pc, intcode) = self.fault(intcode);
+			return Err(VmError::NegativeAddress {
+				pc,
+				intcode,
+				address,
+			});
+		}
+		return Ok(address as usize);
+	}
+
+	fn mem_read(&self, intcode: i64, index: usize, mode: &MemMode) -> Result<i64, VmError> {
 		match mode {
 			MemMode::Address => {
-				let addr = self.ram[index] as usize;
-				self.ram[addr]
+				let addr = self.to_address(intcode, self.ram.read(index))?;
+				Ok(self.ram.read(addr))
+			}
+			MemMode::Immediate => Ok(self.ram.read(index)),
+			MemMode::Relative => {
+				let addr = self.relative_base.wrapping_add(self.ram.read(index));
+				Ok(self.ram.read(self.to_address(intcode, addr)?))
+			}
+		}
+	}
+
+	fn mem_write(
+		&mut self,
+		intcode: i64,
+		index: usize,
+		mode: &MemMode,
+		value: i64,
+	) -> Result<(), VmError> {
+		let addr = self.write_address(intcode, index, mode)?;
+		return self.write_cell(intcode, addr, value);
+	}
+
+	// The address a write parameter refers to
+	fn write_address(&self, intcode: i64, index: usize, mode: &MemMode) -> Result<usize, VmError> {
+		match mode {
+			MemMode::Address => return self.to_address(intcode, self.ram.read(index)),
+			MemMode::Immediate => {
+				let (pc, intcode) = self.fault(intcode);
+				return Err(VmError::ImmediateWrite { pc, intcode });
+			}
+			MemMode::Relative => {
+				let addr = self.relative_base.wrapping_add(self.ram.read(index));
+				return self.to_address(intcode, addr);
 			}
-			MemMode::Immediate => self.ram[index],
 		}
 	}
 
-	fn mem_write(&mut self, index: usize, value: i64) {
-		// Writes are always in MemMode::Address mode
-		let addr = self.ram[index] as usize;
-		self.ram[addr] = value;
+	// Writes a cell on behalf of an instruction, keeping the history and the
+	// decode cache up to date
+	fn write_cell(&mut self, intcode: i64, addr: usize, value: i64) -> Result<(), VmError> {
+		if let Some(history) = self.history.as_mut() {
+			history.record_write(addr, self.ram.read(addr));
+		}
+		if let Some(cache) = self.cache.as_mut() {
+			cache.invalidate(addr);
+		}
+		self.ram.write(addr, value).map_err(|e| {
+			let (pc, intcode) = self.fault(intcode);
+			VmError::MemoryCapExceeded {
+				pc,
+				intcode,
+				address: e.address,
+				limit: e.limit,
+			}
+		})
 	}
 
-	fn opcode_add(&mut self, intcode: i64) {
-		let pmodes = VM::get_param_modes(intcode, 2);
-		let p0 = self.mem_read(self.next_ip(), &pmodes[0]);
-		let p1 = self.mem_read(self.next_ip(), &pmodes[1]);
-		self.mem_write(self.next_ip(), p0 + p1);
+	fn opcode_add(&mut self, intcode: i64) -> Result<(), VmError> {
+		let pmodes = self.get_param_modes(intcode, 3)?;
+		let p0 = self.mem_read(intcode, self.next_ip(), &pmodes[0])?;
+		let p1 = self.mem_read(intcode, self.next_ip(), &pmodes[1])?;
+		self.mem_write(intcode, self.next_ip(), &pmodes[2], p0.wrapping_add(p1))
 	}
 
-	fn opcode_mul(&mut self, intcode: i64) {
-		let pmodes = VM::get_param_modes(intcode, 2);
-		let p0 = self.mem_read(self.next_ip(), &pmodes[0]);
-		let p1 = self.mem_read(self.next_ip(), &pmodes[1]);
-		self.mem_write(self.next_ip(), p0 * p1);
+	fn opcode_mul(&mut self, intcode: i64) -> Result<(), VmError> {
+		let pmodes = self.get_param_modes(intcode, 3)?;
+		let p0 = self.mem_read(intcode, self.next_ip(), &pmodes[0])?;
+		let p1 = self.mem_read(intcode, self.next_ip(), &pmodes[1])?;
+		self.mem_write(intcode, self.next_ip(), &pmodes[2], p0.wrapping_mul(p1))
 	}
 
-	fn opcode_in(&mut self) -> Option<Status> {
-		if self.input.is_empty() {
-			return Some(Status::WaitForInput);
-		} else {
-			let input = self.input.remove(0);
-			self.mem_write(self.next_ip(), input);
+	fn opcode_in(&mut self, intcode: i64) -> Result<Option<Status>, VmError> {
+		let pmodes = self.get_param_modes(intcode, 1)?;
+		// Check the target first so a fault doesn't lose the input
+		let addr = self.write_address(intcode, self.next_ip(), &pmodes[0])?;
+		let input = match self.read_input() {
+			Some(input) => input,
+			None => return Ok(Some(Status::WaitForInput)),
+		};
+		self.write_cell(intcode, addr, input)?;
+		return Ok(None);
+	}
+
+	// Takes the next input value from the queue, then the input device
+	fn read_input(&mut self) -> Option<i64> {
+		let input = match self.input.pop_front() {
+			Some(input) => input,
+			None => self
+				.input_device
+				.as_mut()
+				.and_then(|device| device.read_input())?,
+		};
+		if let Some(history) = self.history.as_mut() {
+			history.record_input(input);
 		}
-		return None;
+		return Some(input);
 	}
 
-	fn opcode_out(&mut self, intcode: i64) -> Status {
-		let pmodes = VM::get_param_modes(intcode, 1);
-		let p0 = self.mem_read(self.next_ip(), &pmodes[0]);
-		self.output.push(p0);
-		return Status::NewOutput;
+	fn opcode_out(&mut self, intcode: i64) -> Result<Status, VmError> {
+		let pmodes = self.get_param_modes(intcode, 1)?;
+		let p0 = self.mem_read(intcode, self.next_ip(), &pmodes[0])?;
+		self.write_output(p0);
+		return Ok(Status::NewOutput);
 	}
 
-	fn opcode_jit(&mut self, intcode: i64) {
-		let pmodes = VM::get_param_modes(intcode, 2);
-		let p0 = self.mem_read(self.next_ip(), &pmodes[0]);
-		let p1 = self.mem_read(self.next_ip(), &pmodes[1]);
+	fn write_output(&mut self, value: i64) {
+		match self.output_device.as_mut() {
+			Some(device) => device.write_output(value),
+			None => self.output.push_back(value),
+		}
+	}
+
+	fn opcode_jit(&mut self, intcode: i64) -> Result<(), VmError> {
+		let pmodes = self.get_param_modes(intcode, 2)?;
+		let p0 = self.mem_read(intcode, self.next_ip(), &pmodes[0])?;
+		let p1 = self.mem_read(intcode, self.next_ip(), &pmodes[1])?;
 		if p0 != 0 {
-			self.pc.set(p1 as usize);
+			self.pc.set(self.to_address(intcode, p1)?);
 		}
+		return Ok(());
 	}
 
-	fn opcode_jif(&mut self, intcode: i64) {
-		let pmodes = VM::get_param_modes(intcode, 2);
-		let p0 = self.mem_read(self.next_ip(), &pmodes[0]);
-		let p1 = self.mem_read(self.next_ip(), &pmodes[1]);
+	fn opcode_jif(&mut self, intcode: i64) -> Result<(), VmError> {
+		let pmodes = self.get_param_modes(intcode, 2)?;
+		let p0 = self.mem_read(intcode, self.next_ip(), &pmodes[0])?;
+		let p1 = self.mem_read(intcode, self.next_ip(), &pmodes[1])?;
 		if p0 == 0 {
-			self.pc.set(p1 as usize);
+			self.pc.set(self.to_address(intcode, p1)?);
 		}
+		return Ok(());
 	}
 
-	fn opcode_lt(&mut self, intcode: i64) {
-		let pmodes = VM::get_param_modes(intcode, 2);
-		let p0 = self.mem_read(self.next_ip(), &pmodes[0]);
-		let p1 = self.mem_read(self.next_ip(), &pmodes[1]);
+	fn opcode_lt(&mut self, intcode: i64) -> Result<(), VmError> {
+		let pmodes = self.get_param_modes(intcode, 3)?;
+		let p0 = self.mem_read(intcode, self.next_ip(), &pmodes[0])?;
+		let p1 = self.mem_read(intcode, self.next_ip(), &pmodes[1])?;
 		if p0 < p1 {
-			self.mem_write(self.next_ip(), 1);
+			self.mem_write(intcode, self.next_ip(), &pmodes[2], 1)
 		} else {
-			self.mem_write(self.next_ip(), 0);
+			self.mem_write(intcode, self.next_ip(), &pmodes[2], 0)
 		}
 	}
 
-	fn opcode_eq(&mut self, intcode: i64) {
-		let pmodes = VM::get_param_modes(intcode, 2);
-		let p0 = self.mem_read(self.next_ip(), &pmodes[0]);
-		let p1 = self.mem_read(self.next_ip(), &pmodes[1]);
+	fn opcode_eq(&mut self, intcode: i64) -> Result<(), VmError> {
+		let pmodes = self.get_param_modes(intcode, 3)?;
+		let p0 = self.mem_read(intcode, self.next_ip(), &pmodes[0])?;
+		let p1 = self.mem_read(intcode, self.next_ip(), &pmodes[1])?;
 		if p0 == p1 {
-			self.mem_write(self.next_ip(), 1);
+			self.mem_write(intcode, self.next_ip(), &pmodes[2], 1)
 		} else {
-			self.mem_write(self.next_ip(), 0);
+			self.mem_write(intcode, self.next_ip(), &pmodes[2], 0)
 		}
 	}
 
+	fn opcode_arb(&mut self, intcode: i64) -> Result<(), VmError> {
+		let pmodes = self.get_param_modes(intcode, 1)?;
+		let p0 = self.mem_read(intcode, self.next_ip(), &pmodes[0])?;
+		self.relative_base = self.relative_base.wrapping_add(p0);
+		return Ok(());
+	}
+
 	fn next_ip(&self) -> usize {
 		let aux = self.pc.get();
 		self.pc.set(self.pc.get() + 1);
 		return aux;
 	}
 
-	pub fn run_intcode(&mut self) -> Status {
-		loop {
-			let intcode: i64 = *self.ram.get(self.next_ip()).expect("Bad address");
-			let opcode = Opcode::from_i64(intcode % 100).expect("Bad opcode");
-			match opcode {
-				Opcode::ADD => {
-					self.opcode_add(intcode);
-				}
-				Opcode::MUL => {
-					self.opcode_mul(intcode);
-				}
-				Opcode::IN => match self.opcode_in() {
-					Some(result) => return result,
-					None => (),
-				},
-				Opcode::OUT => {
-					return self.opcode_out(intcode);
-				}
-				Opcode::JIT => {
-					self.opcode_jit(intcode);
-				}
-				Opcode::JIF => {
-					self.opcode_jif(intcode);
-				}
-				Opcode::LT => {
-					self.opcode_lt(intcode);
-				}
-				Opcode::EQ => {
-					self.opcode_eq(intcode);
+	fn execute(&mut self, intcode: i64) -> Result<Option<Status>, VmError> {
+		if let Some(result) = self.execute_decoded() {
+			return result;
+		}
+		let opcode = match Opcode::from_intcode(intcode) {
+			Some(opcode) => opcode,
+			None => {
+				let (pc, intcode) = self.fault(intcode);
+				return Err(VmError::UnknownOpcode { pc, intcode });
+			}
+		};
+		match opcode {
+			Opcode::ADD => self.opcode_add(intcode)?,
+			Opcode::MUL => self.opcode_mul(intcode)?,
+			Opcode::IN => return self.opcode_in(intcode),
+			Opcode::OUT => return self.opcode_out(intcode).map(Some),
+			Opcode::JIT => self.opcode_jit(intcode)?,
+			Opcode::JIF => self.opcode_jif(intcode)?,
+			Opcode::LT => self.opcode_lt(intcode)?,
+			Opcode::EQ => self.opcode_eq(intcode)?,
+			Opcode::ARB => self.opcode_arb(intcode)?,
+			Opcode::HALT => return Ok(Some(Status::Halt)),
+		}
+		return Ok(None);
+	}
+
+	// Executes a single instruction, returning a status if it produced one
+	pub fn step(&mut self) -> Result<Option<Status>, VmError> {
+		if !self.breakpoints.is_empty() {
+			if let Some(reason) = self.check_breakpoints() {
+				return Ok(Some(Status::Breakpoint(reason)));
+			}
+		}
+		let record = match self.tracer {
+			Some(_) => self.trace_begin(),
+			None => None,
+		};
+		self.instruction_pc = self.pc.get();
+		if let Some(history) = self.history.as_mut() {
+			history.begin(self.instruction_pc, self.relative_base);
+		}
+		let intcode: i64 = self.ram.read(self.next_ip());
+		match self.execute(intcode) {
+			Ok(Some(status)) if status == Status::WaitForInput || status == Status::Halt => {
+				// Stay on the instruction so that running again retries it
+				self.pc.set(self.instruction_pc);
+				return Ok(Some(status));
+			}
+			Ok(status) => {
+				self.instructions += 1;
+				self.breakpoints.resume = None;
+				if let Some(history) = self.history.as_mut() {
+					history.commit();
 				}
-				Opcode::HALT => {
-					return Status::Halt;
+				if let Some(record) = record {
+					self.trace_end(record);
 				}
+				return Ok(status);
+			}
+			Err(error) => {
+				// Leave the VM pointing at the faulting instruction
+				self.pc.set(self.instruction_pc);
+				self.breakpoints.resume = None;
+				return Err(error);
+			}
+		}
+	}
+
+	// Runs at most `budget` instructions
+	pub fn run_for(&mut self, budget: u64) -> Result<Status, VmError> {
+		for _ in 0..budget {
+			if let Some(status) = self.step()? {
+				return Ok(status);
+			}
+		}
+		return Ok(Status::BudgetExhausted);
+	}
+
+	pub fn run_intcode(&mut self) -> Result<Status, VmError> {
+		loop {
+			if let Some(status) = self.step()? {
+				return Ok(status);
+			}
+		}
+	}
+
+	// Takes every value currently sitting in the output queue
+	pub fn drain_output(&mut self) -> Vec<i64> {
+		return self.output.drain(..).collect();
+	}
+
+	// Queues `input` and runs until the program halts or needs more input
+	pub fn run_until_halt(&mut self, input: &[i64]) -> Result<RunResult, VmError> {
+		self.input.extend(input);
+		return self.run_until(None);
+	}
+
+	// Runs until `count` values have been output, or the program halts or needs input.
+	// Values sent to an attached output device still count but aren't collected.
+	pub fn run_until_outputs(&mut self, count: usize) -> Result<RunResult, VmError> {
+		return self.run_until(Some(count));
+	}
+
+	// Runs until the program halts or needs input, collecting everything it outputs
+	pub fn run_until_input(&mut self) -> Result<RunResult, VmError> {
+		return self.run_until(None);
+	}
+
+	fn run_until(&mut self, max_outputs: Option<usize>) -> Result<RunResult, VmError> {
+		let mut output = self.drain_output();
+		let mut count = output.len();
+		loop {
+			if max_outputs.is_some_and(|max| count >= max) {
+				return Ok(RunResult {
+					status: Status::NewOutput,
+					output,
+				});
+			}
+			let status = self.run_intcode()?;
+			output.extend(self.output.drain(..));
+			if status != Status::NewOutput {
+				return Ok(RunResult { status, output });
 			}
+			count += 1;
 		}
 	}
 }
 
+impl Engine for VM {
+	fn from_memory(memory: &[i64]) -> Self {
+		return VM::from_memory(memory);
+	}
+
+	fn reset(&mut self, memory: &[i64]) {
+		VM::reset(self, memory);
+	}
+
+	fn queue_input(&mut self, value: i64) {
+		VM::queue_input(self, value);
+	}
+
+	fn input(&self) -> &VecDeque<i64> {
+		return VM::input(self);
+	}
+
+	fn output(&self) -> &VecDeque<i64> {
+		return VM::output(self);
+	}
+
+	fn drain_output(&mut self) -> Vec<i64> {
+		return VM::drain_output(self);
+	}
+
+	fn memory(&self) -> &Memory {
+		return VM::memory(self);
+	}
+
+	fn pc(&self) -> usize {
+		return VM::pc(self);
+	}
+
+	fn relative_base(&self) -> i64 {
+		return VM::relative_base(self);
+	}
+
+	fn instruction_count(&self) -> u64 {
+		return VM::instruction_count(self);
+	}
+
+	fn step(&mut self) -> Result<Option<Status>, VmError> {
+		return VM::step(self);
+	}
+
+	fn run_for(&mut self, budget: u64) -> Result<Status, VmError> {
+		return VM::run_for(self, budget);
+	}
+
+	fn run_intcode(&mut self) -> Result<Status, VmError> {
+		return VM::run_intcode(self);
+	}
+
+	fn run_until_halt(&mut self, input: &[i64]) -> Result<RunResult, VmError> {
+		return VM::run_until_halt(self, input);
+	}
+
+	fn run_until_outputs(&mut self, count: usize) -> Result<RunResult, VmError> {
+		return VM::run_until_outputs(self, count);
+	}
+
+	fn run_until_input(&mut self) -> Result<RunResult, VmError> {
+		return VM::run_until_input(self);
+	}
+}
+
 #[cfg(test)]
 mod tests {
+	use super::*;
 
 	/*
 		The example program below uses an input instruction to ask for a single number.
@@ -226,15 +745,219 @@ mod tests {
 	*/
 	#[test]
 	fn it_works() {
-		let mut vm = VM::from_memory(
-			[
-				3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36,
-				98, 0, 0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000,
-				1, 20, 4, 20, 1105, 1, 46, 98, 99,
-			]
-			.to_vec(),
+		let mut vm = VM::from_memory(&[
+			3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0,
+			0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4,
+			20, 1105, 1, 46, 98, 99,
+		]);
+		vm.queue_input(5);
+		assert_eq!(vm.run_intcode(), Ok(Status::NewOutput));
+		assert_eq!(vm.output, [999]);
+	}
+
+	#[test]
+	fn large_numbers() {
+		let mut vm = VM::from_memory(&[1102, 34915192, 34915192, 7, 4, 7, 99, 0]);
+		assert_eq!(vm.run_intcode(), Ok(Status::NewOutput));
+		assert_eq!(vm.output, [1219070632396864]);
+
+		let mut vm = VM::from_memory(&[104, 1125899906842624, 99]);
+		assert_eq!(vm.run_intcode(), Ok(Status::NewOutput));
+		assert_eq!(vm.output, [1125899906842624]);
+	}
+
+	#[test]
+	fn overflow_wraps() {
+		let mut vm = VM::from_memory(&[1101, 9223372036854775807, 1, 0, 99]);
+		assert_eq!(vm.run_intcode(), Ok(Status::Halt));
+		assert_eq!(vm.memory().read(0), i64::MIN);
+
+		// The relative base wraps round to a negative address
+		let mut vm = VM::from_memory(&[109, 9223372036854775807, 209, 1, 99]);
+		assert_eq!(
+			vm.run_intcode(),
+			Err(VmError::NegativeAddress {
+				pc: 2,
+				intcode: 209,
+				address: i64::MIN
+			})
+		);
+		assert_eq!(vm.relative_base(), i64::MAX);
+	}
+
+	#[test]
+	fn relative_base() {
+		// Move the relative base to 5, then read an input into rb+2 and echo it back
+		let mut vm = VM::from_memory(&[109, 5, 203, 2, 204, 2, 99, 0, 0, 0]);
+		vm.queue_input(42);
+		assert_eq!(vm.run_intcode(), Ok(Status::NewOutput));
+		assert_eq!(vm.output, [42]);
+		assert_eq!(vm.relative_base(), 5);
+		assert_eq!(vm.run_intcode(), Ok(Status::Halt));
+
+		vm.reset(&[99]);
+		assert_eq!(vm.relative_base(), 0);
+	}
+
+	#[test]
+	fn memory_past_program() {
+		// Quine: outputs a copy of itself, using memory past the end of the program
+		let program = [
+			109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+		];
+		let mut vm = VM::from_memory(&program);
+		while vm.run_intcode() != Ok(Status::Halt) {}
+		assert_eq!(vm.output, program);
+		assert_eq!(vm.memory().read(100), 16);
+	}
+
+	#[test]
+	fn errors() {
+		let mut vm = VM::from_memory(&[1, 0, 0, 0, 42]);
+		assert_eq!(
+			vm.run_intcode(),
+			Err(VmError::UnknownOpcode { pc: 4, intcode: 42 })
+		);
+
+		let mut vm = VM::from_memory(&[301, 0, 0, 0, 99]);
+		assert_eq!(
+			vm.run_intcode(),
+			Err(VmError::InvalidMode {
+				pc: 0,
+				intcode: 301,
+				mode: 3
+			})
+		);
+
+		let mut vm = VM::from_memory(&[1, -1, 0, 0, 99]);
+		assert_eq!(
+			vm.run_intcode(),
+			Err(VmError::NegativeAddress {
+				pc: 0,
+				intcode: 1,
+				address: -1
+			})
+		);
+
+		let mut vm = VM::from_memory(&[11101, 1, 1, 0, 99]);
+		assert_eq!(
+			vm.run_intcode(),
+			Err(VmError::ImmediateWrite {
+				pc: 0,
+				intcode: 11101
+			})
 		);
+
+		// The input is still queued after a faulting read
+		let mut vm = VM::from_memory(&[103, 0, 99]);
 		vm.queue_input(5);
-		vm.run_intcode();
+		assert_eq!(
+			vm.run_intcode(),
+			Err(VmError::ImmediateWrite {
+				pc: 0,
+				intcode: 103
+			})
+		);
+		assert_eq!(vm.input, [5]);
+
+		let mut vm = VM::from_memory(&[1101, 1, 1, 100, 99]);
+		vm.set_memory_limit(50);
+		let error = vm.run_intcode().unwrap_err();
+		assert_eq!(
+			error,
+			VmError::MemoryCapExceeded {
+				pc: 0,
+				intcode: 1101,
+				address: 100,
+				limit: 50
+			}
+		);
+		assert_eq!(
+			error.to_string(),
+			"memory cap of 50 cells exceeded writing to 100 (instruction 1101 at pc 0)"
+		);
+	}
+
+	#[test]
+	fn step_and_budget() {
+		// Count the cell at address 9 down from 3 to 0, then halt
+		let mut vm = VM::from_memory(&[101, -1, 9, 9, 1005, 9, 0, 99, 0, 3]);
+		assert_eq!(vm.step(), Ok(None));
+		assert_eq!(vm.pc(), 4);
+		assert_eq!(vm.instruction_count(), 1);
+
+		assert_eq!(vm.run_for(3), Ok(Status::BudgetExhausted));
+		assert_eq!(vm.instruction_count(), 4);
+		assert_eq!(vm.run_intcode(), Ok(Status::Halt));
+		assert_eq!(vm.instruction_count(), 6);
+		assert_eq!(vm.pc(), 7);
+
+		// Halting again is idempotent
+		assert_eq!(vm.step(), Ok(Some(Status::Halt)));
+		assert_eq!(vm.instruction_count(), 6);
+	}
+
+	#[test]
+	fn wait_for_input_retries() {
+		let mut vm = VM::from_memory(&[3, 5, 4, 5, 99, 0]);
+		assert_eq!(vm.run_intcode(), Ok(Status::WaitForInput));
+		assert_eq!(vm.pc(), 0);
+		assert_eq!(vm.run_intcode(), Ok(Status::WaitForInput));
+		vm.queue_input(7);
+		assert_eq!(vm.run_intcode(), Ok(Status::NewOutput));
+		assert_eq!(vm.output, [7]);
+	}
+
+	#[test]
+	fn run_helpers() {
+		// Outputs its two inputs doubled, then halts
+		let mut vm = VM::from_memory(&[
+			3, 17, 102, 2, 17, 17, 4, 17, 3, 17, 102, 2, 17, 17, 4, 17, 99,
+		]);
+
+		let result = vm.run_until_input().unwrap();
+		assert_eq!(result.status, Status::WaitForInput);
+		assert!(result.output.is_empty());
+
+		vm.queue_input(3);
+		let result = vm.run_until_outputs(1).unwrap();
+		assert_eq!(
+			result,
+			RunResult {
+				status: Status::NewOutput,
+				output: vec![6]
+			}
+		);
+
+		let result = vm.run_until_halt(&[5]).unwrap();
+		assert!(result.halted());
+		assert_eq!(result.last_output(), Some(10));
+		assert!(vm.drain_output().is_empty());
+	}
+
+	#[test]
+	fn io_devices() {
+		// Adds pairs of inputs together until the input runs out
+		let mut vm = VM::from_memory(&[3, 13, 3, 14, 1, 13, 14, 15, 4, 15, 1105, 1, 0]);
+		let mut values = vec![1, 2, 3, 4].into_iter();
+		vm.attach_input(io::FnInput(move || values.next()));
+		let (tx, rx) = std::sync::mpsc::channel();
+		vm.attach_output(tx);
+		let result = vm.run_until_input().unwrap();
+		assert_eq!(result.status, Status::WaitForInput);
+		assert!(result.output.is_empty());
+		assert_eq!(rx.try_iter().collect::<Vec<_>>(), [3, 7]);
+
+		// Chain two doublers together through a shared queue
+		let doubler = [3, 9, 102, 2, 9, 9, 4, 9, 99];
+		let queue = io::shared_queue();
+		let mut first = VM::from_memory(&doubler);
+		first.queue_input(5);
+		first.attach_output(queue.clone());
+		let mut second = VM::from_memory(&doubler);
+		second.attach_input(queue);
+		assert_eq!(second.run_intcode(), Ok(Status::WaitForInput));
+		assert_eq!(first.run_intcode(), Ok(Status::NewOutput));
+		assert_eq!(second.run_until_halt(&[]).unwrap().output, [20]);
 	}
 }
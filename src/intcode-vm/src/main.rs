@@ -2,10 +2,313 @@
 	--- Intcode VM ---
 */
 
-fn main() {
-	println!(read_input(0))
+#![allow(clippy::needless_return)]
+
+use intcode_vm::analysis::Cfg;
+use intcode_vm::debugger::Session;
+use intcode_vm::io::{IoMode, StdinInput, StdoutOutput};
+use intcode_vm::trace::JsonLinesTracer;
+use intcode_vm::{ascii, asm, disasm, repl, snapshot, Snapshot, Status, VmError, VM};
+use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::process;
+
+const USAGE: &str = "Usage: intcode-vm [run] [OPTIONS] <PROGRAM> [INPUT]...
+       intcode-vm disasm [--follow] [--start <ADDR>] <PROGRAM>
+       intcode-vm cfg [--start <ADDR>] <PROGRAM>
+       intcode-vm asm <SOURCE>
+       intcode-vm debug [--batch] [--command <FILE>]... <PROGRAM>
+
+Runs an Intcode program. Inputs are taken from the arguments if any are given,
+otherwise from stdin. PROGRAM may also be a snapshot written by --save, which
+resumes the saved machine. cfg prints the control-flow graph of the code
+reachable from the start address in Graphviz DOT format.
+
+Run options:
+  -a, --ascii               Use ASCII I/O, each INPUT argument is sent as a line
+  -b, --budget <N>          Stop after executing N instructions
+  -l, --memory-limit <N>    Allow the program to allocate at most N memory cells
+  -m, --memory              Print the final memory, including sparse pages
+  -p, --peek <ADDR>[,...]   Print the final value at each ADDR
+  -S, --save <FILE>         Save the machine state to FILE when it stops, even on an error
+  -t, --trace <FILE>        Write a JSON Lines trace of every instruction to FILE
+  -h, --help                Print this message
+
+Disassembler options:
+  -f, --follow              Follow jumps from the start address to separate code from data
+  -s, --start <ADDR>        Start disassembling at ADDR instead of 0
+
+Debugger options:
+  -x, --command <FILE>      Run the debugger commands in FILE first, then read from stdin
+  -B, --batch               Exit after running the command files instead of reading stdin";
+
+// Exit codes, other than 0 for a halted program
+const EXIT_ERROR: i32 = 1; // Bad arguments, unreadable program or a VM error
+const EXIT_NO_INPUT: i32 = 2; // The program wanted input after the input ran out
+const EXIT_BUDGET: i32 = 3; // The instruction budget ran out
+
+struct Options {
+	mode: IoMode,
+	budget: Option<u64>,
+	memory_limit: Option<usize>,
+	print_memory: bool,
+	peek: Vec<usize>,
+	trace: Option<String>,
+	save: Option<String>,
+	program: String,
+	inputs: Vec<String>,
+}
+
+fn fail(message: &str) -> ! {
+	eprintln!("error: {}", message);
+	process::exit(EXIT_ERROR);
+}
+
+fn parse_value<T: std::str::FromStr>(option: &str, value: Option<String>) -> T {
+	let value = value.unwrap_or_else(|| fail(&format!("{} expects a value", option)));
+	return value
+		.parse::<T>()
+		.unwrap_or_else(|_| fail(&format!("invalid value '{}' for {}", value, option)));
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Options {
+	let mut options = Options {
+		mode: IoMode::Numeric,
+		budget: None,
+		memory_limit: None,
+		print_memory: false,
+		peek: Vec::new(),
+		trace: None,
+		save: None,
+		program: String::new(),
+		inputs: Vec::new(),
+	};
+	let mut program = None;
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"-h" | "--help" => {
+				println!("{}", USAGE);
+				process::exit(0);
+			}
+			"-a" | "--ascii" => options.mode = IoMode::Ascii,
+			"-b" | "--budget" => options.budget = Some(parse_value(&arg, args.next())),
+			"-l" | "--memory-limit" => options.memory_limit = Some(parse_value(&arg, args.next())),
+			"-m" | "--memory" => options.print_memory = true,
+			"-t" | "--trace" => options.trace = Some(parse_value(&arg, args.next())),
+			"-S" | "--save" => options.save = Some(parse_value(&arg, args.next())),
+			"-p" | "--peek" => {
+				let list: String = parse_value(&arg, args.next());
+				for address in list.split(',') {
+					options
+						.peek
+						.push(parse_value(&arg, Some(address.trim().to_string())));
+				}
+			}
+			// Anything else that looks like an option, but not a negative number
+			_ if arg.starts_with('-') && arg.parse::<i64>().is_err() && program.is_none() => {
+				fail(&format!("unknown option '{}'\n\n{}", arg, USAGE))
+			}
+			_ if program.is_none() => program = Some(arg),
+			_ => options.inputs.push(arg),
+		}
+	}
+	options.program = program.unwrap_or_else(|| fail(&format!("no program given\n\n{}", USAGE)));
+	return options;
+}
+
+fn load_program(path: &str) -> Vec<i64> {
+	return intcode_vm::load_program(path)
+		.unwrap_or_else(|e| fail(&format!("could not load {}: {}", path, e)));
+}
+
+// Loads a program, or the machine saved in a snapshot
+fn load_vm(path: &str) -> VM {
+	let source = std::fs::read_to_string(path)
+		.unwrap_or_else(|e| fail(&format!("could not load {}: {}", path, e)));
+	if snapshot::is_snapshot(&source) {
+		let snapshot = Snapshot::parse(&source)
+			.unwrap_or_else(|e| fail(&format!("could not load {}: {}", path, e)));
+		return VM::from_snapshot(&snapshot);
+	}
+	let program = intcode_vm::parse_program(&source)
+		.unwrap_or_else(|e| fail(&format!("could not load {}: {}", path, e)));
+	return VM::from_memory(&program);
 }
 
-fn read_input(day: usize) -> String {
-	return std::fs::read_to_string(format!("../../input/{}/day{:0>2}.txt", day)).unwrap();
+fn run(vm: &mut VM, budget: Option<u64>) -> Result<Status, VmError> {
+	let mut remaining = budget;
+	loop {
+		let status = match remaining {
+			Some(budget) => {
+				let start = vm.instruction_count();
+				let status = vm.run_for(budget)?;
+				remaining = Some(budget - (vm.instruction_count() - start));
+				status
+			}
+			None => vm.run_intcode()?,
+		};
+		if status != Status::NewOutput {
+			return Ok(status);
+		}
+	}
+}
+
+fn disasm_main<I: Iterator<Item = String>>(mut args: I) {
+	let mut follow = false;
+	let mut start = 0;
+	let mut path = None;
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"-f" | "--follow" => follow = true,
+			"-s" | "--start" => start = parse_value(&arg, args.next()),
+			_ if arg.starts_with('-') => fail(&format!("unknown option '{}'\n\n{}", arg, USAGE)),
+			_ if path.is_none() => path = Some(arg),
+			_ => fail(&format!("unexpected argument '{}'", arg)),
+		}
+	}
+	let path = path.unwrap_or_else(|| fail(&format!("no program given\n\n{}", USAGE)));
+	let program = load_program(&path);
+
+	let lines = if follow {
+		disasm::disassemble_reachable(&program, start)
+	} else {
+		disasm::disassemble(&program, start)
+	};
+	// Ignore errors so that piping into e.g. `head` doesn't panic
+	let _ = io::stdout().write_all(disasm::format_listing(&lines).as_bytes());
+}
+
+fn cfg_main<I: Iterator<Item = String>>(mut args: I) {
+	let mut start = 0;
+	let mut path = None;
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"-s" | "--start" => start = parse_value(&arg, args.next()),
+			_ if arg.starts_with('-') => fail(&format!("unknown option '{}'\n\n{}", arg, USAGE)),
+			_ if path.is_none() => path = Some(arg),
+			_ => fail(&format!("unexpected argument '{}'", arg)),
+		}
+	}
+	let path = path.unwrap_or_else(|| fail(&format!("no program given\n\n{}", USAGE)));
+	let program = load_program(&path);
+	let _ = io::stdout().write_all(Cfg::build(&program, start).to_dot().as_bytes());
+}
+
+fn asm_main<I: Iterator<Item = String>>(mut args: I) {
+	let path = match (args.next(), args.next()) {
+		(Some(path), None) if !path.starts_with('-') => path,
+		_ => fail(&format!("expected a single source file\n\n{}", USAGE)),
+	};
+	let source = std::fs::read_to_string(&path)
+		.unwrap_or_else(|e| fail(&format!("could not read {}: {}", path, e)));
+	let program = asm::assemble(&source).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+	println!("{}", asm::format_program(&program));
+}
+
+fn debug_main<I: Iterator<Item = String>>(mut args: I) {
+	let mut scripts = Vec::new();
+	let mut batch = false;
+	let mut path = None;
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"-x" | "--command" => scripts.push(parse_value::<String>(&arg, args.next())),
+			"-B" | "--batch" => batch = true,
+			_ if arg.starts_with('-') => fail(&format!("unknown option '{}'\n\n{}", arg, USAGE)),
+			_ if path.is_none() => path = Some(arg),
+			_ => fail(&format!("unexpected argument '{}'", arg)),
+		}
+	}
+	let path = path.unwrap_or_else(|| fail(&format!("no program given\n\n{}", USAGE)));
+	let mut session = Session::attach(load_vm(&path));
+
+	let stdout = io::stdout();
+	for script in &scripts {
+		let source = std::fs::read_to_string(script)
+			.unwrap_or_else(|e| fail(&format!("could not read {}: {}", script, e)));
+		match repl::run_script(&mut session, &source, stdout.lock()) {
+			Ok(true) => (),
+			Ok(false) => return,
+			Err(e) => fail(&format!("{}: {}", script, e)),
+		}
+	}
+	if !batch {
+		let stdin = io::stdin();
+		repl::run_interactive(&mut session, stdin.lock(), stdout.lock());
+	}
+}
+
+fn run_main<I: Iterator<Item = String>>(args: I) {
+	let options = parse_args(args);
+	let mut vm = load_vm(&options.program);
+	if let Some(limit) = options.memory_limit {
+		vm.set_memory_limit(limit);
+	}
+	if options.inputs.is_empty() {
+		vm.attach_input(StdinInput::new(options.mode));
+	}
+	for input in &options.inputs {
+		match options.mode {
+			IoMode::Numeric => vm.queue_input(parse_value("INPUT", Some(input.clone()))),
+			IoMode::Ascii => ascii::encode_line(input)
+				.into_iter()
+				.for_each(|value| vm.queue_input(value)),
+		}
+	}
+	vm.attach_output(StdoutOutput::new(options.mode));
+	if let Some(path) = &options.trace {
+		let file = File::create(path)
+			.unwrap_or_else(|e| fail(&format!("could not create {}: {}", path, e)));
+		vm.set_tracer(JsonLinesTracer::new(BufWriter::new(file)));
+	}
+
+	let status = run(&mut vm, options.budget);
+	drop(vm.take_tracer()); // Flush the trace, even if the program failed
+	if let Some(path) = &options.save {
+		vm.snapshot()
+			.save(path)
+			.unwrap_or_else(|e| fail(&format!("could not save {}: {}", path, e)));
+	}
+	let status = status.unwrap_or_else(|e| fail(&e.to_string()));
+
+	if options.print_memory {
+		for (start, cells) in vm.memory().regions() {
+			let cells: Vec<String> = cells.iter().map(|x| x.to_string()).collect();
+			match start {
+				0 => println!("{}", cells.join(",")),
+				_ => println!("[{}..{}] = {}", start, start + cells.len(), cells.join(",")),
+			}
+		}
+	}
+	for &address in &options.peek {
+		println!("[{}] = {}", address, vm.memory().read(address));
+	}
+
+	match status {
+		Status::WaitForInput => {
+			eprintln!("error: program is waiting for input at pc {}", vm.pc());
+			process::exit(EXIT_NO_INPUT);
+		}
+		Status::BudgetExhausted => {
+			eprintln!(
+				"error: instruction budget exhausted after {} instructions at pc {}",
+				vm.instruction_count(),
+				vm.pc()
+			);
+			process::exit(EXIT_BUDGET);
+		}
+		_ => (),
+	}
+}
+
+fn main() {
+	let mut args = env::args().skip(1).peekable();
+	match args.peek().map(String::as_str) {
+		Some("disasm") => disasm_main(args.skip(1)),
+		Some("cfg") => cfg_main(args.skip(1)),
+		Some("asm") => asm_main(args.skip(1)),
+		Some("debug") => debug_main(args.skip(1)),
+		Some("run") => run_main(args.skip(1)),
+		_ => run_main(args),
+	}
 }
@@ -0,0 +1,196 @@
+/*
+	--- Intcode VM: memory ---
+*/
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub const PAGE_SIZE: usize = 1024; // Number of cells in a sparse page
+pub const DENSE_WINDOW: usize = 64 * 1024; // Addresses below this are stored contiguously
+pub const DEFAULT_MEMORY_LIMIT: usize = 16 * 1024 * 1024; // Maximum number of allocated cells
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryLimitExceeded {
+	pub address: usize, // Address of the write that needed more memory
+	pub limit: usize,   // Configured cap, in cells
+}
+
+// Zero-initialised memory that grows on demand. Low addresses live in a
+// contiguous vector, anything past `dense_end` is paged in sparsely so that a
+// program poking at e.g. address 2^40 doesn't try to allocate terabytes.
+// Storage is shared copy-on-write, so cloning is cheap and a clone only
+// copies the regions it goes on to modify.
+#[derive(Clone, Debug)]
+pub struct Memory {
+	dense: Arc<Vec<i64>>, // Contiguous memory starting at address 0
+	dense_end: usize,     // First address handled by the sparse pages
+	pages: HashMap<usize, Arc<[i64; PAGE_SIZE]>>, // Sparse pages keyed by page number
+	limit: usize,         // Hard cap on allocated cells
+}
+
+impl Memory {
+	pub fn new() -> Self {
+		Memory::from_slice(&[])
+	}
+
+	pub fn from_slice(program: &[i64]) -> Self {
+		Memory {
+			dense: Arc::new(program.to_vec()),
+			dense_end: program.len().max(DENSE_WINDOW),
+			pages: HashMap::new(),
+			limit: DEFAULT_MEMORY_LIMIT.max(program.len()),
+		}
+	}
+
+	pub fn limit(&self) -> usize {
+		return self.limit;
+	}
+
+	pub fn set_limit(&mut self, limit: usize) {
+		self.limit = limit;
+	}
+
+	// Number of cells currently backed by real storage
+	pub fn allocated(&self) -> usize {
+		return self.dense.len() + self.pages.len() * PAGE_SIZE;
+	}
+
+	// The contiguous part of memory, which always contains the loaded program
+	pub fn dense(&self) -> &[i64] {
+		return &self.dense;
+	}
+
+	pub fn read(&self, address: usize) -> i64 {
+		if address < self.dense.len() {
+			return self.dense[address];
+		}
+		if address < self.dense_end {
+			return 0;
+		}
+		match self.pages.get(&(address / PAGE_SIZE)) {
+			Some(page) => page[address % PAGE_SIZE],
+			None => 0,
+		}
+	}
+
+	pub fn write(&mut self, address: usize, value: i64) -> Result<(), MemoryLimitExceeded> {
+		if address < self.dense.len() {
+			Arc::make_mut(&mut self.dense)[address] = value;
+			return Ok(());
+		}
+
+		if address < self.dense_end {
+			// Grow the contiguous region up to and including the address
+			if address + 1 + self.pages.len() * PAGE_SIZE > self.limit {
+				return Err(self.exceeded(address));
+			}
+			let dense = Arc::make_mut(&mut self.dense);
+			dense.resize(address + 1, 0);
+			dense[address] = value;
+			return Ok(());
+		}
+
+		let page_number = address / PAGE_SIZE;
+		if !self.pages.contains_key(&page_number) {
+			if value == 0 {
+				// Unallocated memory already reads as zero
+				return Ok(());
+			}
+			if self.allocated() + PAGE_SIZE > self.limit {
+				return Err(self.exceeded(address));
+			}
+			self.pages.insert(page_number, Arc::new([0; PAGE_SIZE]));
+		}
+		let page = self
+			.pages
+			.get_mut(&page_number)
+			.expect("Page was just allocated");
+		Arc::make_mut(page)[address % PAGE_SIZE] = value;
+		return Ok(());
+	}
+
+	// Every allocated region as (start address, cells), in address order
+	pub fn regions(&self) -> Vec<(usize, &[i64])> {
+		let mut regions = vec![(0, &self.dense[..])];
+		let mut pages: Vec<_> = self.pages.iter().collect();
+		pages.sort_unstable_by_key(|&(number, _)| *number);
+		for (number, page) in pages {
+			regions.push((number * PAGE_SIZE, &page[..]));
+		}
+		return regions;
+	}
+
+	fn exceeded(&self, address: usize) -> MemoryLimitExceeded {
+		MemoryLimitExceeded {
+			address,
+			limit: self.limit,
+		}
+	}
+}
+
+impl Default for Memory {
+	fn default() -> Self {
+		Memory::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn reads_default_to_zero() {
+		let memory = Memory::from_slice(&[1, 2, 3]);
+		assert_eq!(memory.read(2), 3);
+		assert_eq!(memory.read(3), 0);
+		assert_eq!(memory.read(DENSE_WINDOW * 10), 0);
+		assert_eq!(memory.allocated(), 3);
+	}
+
+	#[test]
+	fn writes_grow_dense_and_sparse_regions() {
+		let mut memory = Memory::from_slice(&[1, 2, 3]);
+		memory.write(100, 7).unwrap();
+		assert_eq!(memory.dense().len(), 101);
+		assert_eq!(memory.read(100), 7);
+
+		let far = 1 << 40;
+		memory.write(far, -5).unwrap();
+		assert_eq!(memory.read(far), -5);
+		assert_eq!(memory.read(far + 1), 0);
+		assert_eq!(memory.allocated(), 101 + PAGE_SIZE);
+	}
+
+	#[test]
+	fn limit_is_enforced() {
+		let mut memory = Memory::from_slice(&[0; 10]);
+		memory.set_limit(16);
+		assert!(memory.write(15, 1).is_ok());
+		assert_eq!(
+			memory.write(16, 1),
+			Err(MemoryLimitExceeded {
+				address: 16,
+				limit: 16
+			})
+		);
+		assert!(memory.write(DENSE_WINDOW, 1).is_err());
+		// Writing zero to an unallocated page never needs storage
+		assert!(memory.write(DENSE_WINDOW, 0).is_ok());
+	}
+
+	#[test]
+	fn clones_are_independent() {
+		let mut memory = Memory::from_slice(&[1, 2, 3]);
+		memory.write(DENSE_WINDOW, 4).unwrap();
+		let mut copy = memory.clone();
+		copy.write(0, 10).unwrap();
+		copy.write(DENSE_WINDOW, 40).unwrap();
+		assert_eq!((memory.read(0), memory.read(DENSE_WINDOW)), (1, 4));
+		assert_eq!((copy.read(0), copy.read(DENSE_WINDOW)), (10, 40));
+
+		let regions = copy.regions();
+		assert_eq!(regions.len(), 2);
+		assert_eq!(regions[0], (0, &[10, 2, 3][..]));
+		assert_eq!(regions[1].0, DENSE_WINDOW);
+	}
+}
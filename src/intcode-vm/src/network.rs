@@ -0,0 +1,422 @@
+/*
+	--- Intcode VM: networks ---
+
+	A Network owns several VMs and takes turns running them, delivering what
+	each machine outputs to the inputs of the others:
+
+		Pipeline      machine n feeds machine n + 1, the last one's output leaves
+		Ring          the same, but the last machine feeds the first
+		PacketSwitch  outputs are `address, values...` packets for that machine
+
+	Anything sent to an address outside the network ends up in the outbox.
+	When nothing is left that could make progress, e.g. every machine waits
+	for input, the network is idle and hands control back to the caller, who
+	can inject more input (the day 23 NAT) or give up.
+
+	Machines run on the interpreter by default, or on any other Engine, e.g.
+	`Network::<CompiledVM>::with_engine(Topology::Ring)`.
+*/
+
+use crate::{Engine, Status, VmError, VM};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+// Instructions each machine runs per turn
+pub const DEFAULT_TIME_SLICE: u64 = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Topology {
+	Pipeline,
+	Ring,
+	PacketSwitch { packet_size: usize }, // Values per packet, including the address
+}
+
+// Values sent from one machine to an address
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+	pub from: usize,
+	pub to: i64, // A machine index, or somewhere outside the network
+	pub values: Vec<i64>,
+}
+
+// Why run() handed control back
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NetworkStatus {
+	Output, // A message left the network, see take_output()
+	Idle,   // Nothing can happen without more input
+	Halted, // Every machine halted
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NetworkError {
+	pub machine: usize,
+	pub error: VmError,
+}
+
+impl fmt::Display for NetworkError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "machine {}: {}", self.machine, self.error)
+	}
+}
+
+impl Error for NetworkError {}
+
+// Sees every message routed through a network
+pub trait Observer {
+	fn message(&mut self, message: &Message);
+}
+
+// Lets an observer be shared, so it can be inspected while attached
+impl<T: Observer + ?Sized> Observer for Arc<Mutex<T>> {
+	fn message(&mut self, message: &Message) {
+		self.lock().expect("Poisoned observer").message(message);
+	}
+}
+
+// Records all traffic
+impl Observer for Vec<Message> {
+	fn message(&mut self, message: &Message) {
+		self.push(message.clone());
+	}
+}
+
+struct Machine<E> {
+	vm: E,
+	status: Option<Status>, // How its last turn ended
+	packet: Vec<i64>,       // Start of a packet still being output
+	last_output: Option<i64>,
+}
+
+pub struct Network<E: Engine = VM> {
+	machines: Vec<Machine<E>>,
+	topology: Topology,
+	idle_input: Option<i64>, // Fed to a machine with no input instead of waiting
+	time_slice: u64,
+	next: usize,        // Machine whose turn is next
+	quiet_turns: usize, // Consecutive turns in which nothing happened
+	outbox: VecDeque<Message>,
+	observers: Vec<Box<dyn Observer + Send>>,
+}
+
+impl Network {
+	pub fn new(topology: Topology) -> Self {
+		return Network::with_engine(topology);
+	}
+
+	// `count` machines running copies of the same program
+	pub fn from_program(program: &[i64], count: usize, topology: Topology) -> Self {
+		return Network::from_program_with_engine(program, count, topology);
+	}
+}
+
+impl<E: Engine> Network<E> {
+	// An empty network whose machines run on `E`
+	pub fn with_engine(topology: Topology) -> Self {
+		Network {
+			machines: Vec::new(),
+			topology,
+			idle_input: None,
+			time_slice: DEFAULT_TIME_SLICE,
+			next: 0,
+			quiet_turns: 0,
+			outbox: VecDeque::new(),
+			observers: Vec::new(),
+		}
+	}
+
+	pub fn from_program_with_engine(program: &[i64], count: usize, topology: Topology) -> Self {
+		let mut network = Network::with_engine(topology);
+		for _ in 0..count {
+			network.add(E::from_memory(program));
+		}
+		return network;
+	}
+
+	// Adds a machine, returning its address
+	pub fn add(&mut self, vm: E) -> usize {
+		self.machines.push(Machine {
+			vm,
+			status: None,
+			packet: Vec::new(),
+			last_output: None,
+		});
+		return self.machines.len() - 1;
+	}
+
+	pub fn len(&self) -> usize {
+		return self.machines.len();
+	}
+
+	pub fn is_empty(&self) -> bool {
+		return self.machines.is_empty();
+	}
+
+	pub fn vm(&self, machine: usize) -> &E {
+		return &self.machines[machine].vm;
+	}
+
+	pub fn vm_mut(&mut self, machine: usize) -> &mut E {
+		return &mut self.machines[machine].vm;
+	}
+
+	// The most recent value a machine output
+	pub fn last_output(&self, machine: usize) -> Option<i64> {
+		return self.machines[machine].last_output;
+	}
+
+	// Instead of waiting, machines with no input read `value`, as the day 23
+	// computers read -1
+	pub fn set_idle_input(&mut self, value: Option<i64>) {
+		self.idle_input = value;
+	}
+
+	pub fn set_time_slice(&mut self, instructions: u64) {
+		self.time_slice = instructions.max(1);
+	}
+
+	pub fn observe<T: Observer + Send + 'static>(&mut self, observer: T) {
+		self.observers.push(Box::new(observer));
+	}
+
+	pub fn queue_input(&mut self, machine: usize, value: i64) {
+		self.machines[machine].vm.queue_input(value);
+		self.quiet_turns = 0;
+	}
+
+	// Sends values into the network from outside, e.g. from a NAT. Observers
+	// see the message as coming from an address past the last machine.
+	pub fn send(&mut self, to: i64, values: &[i64]) {
+		let message = Message {
+			from: self.machines.len(),
+			to,
+			values: values.to_vec(),
+		};
+		self.deliver(message);
+	}
+
+	// The oldest message that left the network
+	pub fn take_output(&mut self) -> Option<Message> {
+		return self.outbox.pop_front();
+	}
+
+	// Runs machines in turn until a message leaves the network, or until every
+	// machine has halted or is idle
+	pub fn run(&mut self) -> Result<NetworkStatus, NetworkError> {
+		let outbox = self.outbox.len();
+		loop {
+			if self
+				.machines
+				.iter()
+				.all(|machine| machine.status == Some(Status::Halt))
+			{
+				return Ok(NetworkStatus::Halted);
+			}
+			if self.quiet_turns >= self.machines.len() {
+				return Ok(NetworkStatus::Idle);
+			}
+			let machine = self.next;
+			self.next = (self.next + 1) % self.machines.len();
+			if self.turn(machine)? {
+				self.quiet_turns = 0;
+			} else {
+				self.quiet_turns += 1;
+			}
+			if self.outbox.len() > outbox {
+				return Ok(NetworkStatus::Output);
+			}
+		}
+	}
+
+	// Keeps running through any output, until every machine has halted or
+	// the network is idle
+	pub fn run_to_completion(&mut self) -> Result<NetworkStatus, NetworkError> {
+		loop {
+			match self.run()? {
+				NetworkStatus::Output => (),
+				status => return Ok(status),
+			}
+		}
+	}
+
+	// Runs one machine for a time slice. Returns whether it did anything: read
+	// real input, output or ran without waiting.
+	fn turn(&mut self, index: usize) -> Result<bool, NetworkError> {
+		let idle_input = self.idle_input;
+		let time_slice = self.time_slice;
+		let machine = &mut self.machines[index];
+		let mut busy = !machine.vm.input().is_empty();
+		match machine.status {
+			Some(Status::Halt) => return Ok(false),
+			Some(Status::WaitForInput) if !busy => match idle_input {
+				Some(value) => machine.vm.queue_input(value),
+				None => return Ok(false),
+			},
+			_ => busy = true,
+		}
+
+		let start = machine.vm.instruction_count();
+		let mut output = Vec::new();
+		let status = loop {
+			let used = machine.vm.instruction_count() - start;
+			let status = match machine.vm.run_for(time_slice.saturating_sub(used)) {
+				Ok(status) => status,
+				Err(error) => {
+					return Err(NetworkError {
+						machine: index,
+						error,
+					})
+				}
+			};
+			output.extend(machine.vm.drain_output());
+			if status != Status::NewOutput || used >= time_slice {
+				break status;
+			}
+		};
+		machine.status = Some(status);
+		busy |= !output.is_empty();
+
+		for value in output {
+			self.route(index, value);
+		}
+		return Ok(busy);
+	}
+
+	fn route(&mut self, from: usize, value: i64) {
+		let count = self.machines.len();
+		self.machines[from].last_output = Some(value);
+		let (to, values) = match self.topology {
+			Topology::Pipeline => (from + 1, vec![value]),
+			Topology::Ring => ((from + 1) % count, vec![value]),
+			Topology::PacketSwitch { packet_size } => {
+				let packet = &mut self.machines[from].packet;
+				packet.push(value);
+				if packet.len() < packet_size {
+					return;
+				}
+				let values = packet.split_off(1);
+				let to = packet.pop().expect("Packet address");
+				self.deliver(Message { from, to, values });
+				return;
+			}
+		};
+		self.deliver(Message {
+			from,
+			to: to as i64,
+			values,
+		});
+	}
+
+	fn deliver(&mut self, message: Message) {
+		for observer in self.observers.iter_mut() {
+			observer.message(&message);
+		}
+		if message.to < 0 || message.to as usize >= self.machines.len() {
+			self.outbox.push_back(message);
+			return;
+		}
+		let vm = &mut self.machines[message.to as usize].vm;
+		for &value in &message.values {
+			vm.queue_input(value);
+		}
+		self.quiet_turns = 0;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::compile::CompiledVM;
+
+	// Adds its first input to every following one
+	const ADDER: [i64; 15] = [3, 13, 3, 14, 1, 13, 14, 14, 4, 14, 1105, 1, 2, 0, 0];
+
+	// Outputs its input doubled until that reaches 100, then halts
+	const DOUBLER: [i64; 22] = [
+		3, 20, 1002, 20, 2, 20, 4, 20, 1007, 20, 100, 21, 1005, 21, 0, 99, 0, 0, 0, 0, 0, 0,
+	];
+
+	#[test]
+	fn pipelines() {
+		let mut network = Network::from_program(&ADDER, 3, Topology::Pipeline);
+		for machine in 0..3 {
+			network.queue_input(machine, 10 * (machine as i64 + 1));
+		}
+		network.queue_input(0, 1);
+		network.queue_input(0, 2);
+		assert_eq!(network.run(), Ok(NetworkStatus::Output));
+		let message = network.take_output().unwrap();
+		assert_eq!((message.from, message.to, message.values), (2, 3, vec![61]));
+		assert_eq!(network.take_output().unwrap().values, [62]);
+		assert_eq!(network.run(), Ok(NetworkStatus::Idle));
+		assert_eq!(network.last_output(1), Some(32));
+
+		network.queue_input(0, 5);
+		assert_eq!(network.run(), Ok(NetworkStatus::Output));
+		assert_eq!(network.take_output().unwrap().values, [65]);
+	}
+
+	#[test]
+	fn rings_and_observers() {
+		let mut network = Network::from_program(&DOUBLER, 2, Topology::Ring);
+		let traffic = Arc::new(Mutex::new(Vec::new()));
+		network.observe(Arc::clone(&traffic));
+		network.queue_input(0, 3);
+		assert_eq!(network.run_to_completion(), Ok(NetworkStatus::Halted));
+		let values: Vec<i64> = traffic
+			.lock()
+			.unwrap()
+			.iter()
+			.map(|m| m.values[0])
+			.collect();
+		assert_eq!(values, [6, 12, 24, 48, 96, 192, 384]);
+		assert_eq!(network.last_output(1), Some(192));
+
+		// The same on compiled machines
+		let mut network: Network<CompiledVM> =
+			Network::from_program_with_engine(&DOUBLER, 2, Topology::Ring);
+		network.queue_input(0, 3);
+		assert_eq!(network.run_to_completion(), Ok(NetworkStatus::Halted));
+		assert_eq!(network.last_output(1), Some(192));
+		assert!(network.vm(0).block_count() > 0);
+	}
+
+	#[test]
+	fn routes_packets() {
+		let program = crate::parse_program(include_str!("../../../input/2019/day23.txt")).unwrap();
+		let topology = Topology::PacketSwitch { packet_size: 3 };
+		let mut network = Network::from_program(&program, 50, topology);
+		network.set_idle_input(Some(-1));
+		for address in 0..50 {
+			network.queue_input(address, address as i64);
+		}
+		assert_eq!(network.run(), Ok(NetworkStatus::Output));
+		let nat = network.take_output().unwrap();
+		assert_eq!(nat.to, 255);
+		assert_eq!(nat.values.len(), 2);
+
+		// The NAT wakes the network up again once it goes idle
+		let mut last = nat.values;
+		assert_eq!(network.run_to_completion(), Ok(NetworkStatus::Idle));
+		while let Some(message) = network.take_output() {
+			last = message.values;
+		}
+		network.send(0, &last);
+		assert_eq!(network.run(), Ok(NetworkStatus::Output));
+	}
+
+	#[test]
+	fn reports_faults() {
+		let mut network = Network::from_program(&[99], 2, Topology::Ring);
+		network.add(VM::from_memory(&[42]));
+		let error = network.run().unwrap_err();
+		assert_eq!(error.machine, 2);
+		assert_eq!(
+			error.to_string(),
+			"machine 2: unknown opcode (instruction 42 at pc 0)"
+		);
+		network.vm_mut(2).reset(&[99]);
+		assert_eq!(network.run(), Ok(NetworkStatus::Halted));
+	}
+}
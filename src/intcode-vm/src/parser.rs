@@ -0,0 +1,103 @@
+/*
+	--- Intcode VM: program parser ---
+*/
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum ParseError {
+	InvalidValue {
+		offset: usize, // Byte offset of the token in the source
+		token: String,
+	},
+	Io(io::Error),
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ParseError::InvalidValue { offset, token } if token.is_empty() => {
+				write!(f, "missing value at byte {}", offset)
+			}
+			ParseError::InvalidValue { offset, token } => {
+				write!(f, "invalid value '{}' at byte {}", token, offset)
+			}
+			ParseError::Io(e) => write!(f, "{}", e),
+		}
+	}
+}
+
+impl Error for ParseError {}
+
+impl From<io::Error> for ParseError {
+	fn from(e: io::Error) -> Self {
+		ParseError::Io(e)
+	}
+}
+
+// Parses comma separated intcode. Whitespace and newlines around values are
+// ignored, as is a trailing comma.
+pub fn parse_program(source: &str) -> Result<Vec<i64>, ParseError> {
+	let mut memory = Vec::new();
+	let mut offset = 0;
+	let mut tokens = source.split(',').peekable();
+	while let Some(token) = tokens.next() {
+		let trimmed = token.trim();
+		let start = offset + (token.len() - token.trim_start().len());
+		offset += token.len() + 1;
+		if trimmed.is_empty() && tokens.peek().is_none() {
+			break; // Trailing comma or an empty program
+		}
+		match trimmed.parse::<i64>() {
+			Ok(value) => memory.push(value),
+			Err(_) => {
+				return Err(ParseError::InvalidValue {
+					offset: start,
+					token: trimmed.to_string(),
+				})
+			}
+		}
+	}
+	return Ok(memory);
+}
+
+pub fn read_program<R: Read>(mut reader: R) -> Result<Vec<i64>, ParseError> {
+	let mut source = String::new();
+	reader.read_to_string(&mut source)?;
+	return parse_program(&source);
+}
+
+pub fn load_program<P: AsRef<Path>>(path: P) -> Result<Vec<i64>, ParseError> {
+	return read_program(File::open(path)?);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn tolerates_whitespace() {
+		assert_eq!(parse_program("1,0, 0,\n3,99\n").unwrap(), [1, 0, 0, 3, 99]);
+		assert_eq!(parse_program(" 1 ,\n-2,\r\n").unwrap(), [1, -2]);
+		assert!(parse_program("\n").unwrap().is_empty());
+		assert_eq!(read_program(&b"104,5,99"[..]).unwrap(), [104, 5, 99]);
+	}
+
+	#[test]
+	fn reports_bad_tokens() {
+		match parse_program("1,2, x3,4") {
+			Err(ParseError::InvalidValue { offset, token }) => {
+				assert_eq!(offset, 5);
+				assert_eq!(token, "x3");
+			}
+			other => panic!("unexpected result {:?}", other),
+		}
+		let error = parse_program("1,,2").unwrap_err();
+		assert_eq!(error.to_string(), "missing value at byte 2");
+		assert!(load_program("does/not/exist.txt").is_err());
+	}
+}
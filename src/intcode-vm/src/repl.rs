@@ -0,0 +1,215 @@
+/*
+	--- Intcode VM: debugger REPL ---
+
+	A gdb-like line-oriented front end for debugger::Session. Scripts use the
+	same commands, one per line, with `#` comments. Each command is echoed
+	after the prompt so the output of a script reads like a transcript of an
+	interactive session, which makes it easy to replay one in a test.
+*/
+
+use crate::debugger::{Command, CommandError, Session};
+use std::error::Error;
+use std::fmt;
+use std::io::{BufRead, Write};
+
+pub const PROMPT: &str = "(ivm) ";
+
+// A command in a script that failed
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScriptError {
+	pub line: usize, // 1-based line of the command
+	pub error: CommandError,
+}
+
+impl fmt::Display for ScriptError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "line {}: {}", self.line, self.error)
+	}
+}
+
+impl Error for ScriptError {}
+
+fn is_comment(line: &str) -> bool {
+	let line = line.trim();
+	return line.is_empty() || line.starts_with('#');
+}
+
+// Whether an empty line repeats the command: stepping and running, or
+// commands that only look at the machine
+fn repeats(command: &Command) -> bool {
+	match command {
+		Command::Step(_) | Command::Next | Command::Continue(_) => return true,
+		Command::Breakpoints | Command::Examine { .. } | Command::Info => return true,
+		Command::Disassemble { .. } | Command::Output | Command::Help => return true,
+		_ => return false,
+	}
+}
+
+// Runs one command, writing its response. Returns false once the user quits.
+fn execute<W: Write>(
+	session: &mut Session,
+	command: &Command,
+	output: &mut W,
+) -> Result<bool, CommandError> {
+	if *command == Command::Quit {
+		return Ok(false);
+	}
+	let response = session.execute(command)?;
+	if !response.is_empty() {
+		let _ = writeln!(output, "{}", response);
+	}
+	return Ok(true);
+}
+
+// Runs every command in `script`, stopping at the first one that fails.
+// Returns false if the script quit.
+pub fn run_script<W: Write>(
+	session: &mut Session,
+	script: &str,
+	mut output: W,
+) -> Result<bool, ScriptError> {
+	for (index, line) in script.lines().enumerate() {
+		if is_comment(line) {
+			continue;
+		}
+		let _ = writeln!(output, "{}{}", PROMPT, line.trim());
+		match Command::parse(line).and_then(|command| execute(session, &command, &mut output)) {
+			Ok(true) => (),
+			Ok(false) => return Ok(false),
+			Err(error) => {
+				return Err(ScriptError {
+					line: index + 1,
+					error,
+				})
+			}
+		}
+	}
+	return Ok(true);
+}
+
+// Reads commands until the input ends or the user quits. Errors are reported
+// and the session carries on, and an empty line repeats the last command if
+// it's one that repeats().
+pub fn run_interactive<R: BufRead, W: Write>(session: &mut Session, mut input: R, mut output: W) {
+	let mut last = None;
+	loop {
+		let _ = write!(output, "{}", PROMPT);
+		let _ = output.flush();
+		let mut line = String::new();
+		match input.read_line(&mut line) {
+			Ok(0) | Err(_) => return, // End of input
+			Ok(_) => (),
+		}
+		let command = match line.trim() {
+			"" => match &last {
+				Some(last) => Command::clone(last),
+				None => continue,
+			},
+			line if line.starts_with('#') => continue,
+			line => match Command::parse(line) {
+				Ok(command) => command,
+				Err(error) => {
+					let _ = writeln!(output, "error: {}", error);
+					last = None;
+					continue;
+				}
+			},
+		};
+		last = Some(command.clone()).filter(repeats);
+		match execute(session, &command, &mut output) {
+			Ok(true) => (),
+			Ok(false) => return,
+			Err(error) => {
+				let _ = writeln!(output, "error: {}", error);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Outputs its input doubled, then halts
+	const DOUBLER: [i64; 9] = [3, 9, 102, 2, 9, 9, 4, 9, 99];
+
+	#[test]
+	fn replays_scripts() {
+		let script = "
+			# Double 21 and look at the result
+			break OUT
+			input 21
+			continue
+			x/2 8
+			continue
+		";
+		let mut session = Session::new(&DOUBLER);
+		let mut output = Vec::new();
+		assert_eq!(run_script(&mut session, script, &mut output), Ok(true));
+		assert_eq!(
+			String::from_utf8(output).unwrap(),
+			"(ivm) break OUT
+(ivm) input 21
+(ivm) continue
+breakpoint on OUT
+=>     6: OUT [9]
+(ivm) x/2 8
+    8: 99 42
+(ivm) continue
+output: 42
+halted
+=>     8: HALT
+"
+		);
+	}
+
+	#[test]
+	fn script_errors() {
+		let mut session = Session::new(&DOUBLER);
+		let error = run_script(&mut session, "step\n\nstep x\nstep", Vec::new()).unwrap_err();
+		assert_eq!(error.to_string(), "line 3: invalid count 'x'");
+		assert_eq!(session.vm().instruction_count(), 0); // The IN is waiting for input
+
+		assert_eq!(
+			run_script(&mut session, "quit\nstep", Vec::new()),
+			Ok(false)
+		);
+	}
+
+	#[test]
+	fn interactive() {
+		let mut session = Session::new(&DOUBLER);
+		let mut output = Vec::new();
+		run_interactive(
+			&mut session,
+			&b"input 5\nbogus\nstep\n\nquit\nstep\n"[..],
+			&mut output,
+		);
+		assert_eq!(
+			String::from_utf8(output).unwrap(),
+			"(ivm) (ivm) error: unknown command 'bogus', try 'help'
+(ivm) =>     2: MUL #2, [9], [9]
+(ivm) =>     6: OUT [9]
+(ivm) "
+		);
+		assert_eq!(session.vm().instruction_count(), 2);
+
+		// Commands that change the machine's state aren't repeated
+		let mut session = Session::new(&DOUBLER);
+		let mut output = Vec::new();
+		run_interactive(
+			&mut session,
+			&b"input 5\n\ninfo\n\nrestart\n\n"[..],
+			&mut output,
+		);
+		let info =
+			"pc 0  rb 0  instructions 0\nstatus: not started\ninput: [5]\noutput: 0 value(s)";
+		assert_eq!(
+			String::from_utf8(output).unwrap(),
+			format!(
+				"(ivm) (ivm) (ivm) {}\n(ivm) {}\n(ivm) =>     0: IN [9]\n(ivm) (ivm) ",
+				info, info
+			)
+		);
+	}
+}
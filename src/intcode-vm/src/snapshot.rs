@@ -0,0 +1,353 @@
+/*
+	--- Intcode VM: snapshots ---
+
+	A snapshot is the complete state of a machine part way through a run, so
+	it can be resumed later or branched into several copies. Attached devices,
+	tracers and breakpoints belong to whoever is driving the VM and aren't
+	included.
+
+	Snapshots are saved as text, one field per line:
+
+		intcode-snapshot 1
+		pc 12
+		relative-base 2000
+		instructions 345
+		memory-limit 16777216
+		input 1,2
+		output
+		memory 0 109,1,204,-1
+		memory 1048576 0,0,7
+
+	Each `memory` line gives a start address and the cells from there on. The
+	line starting at 0 holds all of the contiguous memory, so it comes back
+	the same length, but sparse pages leave out their trailing zeroes.
+*/
+
+use crate::{parse_program, Memory, VM};
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+const HEADER: &str = "intcode-snapshot 1";
+
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+	pub memory: Memory,        // Includes the memory limit
+	pub pc: usize,             // Address of the next instruction
+	pub relative_base: i64,    // Base offset for relative parameters
+	pub instructions: u64,     // Instructions executed so far
+	pub input: VecDeque<i64>,  // Input queued but not yet read
+	pub output: VecDeque<i64>, // Output not yet drained
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+	Invalid {
+		line: usize, // 1-based line of the problem
+		message: String,
+	},
+	Io(io::Error),
+}
+
+impl fmt::Display for SnapshotError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			SnapshotError::Invalid { line, message } => write!(f, "line {}: {}", line, message),
+			SnapshotError::Io(e) => write!(f, "{}", e),
+		}
+	}
+}
+
+impl Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+	fn from(e: io::Error) -> Self {
+		SnapshotError::Io(e)
+	}
+}
+
+fn join(values: &[i64]) -> String {
+	let values: Vec<String> = values.iter().map(|x| x.to_string()).collect();
+	return values.join(",");
+}
+
+// Whether `source` looks like a snapshot rather than an intcode program
+pub fn is_snapshot(source: &str) -> bool {
+	return source.starts_with("intcode-snapshot");
+}
+
+impl Snapshot {
+	pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+		writeln!(writer, "{}", HEADER)?;
+		writeln!(writer, "pc {}", self.pc)?;
+		writeln!(writer, "relative-base {}", self.relative_base)?;
+		writeln!(writer, "instructions {}", self.instructions)?;
+		writeln!(writer, "memory-limit {}", self.memory.limit())?;
+		let input: Vec<i64> = self.input.iter().copied().collect();
+		writeln!(writer, "input {}", join(&input))?;
+		let output: Vec<i64> = self.output.iter().copied().collect();
+		writeln!(writer, "output {}", join(&output))?;
+		for (start, cells) in self.memory.regions() {
+			let used = match start {
+				0 => cells.len(),
+				_ => cells
+					.iter()
+					.rposition(|&x| x != 0)
+					.map_or(0, |last| last + 1),
+			};
+			if used > 0 || start == 0 {
+				writeln!(writer, "memory {} {}", start, join(&cells[..used]))?;
+			}
+		}
+		return writer.flush();
+	}
+
+	pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+		return self.write_to(BufWriter::new(File::create(path)?));
+	}
+
+	pub fn parse(source: &str) -> Result<Snapshot, SnapshotError> {
+		let mut lines = source.lines().enumerate();
+		match lines.next() {
+			Some((_, HEADER)) => (),
+			Some((_, line)) if is_snapshot(line) => {
+				return Err(invalid(1, format!("unsupported version '{}'", line)))
+			}
+			_ => return Err(invalid(1, "not an intcode snapshot")),
+		}
+
+		let mut pc = None;
+		let mut relative_base = None;
+		let mut instructions = None;
+		let mut limit = None;
+		let mut input = None;
+		let mut output = None;
+		let mut regions = Vec::new();
+		for (index, line) in lines {
+			let line_number = index + 1;
+			if line.trim().is_empty() {
+				continue;
+			}
+			let (key, value) = match line.find(' ') {
+				Some(space) => (&line[..space], line[space + 1..].trim()),
+				None => (line.trim(), ""),
+			};
+			let list = |value: &str| {
+				parse_program(value).map_err(|e| invalid(line_number, format!("{}: {}", key, e)))
+			};
+			match key {
+				"pc" => pc = Some(field(line_number, key, value)?),
+				"relative-base" => relative_base = Some(field(line_number, key, value)?),
+				"instructions" => instructions = Some(field(line_number, key, value)?),
+				"memory-limit" => limit = Some(field(line_number, key, value)?),
+				"input" => input = Some(list(value)?),
+				"output" => output = Some(list(value)?),
+				"memory" => {
+					let (start, cells) = value.split_once(' ').unwrap_or((value, ""));
+					let start: usize = field(line_number, key, start)?;
+					if regions.iter().any(|&(_, other, _)| other == start) {
+						let message = format!("memory at {} is already set", start);
+						return Err(invalid(line_number, message));
+					}
+					regions.push((line_number, start, list(cells)?));
+				}
+				_ => return Err(invalid(line_number, format!("unknown field '{}'", key))),
+			}
+		}
+
+		let missing = |field: &str| invalid(source.lines().count(), format!("missing {}", field));
+		// Contiguous memory first, so it keeps its length, then everything else
+		// with the limit lifted until it's all loaded
+		regions.sort_by_key(|&(_, start, _)| start != 0);
+		let mut memory = match regions.first() {
+			Some((_, 0, cells)) => Memory::from_slice(cells),
+			_ => Memory::new(),
+		};
+		memory.set_limit(usize::MAX);
+		for (line_number, start, cells) in regions.iter().filter(|&&(_, start, _)| start != 0) {
+			for (i, &cell) in cells.iter().enumerate() {
+				let out_of_range = || invalid(*line_number, "memory: address out of range");
+				let address = start.checked_add(i).ok_or_else(out_of_range)?;
+				memory.write(address, cell).map_err(|_| out_of_range())?;
+			}
+		}
+		memory.set_limit(limit.ok_or_else(|| missing("memory-limit"))?);
+		return Ok(Snapshot {
+			memory,
+			pc: pc.ok_or_else(|| missing("pc"))?,
+			relative_base: relative_base.ok_or_else(|| missing("relative-base"))?,
+			instructions: instructions.ok_or_else(|| missing("instructions"))?,
+			input: input.ok_or_else(|| missing("input"))?.into(),
+			output: output.ok_or_else(|| missing("output"))?.into(),
+		});
+	}
+
+	pub fn read_from<R: Read>(mut reader: R) -> Result<Snapshot, SnapshotError> {
+		let mut source = String::new();
+		reader.read_to_string(&mut source)?;
+		return Snapshot::parse(&source);
+	}
+
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<Snapshot, SnapshotError> {
+		return Snapshot::read_from(File::open(path)?);
+	}
+}
+
+// Parses the value of a numeric field
+fn field<T: FromStr>(line: usize, key: &str, value: &str) -> Result<T, SnapshotError> {
+	return value
+		.parse::<T>()
+		.map_err(|_| invalid(line, format!("invalid value '{}' for {}", value, key)));
+}
+
+fn invalid<S: Into<String>>(line: usize, message: S) -> SnapshotError {
+	SnapshotError::Invalid {
+		line,
+		message: message.into(),
+	}
+}
+
+impl VM {
+	// Captures the machine state. Memory is shared copy-on-write, so this is
+	// cheap even for large programs.
+	pub fn snapshot(&self) -> Snapshot {
+		Snapshot {
+			memory: self.ram.clone(),
+			pc: self.pc.get(),
+			relative_base: self.relative_base,
+			instructions: self.instructions,
+			input: self.input.clone(),
+			output: self.output.clone(),
+		}
+	}
+
+	// Puts the machine back into a captured state. Devices, the tracer and
+	// breakpoints are kept, but the history is cleared.
+	pub fn restore(&mut self, snapshot: &Snapshot) {
+		self.ram = snapshot.memory.clone();
+		self.pc = Cell::new(snapshot.pc);
+		self.relative_base = snapshot.relative_base;
+		self.instructions = snapshot.instructions;
+		self.instruction_pc = snapshot.pc;
+		self.input = snapshot.input.clone();
+		self.output = snapshot.output.clone();
+		self.edited();
+		self.revalidate_cache();
+	}
+
+	pub fn from_snapshot(snapshot: &Snapshot) -> VM {
+		let mut vm = VM::new();
+		vm.restore(snapshot);
+		return vm;
+	}
+
+	// A copy of the machine that runs independently from here on, without
+	// any of the devices, tracer or breakpoints
+	pub fn fork(&self) -> VM {
+		return VM::from_snapshot(&self.snapshot());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{Status, DEFAULT_MEMORY_LIMIT};
+
+	// Adds pairs of inputs together until the input runs out
+	const ADDER: [i64; 13] = [3, 13, 3, 14, 1, 13, 14, 15, 4, 15, 1105, 1, 0];
+
+	#[test]
+	fn branches_execution() {
+		let mut vm = VM::from_memory(&ADDER);
+		vm.queue_input(1);
+		assert_eq!(vm.run_intcode(), Ok(Status::WaitForInput));
+		let snapshot = vm.snapshot();
+
+		let mut left = vm.fork();
+		assert_eq!(left.run_until_halt(&[2]).unwrap().output, [3]);
+		assert_eq!(vm.run_until_halt(&[10]).unwrap().output, [11]);
+
+		vm.restore(&snapshot);
+		assert_eq!(vm.run_until_halt(&[5]).unwrap().output, [6]);
+		assert_eq!(vm.instruction_count(), left.instruction_count());
+	}
+
+	#[test]
+	fn round_trips_through_text() {
+		let mut vm = VM::from_memory(&[109, 7, 21101, 2, 3, 1048576, 3, 0, 99]);
+		vm.set_memory_limit(100_000);
+		vm.queue_input(-4);
+		vm.queue_input(9);
+		assert_eq!(vm.step(), Ok(None));
+		assert_eq!(vm.step(), Ok(None));
+
+		let mut text = Vec::new();
+		vm.snapshot().write_to(&mut text).unwrap();
+		let text = String::from_utf8(text).unwrap();
+		assert_eq!(
+			text,
+			"intcode-snapshot 1\npc 6\nrelative-base 7\ninstructions 2\nmemory-limit 100000\n\
+			 input -4,9\noutput \nmemory 0 109,7,21101,2,3,1048576,3,0,99\nmemory 1048576 0,0,0,0,0,0,0,5\n"
+		);
+
+		let mut copy = VM::from_snapshot(&Snapshot::parse(&text).unwrap());
+		assert_eq!(copy.memory().read(1048583), 5);
+		assert_eq!(copy.memory().limit(), 100_000);
+		assert_eq!(copy.run_until_halt(&[]).unwrap().status, Status::Halt);
+		assert_eq!(copy.memory().read(0), -4);
+		assert_eq!(copy.input(), &[9]);
+	}
+
+	#[test]
+	fn keeps_contiguous_memory_length() {
+		// Writing a zero past the program still grows the contiguous memory
+		let mut vm = VM::from_memory(&[1101, 0, 0, 20, 99]);
+		assert_eq!(vm.run_intcode(), Ok(Status::Halt));
+		assert_eq!(vm.memory().dense().len(), 21);
+
+		let mut text = Vec::new();
+		vm.snapshot().write_to(&mut text).unwrap();
+		let snapshot = Snapshot::parse(&String::from_utf8(text).unwrap()).unwrap();
+		assert_eq!(snapshot.memory.dense(), vm.memory().dense());
+	}
+
+	#[test]
+	fn errors() {
+		let error = Snapshot::parse("1,2,3").unwrap_err();
+		assert_eq!(error.to_string(), "line 1: not an intcode snapshot");
+
+		let error = Snapshot::parse("intcode-snapshot 1\npc x\n").unwrap_err();
+		assert_eq!(error.to_string(), "line 2: invalid value 'x' for pc");
+
+		let error = Snapshot::parse("intcode-snapshot 1\npc -1\n").unwrap_err();
+		assert_eq!(error.to_string(), "line 2: invalid value '-1' for pc");
+
+		let error = Snapshot::parse("intcode-snapshot 1\nmemory-limit -5\n").unwrap_err();
+		assert_eq!(
+			error.to_string(),
+			"line 2: invalid value '-5' for memory-limit"
+		);
+
+		let error = Snapshot::parse("intcode-snapshot 1\npc 0\nmemory 0 1,,2").unwrap_err();
+		assert_eq!(error.to_string(), "line 3: memory: missing value at byte 2");
+
+		let text = format!("intcode-snapshot 1\nmemory {} 1,2", usize::MAX);
+		let error = Snapshot::parse(&text).unwrap_err();
+		assert_eq!(error.to_string(), "line 2: memory: address out of range");
+
+		let error = Snapshot::parse("intcode-snapshot 1\nmemory 0 1\nmemory 0 2").unwrap_err();
+		assert_eq!(error.to_string(), "line 3: memory at 0 is already set");
+
+		let text = format!(
+			"intcode-snapshot 1\npc 0\nmemory-limit {}",
+			DEFAULT_MEMORY_LIMIT
+		);
+		let error = Snapshot::parse(&text).unwrap_err();
+		assert_eq!(error.to_string(), "line 3: missing relative-base");
+	}
+}
@@ -0,0 +1,491 @@
+/*
+	--- Intcode VM: constraint solver ---
+
+	Decides whether the constraints of a symbolic path can all hold, and
+	finds symbol values that satisfy them. Constraints that are linear in the
+	symbols are turned into bounds on each symbol's range, which usually pins
+	the answer down without guessing. Anything else is only checked once
+	every symbol it mentions has a value. When propagation runs out, the
+	search guesses a value, preferring those nearest zero, and splits ranges
+	in half rather than trying every value in turn.
+*/
+
+use crate::symbolic::{Constraint, Expr, Symbol, SymbolInfo, SymbolKind};
+use std::collections::{BTreeMap, BTreeSet};
+
+// Nodes the search may visit before giving up
+const MAX_NODES: usize = 20_000;
+// Propagation passes per node; each pass only has to shrink one range
+const MAX_PASSES: usize = 100;
+// Ranges at most this wide have every value tried instead of being split
+const ENUMERATE_WIDTH: i128 = 64;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Satisfiability {
+	Sat(BTreeMap<Symbol, i64>), // Values for the symbols the constraints mention
+	Unsat,
+	Unknown, // Gave up, or the answer depends on a load
+}
+
+// constant + sum of coefficient * symbol
+#[derive(Clone, Debug, PartialEq)]
+struct Linear {
+	constant: i128,
+	terms: BTreeMap<Symbol, i128>,
+}
+
+impl Linear {
+	fn constant(value: i128) -> Self {
+		Linear {
+			constant: value,
+			terms: BTreeMap::new(),
+		}
+	}
+
+	fn of(expr: &Expr) -> Option<Linear> {
+		match expr {
+			Expr::Const(value) => Some(Linear::constant(*value as i128)),
+			Expr::Symbol(symbol) => {
+				let mut linear = Linear::constant(0);
+				linear.terms.insert(*symbol, 1);
+				Some(linear)
+			}
+			Expr::Add(a, b) => Some(Linear::of(a)?.plus(&Linear::of(b)?, 1)),
+			Expr::Mul(a, b) => {
+				let (a, b) = (Linear::of(a)?, Linear::of(b)?);
+				match (a.terms.is_empty(), b.terms.is_empty()) {
+					(true, _) => Some(b.scaled(a.constant)),
+					(_, true) => Some(a.scaled(b.constant)),
+					_ => None,
+				}
+			}
+			Expr::Lt(..) | Expr::Eq(..) => None,
+		}
+	}
+
+	// self + scale * other
+	fn plus(mut self, other: &Linear, scale: i128) -> Self {
+		self.constant = self
+			.constant
+			.saturating_add(other.constant.saturating_mul(scale));
+		for (&symbol, &coefficient) in &other.terms {
+			let sum = self.terms.get(&symbol).copied().unwrap_or(0);
+			let sum = sum.saturating_add(coefficient.saturating_mul(scale));
+			if sum == 0 {
+				self.terms.remove(&symbol);
+			} else {
+				self.terms.insert(symbol, sum);
+			}
+		}
+		return self;
+	}
+
+	fn scaled(self, scale: i128) -> Self {
+		return Linear::constant(0).plus(&self, scale);
+	}
+
+	// Smallest value over the ranges, or None if it doesn't fit an i128
+	fn min(&self, domains: &Domains) -> Option<i128> {
+		let mut sum = self.constant;
+		for (symbol, &coefficient) in &self.terms {
+			sum = sum.checked_add(term_min(coefficient, domains[symbol])?)?;
+		}
+		return Some(sum);
+	}
+
+	// The value, once every symbol is fixed
+	fn value(&self, domains: &Domains) -> Option<i128> {
+		let mut sum = self.constant;
+		for (symbol, &coefficient) in &self.terms {
+			let (lo, hi) = domains[symbol];
+			if lo != hi {
+				return None;
+			}
+			sum = sum.checked_add(coefficient.checked_mul(lo)?)?;
+		}
+		return Some(sum);
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Relation {
+	NotPositive, // <= 0
+	NonZero,     // != 0
+}
+
+type Domains = BTreeMap<Symbol, (i128, i128)>;
+
+fn term_min(coefficient: i128, (lo, hi): (i128, i128)) -> Option<i128> {
+	return coefficient.checked_mul(if coefficient > 0 { lo } else { hi });
+}
+
+fn div_floor(a: i128, b: i128) -> i128 {
+	let quotient = a / b;
+	if a % b != 0 && (a < 0) != (b < 0) {
+		return quotient - 1;
+	}
+	return quotient;
+}
+
+fn div_ceil(a: i128, b: i128) -> i128 {
+	let quotient = a / b;
+	if a % b != 0 && (a < 0) == (b < 0) {
+		return quotient + 1;
+	}
+	return quotient;
+}
+
+// Strips comparisons of comparisons, such as `(a == b) == 0`, which is how
+// programs spell "not". Returns the condition and whether it must be nonzero.
+fn normalise(mut expr: &Expr, mut nonzero: bool) -> (&Expr, bool) {
+	while let Expr::Eq(a, b) = expr {
+		let (comparison, value) = match (&**a, &**b) {
+			(inner @ Expr::Eq(..), Expr::Const(value))
+			| (inner @ Expr::Lt(..), Expr::Const(value)) => (inner, *value),
+			(Expr::Const(value), inner @ Expr::Eq(..))
+			| (Expr::Const(value), inner @ Expr::Lt(..)) => (inner, *value),
+			_ => break,
+		};
+		match value {
+			1 => expr = comparison,
+			0 => {
+				expr = comparison;
+				nonzero = !nonzero;
+			}
+			_ => break, // Never equal, which evaluating will find
+		}
+	}
+	return (expr, nonzero);
+}
+
+// Turns a constraint into linear relations, or None if it isn't linear
+fn relations(constraint: &Constraint) -> Option<Vec<(Linear, Relation)>> {
+	let (expr, nonzero) = match constraint {
+		Constraint::NonZero(expr) => normalise(expr, true),
+		Constraint::Zero(expr) => normalise(expr, false),
+	};
+	let difference = |a: &Expr, b: &Expr| Some(Linear::of(a)?.plus(&Linear::of(b)?, -1));
+	let relations = match (expr, nonzero) {
+		(Expr::Eq(a, b), true) => {
+			let d = difference(a, b)?;
+			let negated = d.clone().scaled(-1);
+			vec![(d, Relation::NotPositive), (negated, Relation::NotPositive)]
+		}
+		(Expr::Eq(a, b), false) => vec![(difference(a, b)?, Relation::NonZero)],
+		// a < b is a - b + 1 <= 0
+		(Expr::Lt(a, b), true) => vec![(
+			difference(a, b)?.plus(&Linear::constant(1), 1),
+			Relation::NotPositive,
+		)],
+		(Expr::Lt(a, b), false) => vec![(difference(b, a)?, Relation::NotPositive)],
+		(expr, true) => vec![(Linear::of(expr)?, Relation::NonZero)],
+		(expr, false) => {
+			let linear = Linear::of(expr)?;
+			let negated = linear.clone().scaled(-1);
+			vec![
+				(linear, Relation::NotPositive),
+				(negated, Relation::NotPositive),
+			]
+		}
+	};
+	return Some(relations);
+}
+
+struct Search<'a> {
+	relations: Vec<(Linear, Relation)>,
+	checks: Vec<&'a Constraint>, // Not linear, checked once their symbols are fixed
+	nodes: usize,
+}
+
+impl<'a> Search<'a> {
+	// Shrinks ranges until nothing changes, returning false if one empties
+	fn propagate(&self, domains: &mut Domains) -> bool {
+		for _ in 0..MAX_PASSES {
+			let mut changed = false;
+			for (linear, relation) in &self.relations {
+				let narrowed = match relation {
+					Relation::NotPositive => narrow_not_positive(linear, domains),
+					Relation::NonZero => narrow_nonzero(linear, domains),
+				};
+				match narrowed {
+					Some(narrowed) => changed |= narrowed,
+					None => return false,
+				}
+			}
+			if !changed {
+				break;
+			}
+		}
+		return true;
+	}
+
+	fn satisfied(&self, domains: &Domains) -> bool {
+		let relations =
+			self.relations
+				.iter()
+				.all(|(linear, relation)| match linear.value(domains) {
+					Some(value) => match relation {
+						Relation::NotPositive => value <= 0,
+						Relation::NonZero => value != 0,
+					},
+					None => false,
+				});
+		let value = |symbol: Symbol| domains.get(&symbol).map(|&(lo, _)| lo as i64);
+		return relations
+			&& self
+				.checks
+				.iter()
+				.all(|check| check.holds(&value) == Some(true));
+	}
+
+	// Depth first search for values, or Err if it took too long
+	fn search(&mut self, mut domains: Domains) -> Result<Option<Domains>, ()> {
+		self.nodes += 1;
+		if self.nodes > MAX_NODES {
+			return Err(());
+		}
+		if !self.propagate(&mut domains) {
+			return Ok(None);
+		}
+		let unfixed = domains
+			.iter()
+			.filter(|(_, &(lo, hi))| lo < hi)
+			.min_by_key(|(_, &(lo, hi))| hi.saturating_sub(lo));
+		let (&symbol, &(lo, hi)) = match unfixed {
+			Some(unfixed) => unfixed,
+			None if self.satisfied(&domains) => return Ok(Some(domains)),
+			None => return Ok(None),
+		};
+
+		let mut branches = Vec::new();
+		if hi - lo < ENUMERATE_WIDTH {
+			let mut values: Vec<i128> = (lo..=hi).collect();
+			values.sort_by_key(|value| value.abs());
+			branches.extend(values.into_iter().map(|value| (value, value)));
+		} else {
+			// Try the value nearest zero, then the rest in halves, nearest first
+			let guess = 0.max(lo).min(hi);
+			branches.push((guess, guess));
+			if guess == lo || guess == hi {
+				let (start, end) = if guess == lo {
+					(lo + 1, hi)
+				} else {
+					(lo, hi - 1)
+				};
+				let middle = start + (end - start) / 2;
+				if guess == lo {
+					branches.extend(&[(start, middle), (middle + 1, end)]);
+				} else {
+					branches.extend(&[(middle + 1, end), (start, middle)]);
+				}
+			} else {
+				branches.extend(&[(guess + 1, hi), (lo, guess - 1)]);
+			}
+		}
+		for (lo, hi) in branches {
+			let mut domains = domains.clone();
+			domains.insert(symbol, (lo, hi));
+			if let Some(solution) = self.search(domains)? {
+				return Ok(Some(solution));
+			}
+		}
+		return Ok(None);
+	}
+}
+
+// Applies linear <= 0 to each symbol's range. Returns whether a range
+// changed, or None if the relation can't hold.
+fn narrow_not_positive(linear: &Linear, domains: &mut Domains) -> Option<bool> {
+	let min = match linear.min(domains) {
+		Some(min) => min,
+		None => return Some(false), // Too big to reason about
+	};
+	if min > 0 {
+		return None;
+	}
+	let mut changed = false;
+	for (symbol, &coefficient) in &linear.terms {
+		let (lo, hi) = domains[symbol];
+		// coefficient * x <= -(everything else at its smallest)
+		let bound = term_min(coefficient, (lo, hi)).expect("Checked by min()") - min;
+		let (new_lo, new_hi) = if coefficient > 0 {
+			(lo, hi.min(div_floor(bound, coefficient)))
+		} else {
+			(lo.max(div_ceil(bound, coefficient)), hi)
+		};
+		if new_lo > new_hi {
+			return None;
+		}
+		if (new_lo, new_hi) != (lo, hi) {
+			domains.insert(*symbol, (new_lo, new_hi));
+			changed = true;
+		}
+	}
+	return Some(changed);
+}
+
+// Applies linear != 0 once at most one symbol is left unfixed, by ruling out
+// the value that would make it zero if that's at the edge of its range
+fn narrow_nonzero(linear: &Linear, domains: &mut Domains) -> Option<bool> {
+	let mut unfixed = linear.terms.iter().filter(|(symbol, _)| {
+		let (lo, hi) = domains[*symbol];
+		lo < hi
+	});
+	let (symbol, coefficient) = match (unfixed.next(), unfixed.next()) {
+		(None, _) => {
+			return if linear.value(domains)? == 0 {
+				None
+			} else {
+				Some(false)
+			}
+		}
+		(Some((&symbol, &coefficient)), None) => (symbol, coefficient),
+		_ => return Some(false),
+	};
+	let mut rest = linear.clone();
+	rest.terms.remove(&symbol);
+	let rest = match rest.value(domains) {
+		Some(rest) => rest,
+		None => return Some(false),
+	};
+	if rest % coefficient != 0 {
+		return Some(false);
+	}
+	let zero = -rest / coefficient;
+	let (lo, hi) = domains[&symbol];
+	let narrowed = if zero == lo {
+		(lo + 1, hi)
+	} else if zero == hi {
+		(lo, hi - 1)
+	} else {
+		return Some(false);
+	};
+	domains.insert(symbol, narrowed);
+	return Some(true);
+}
+
+// Whether all of `constraints` can hold, given the ranges in `symbols`
+pub(crate) fn check(constraints: &[Constraint], symbols: &[SymbolInfo]) -> Satisfiability {
+	let mut search = Search {
+		relations: Vec::new(),
+		checks: Vec::new(),
+		nodes: 0,
+	};
+	let mut mentioned = BTreeSet::new();
+	for constraint in constraints {
+		constraint.symbols(&mut mentioned);
+		match relations(constraint) {
+			Some(relations) => search.relations.extend(relations),
+			None => search.checks.push(constraint),
+		}
+	}
+	let domains: Domains = mentioned
+		.iter()
+		.map(|&symbol| {
+			let range = &symbols[symbol.index()].range;
+			(symbol, (*range.start() as i128, *range.end() as i128))
+		})
+		.collect();
+
+	let domains = match search.search(domains) {
+		Ok(Some(domains)) => domains,
+		Ok(None) => return Satisfiability::Unsat,
+		Err(()) => return Satisfiability::Unknown,
+	};
+	// A load stands for whatever was in memory, not a free choice
+	if mentioned
+		.iter()
+		.any(|symbol| matches!(symbols[symbol.index()].kind, SymbolKind::Load { .. }))
+	{
+		return Satisfiability::Unknown;
+	}
+	let values = domains
+		.into_iter()
+		.map(|(symbol, (value, _))| (symbol, value as i64));
+	return Satisfiability::Sat(values.collect());
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::symbolic::SymbolicVM;
+	use std::ops::RangeInclusive;
+
+	// Symbols x0, x1, ... with the same range
+	fn symbols(count: usize, range: RangeInclusive<i64>) -> (SymbolicVM, Vec<Expr>) {
+		let mut vm = SymbolicVM::from_memory(&[99]);
+		let symbols = (0..count)
+			.map(|i| Expr::Symbol(vm.symbolic_input(&format!("x{}", i), range.clone())))
+			.collect();
+		return (vm, symbols);
+	}
+
+	fn values(result: Satisfiability) -> Vec<i64> {
+		match result {
+			Satisfiability::Sat(values) => return values.values().copied().collect(),
+			result => panic!("Expected a solution, got {:?}", result),
+		}
+	}
+
+	#[test]
+	fn solves_linear_equations() {
+		// 100 * x0 + x1 + 19684299 == 19690720, as in day 2
+		let (vm, x) = symbols(2, 0..=99);
+		let sum = Expr::sum(Expr::product(x[0].clone(), Expr::Const(100)), x[1].clone());
+		let constraints = [Constraint::equal(
+			Expr::sum(sum, Expr::Const(19_684_299)),
+			19_690_720,
+		)];
+		assert_eq!(values(vm.check(&constraints)), [64, 21]);
+
+		// 3 * x0 == 10 has no integer solution
+		let (vm, x) = symbols(1, i64::MIN..=i64::MAX);
+		let constraints = [Constraint::equal(
+			Expr::product(x[0].clone(), Expr::Const(3)),
+			10,
+		)];
+		assert_eq!(vm.check(&constraints), Satisfiability::Unsat);
+	}
+
+	#[test]
+	fn handles_comparisons() {
+		let (vm, x) = symbols(2, i64::MIN..=i64::MAX);
+		// 5 < x0, x0 < x1, x1 != 7 and not (x1 == 8), i.e. (x1 == 8) == 0
+		let constraints = [
+			Constraint::NonZero(Expr::lt(Expr::Const(5), x[0].clone())),
+			Constraint::NonZero(Expr::lt(x[0].clone(), x[1].clone())),
+			Constraint::Zero(Expr::eq(x[1].clone(), Expr::Const(7))),
+			Constraint::NonZero(Expr::eq(
+				Expr::eq(x[1].clone(), Expr::Const(8)),
+				Expr::Const(0),
+			)),
+		];
+		assert_eq!(values(vm.check(&constraints)), [6, 9]);
+
+		// x0 < 0 and x0 >= 0
+		let constraints = [
+			Constraint::NonZero(Expr::lt(x[0].clone(), Expr::Const(0))),
+			Constraint::Zero(Expr::lt(x[0].clone(), Expr::Const(0))),
+		];
+		assert_eq!(vm.check(&constraints), Satisfiability::Unsat);
+	}
+
+	#[test]
+	fn checks_nonlinear_constraints() {
+		// x0 * x1 == 391 with both in 2..=30
+		let (vm, x) = symbols(2, 2..=30);
+		let constraints = [Constraint::equal(
+			Expr::product(x[0].clone(), x[1].clone()),
+			391,
+		)];
+		assert_eq!(values(vm.check(&constraints)), [17, 23]);
+
+		// x0 * x0 == 2 can't be answered by bounds alone, so the search gives up
+		let (vm, x) = symbols(1, i64::MIN..=i64::MAX);
+		let constraints = [Constraint::equal(
+			Expr::product(x[0].clone(), x[0].clone()),
+			2,
+		)];
+		assert_eq!(vm.check(&constraints), Satisfiability::Unknown);
+	}
+}
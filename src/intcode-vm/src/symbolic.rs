@@ -0,0 +1,1094 @@
+/*
+	--- Intcode VM: symbolic execution ---
+
+	Runs a program with some of its inputs or memory cells left unknown.
+	Each unknown is a symbol, and values computed from symbols are
+	expressions over them. A jump that depends on a symbol forks execution:
+	one path assumes the jump is taken and the other that it isn't, and each
+	records its assumption as a constraint. Exploring a program gives every
+	path through it, each with its constraints, outputs and final memory.
+
+	"What input makes the program output X" then becomes: find a path whose
+	constraints can hold along with `output == X`. solve() asks a small
+	solver (see solver.rs) for symbol values, and replays them on the
+	ordinary VM to check the program really does what the path says before
+	returning them.
+
+	Instructions, jump targets, the relative base and addresses have to be
+	concrete. When one depends on symbols that only have a few possible
+	values between them, such as a phase setting used to index a jump table,
+	the path forks once per combination of values, and each fork carries on
+	with those values substituted. Otherwise, reading through the address
+	gives a new symbol standing for whatever was there (a load), so the path
+	carries on, but any answer that depends on a load is refused. Anything
+	else stops the path with an error.
+*/
+
+pub use crate::solver::Satisfiability;
+use crate::{solver, MemMode, Memory, Opcode, Status, VmError, DEFAULT_MEMORY_LIMIT, VM};
+use num_traits::FromPrimitive;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::ops::{Index, RangeInclusive};
+use std::rc::Rc;
+
+// Instructions each path may run before it's abandoned
+pub const DEFAULT_PATH_BUDGET: u64 = 1_000_000;
+// Paths explore() will follow before it stops forking
+pub const DEFAULT_MAX_PATHS: usize = 10_000;
+// Most combinations of symbol values a path forks into to make a value concrete
+pub const DEFAULT_FORK_LIMIT: u64 = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Symbol(usize);
+
+impl Symbol {
+	pub(crate) fn index(self) -> usize {
+		return self.0;
+	}
+}
+
+impl fmt::Display for Symbol {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "s{}", self.0)
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SymbolKind {
+	Input(usize),       // The nth value queued as input
+	Cell(usize),        // The initial value of a memory cell
+	Load { pc: usize }, // Read through an address that depended on a symbol
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SymbolInfo {
+	pub name: String,
+	pub kind: SymbolKind,
+	pub range: RangeInclusive<i64>, // Values the symbol may take
+}
+
+// A value computed from symbols. Comparisons are 1 when they hold, else 0.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+	Const(i64),
+	Symbol(Symbol),
+	Add(Rc<Expr>, Rc<Expr>),
+	Mul(Rc<Expr>, Rc<Expr>),
+	Lt(Rc<Expr>, Rc<Expr>),
+	Eq(Rc<Expr>, Rc<Expr>),
+}
+
+// The constructors fold constants, so concrete values stay Expr::Const
+impl Expr {
+	pub fn sum(a: Expr, b: Expr) -> Expr {
+		match (&a, &b) {
+			(Expr::Const(a), Expr::Const(b)) => Expr::Const(a.wrapping_add(*b)),
+			(Expr::Const(0), _) => b,
+			(_, Expr::Const(0)) => a,
+			_ => Expr::Add(Rc::new(a), Rc::new(b)),
+		}
+	}
+
+	pub fn product(a: Expr, b: Expr) -> Expr {
+		match (&a, &b) {
+			(Expr::Const(a), Expr::Const(b)) => Expr::Const(a.wrapping_mul(*b)),
+			(Expr::Const(0), _) | (_, Expr::Const(0)) => Expr::Const(0),
+			(Expr::Const(1), _) => b,
+			(_, Expr::Const(1)) => a,
+			_ => Expr::Mul(Rc::new(a), Rc::new(b)),
+		}
+	}
+
+	pub fn lt(a: Expr, b: Expr) -> Expr {
+		match (&a, &b) {
+			(Expr::Const(a), Expr::Const(b)) => Expr::Const((a < b) as i64),
+			_ if a == b => Expr::Const(0),
+			_ => Expr::Lt(Rc::new(a), Rc::new(b)),
+		}
+	}
+
+	pub fn eq(a: Expr, b: Expr) -> Expr {
+		match (&a, &b) {
+			(Expr::Const(a), Expr::Const(b)) => Expr::Const((a == b) as i64),
+			_ if a == b => Expr::Const(1),
+			_ => Expr::Eq(Rc::new(a), Rc::new(b)),
+		}
+	}
+
+	pub fn as_const(&self) -> Option<i64> {
+		match *self {
+			Expr::Const(value) => Some(value),
+			_ => None,
+		}
+	}
+
+	// The value given values for the symbols, or None if one is missing
+	pub fn eval(&self, values: &dyn Fn(Symbol) -> Option<i64>) -> Option<i64> {
+		let value = match self {
+			Expr::Const(value) => *value,
+			Expr::Symbol(symbol) => values(*symbol)?,
+			Expr::Add(a, b) => a.eval(values)?.wrapping_add(b.eval(values)?),
+			Expr::Mul(a, b) => a.eval(values)?.wrapping_mul(b.eval(values)?),
+			Expr::Lt(a, b) => (a.eval(values)? < b.eval(values)?) as i64,
+			Expr::Eq(a, b) => (a.eval(values)? == b.eval(values)?) as i64,
+		};
+		return Some(value);
+	}
+
+	// Replaces `symbol` with `value`, folding whatever becomes constant
+	pub fn substitute(&self, symbol: Symbol, value: i64) -> Expr {
+		let substitute = |expr: &Rc<Expr>| expr.substitute(symbol, value);
+		match self {
+			Expr::Symbol(s) if *s == symbol => Expr::Const(value),
+			Expr::Const(_) | Expr::Symbol(_) => self.clone(),
+			Expr::Add(a, b) => Expr::sum(substitute(a), substitute(b)),
+			Expr::Mul(a, b) => Expr::product(substitute(a), substitute(b)),
+			Expr::Lt(a, b) => Expr::lt(substitute(a), substitute(b)),
+			Expr::Eq(a, b) => Expr::eq(substitute(a), substitute(b)),
+		}
+	}
+
+	// Adds every symbol the expression mentions to `symbols`
+	pub fn symbols(&self, symbols: &mut BTreeSet<Symbol>) {
+		match self {
+			Expr::Const(_) => (),
+			Expr::Symbol(symbol) => {
+				symbols.insert(*symbol);
+			}
+			Expr::Add(a, b) | Expr::Mul(a, b) | Expr::Lt(a, b) | Expr::Eq(a, b) => {
+				a.symbols(symbols);
+				b.symbols(symbols);
+			}
+		}
+	}
+}
+
+impl From<i64> for Expr {
+	fn from(value: i64) -> Self {
+		Expr::Const(value)
+	}
+}
+
+impl fmt::Display for Expr {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Expr::Const(value) => write!(f, "{}", value),
+			Expr::Symbol(symbol) => write!(f, "{}", symbol),
+			Expr::Add(a, b) => write!(f, "({} + {})", a, b),
+			Expr::Mul(a, b) => write!(f, "({} * {})", a, b),
+			Expr::Lt(a, b) => write!(f, "({} < {})", a, b),
+			Expr::Eq(a, b) => write!(f, "({} == {})", a, b),
+		}
+	}
+}
+
+// Something a path assumes about an expression
+#[derive(Clone, Debug, PartialEq)]
+pub enum Constraint {
+	NonZero(Expr),
+	Zero(Expr),
+}
+
+impl Constraint {
+	pub fn equal(expr: Expr, value: i64) -> Self {
+		return Constraint::NonZero(Expr::eq(expr, Expr::Const(value)));
+	}
+
+	// Whether it holds given values for the symbols, or None if one is missing
+	pub fn holds(&self, values: &dyn Fn(Symbol) -> Option<i64>) -> Option<bool> {
+		match self {
+			Constraint::NonZero(expr) => Some(expr.eval(values)? != 0),
+			Constraint::Zero(expr) => Some(expr.eval(values)? == 0),
+		}
+	}
+
+	pub fn symbols(&self, symbols: &mut BTreeSet<Symbol>) {
+		match self {
+			Constraint::NonZero(expr) | Constraint::Zero(expr) => expr.symbols(symbols),
+		}
+	}
+}
+
+impl fmt::Display for Constraint {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Constraint::NonZero(expr) => write!(f, "{} != 0", expr),
+			Constraint::Zero(expr) => write!(f, "{} == 0", expr),
+		}
+	}
+}
+
+// Why a path can't be followed any further symbolically
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SymbolicError {
+	Vm(VmError),
+	SymbolicInstruction { pc: usize }, // The instruction itself depends on a symbol
+	SymbolicAddress { pc: usize },     // Writes to an address that depends on a symbol
+	SymbolicJump { pc: usize },        // Jumps to an address that depends on a symbol
+	SymbolicRelativeBase { pc: usize },
+}
+
+impl fmt::Display for SymbolicError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			SymbolicError::Vm(error) => write!(f, "{}", error),
+			SymbolicError::SymbolicInstruction { pc } => {
+				write!(f, "symbolic instruction at pc {}", pc)
+			}
+			SymbolicError::SymbolicAddress { pc } => {
+				write!(f, "write to a symbolic address at pc {}", pc)
+			}
+			SymbolicError::SymbolicJump { pc } => {
+				write!(f, "jump to a symbolic address at pc {}", pc)
+			}
+			SymbolicError::SymbolicRelativeBase { pc } => {
+				write!(f, "symbolic relative base adjustment at pc {}", pc)
+			}
+		}
+	}
+}
+
+impl Error for SymbolicError {}
+
+// How a path ended
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PathEnd {
+	Halted,
+	WaitForInput, // Ran out of queued input
+	BudgetExhausted,
+	PathLimit, // Needed to fork after explore() reached its limit
+	Error(SymbolicError),
+}
+
+// One way through the program
+#[derive(Clone, Debug)]
+pub struct Path {
+	pub constraints: Vec<Constraint>, // Assumed along the way
+	pub output: Vec<Expr>,
+	pub end: PathEnd,
+	pub pc: usize,                   // Of the instruction the path ended on
+	memory: Memory,                  // Concrete cells
+	symbolic: BTreeMap<usize, Expr>, // Cells holding expressions, overriding `memory`
+	relative_base: i64,
+	input: VecDeque<Expr>,
+	instructions: u64,
+}
+
+impl Path {
+	pub fn memory(&self, address: usize) -> Expr {
+		match self.symbolic.get(&address) {
+			Some(expr) => return expr.clone(),
+			None => return Expr::Const(self.memory.read(address)),
+		}
+	}
+
+	pub fn relative_base(&self) -> i64 {
+		return self.relative_base;
+	}
+
+	pub fn instruction_count(&self) -> u64 {
+		return self.instructions;
+	}
+
+	// Fixes the value of a symbol from here on. Storing a cell that becomes
+	// concrete can still run into the memory cap, since the placeholder it
+	// held didn't need a page.
+	fn substitute(&mut self, symbol: Symbol, value: i64) -> Result<(), SymbolicError> {
+		self.constraints
+			.push(Constraint::equal(Expr::Symbol(symbol), value));
+		for expr in self.input.iter_mut().chain(self.output.iter_mut()) {
+			*expr = expr.substitute(symbol, value);
+		}
+		let cells: Vec<usize> = self.symbolic.keys().copied().collect();
+		for address in cells {
+			let expr = self.symbolic[&address].substitute(symbol, value);
+			match expr.as_const() {
+				Some(value) => {
+					if let Err(e) = self.memory.write(address, value) {
+						let intcode = self.memory(self.pc).as_const().unwrap_or(0);
+						return Err(SymbolicError::Vm(VmError::MemoryCapExceeded {
+							pc: self.pc,
+							intcode,
+							address: e.address,
+							limit: e.limit,
+						}));
+					}
+					self.symbolic.remove(&address);
+				}
+				None => {
+					self.symbolic.insert(address, expr);
+				}
+			}
+		}
+		return Ok(());
+	}
+
+	fn write(
+		&mut self,
+		pc: usize,
+		intcode: i64,
+		address: usize,
+		value: Expr,
+	) -> Result<(), SymbolicError> {
+		let concrete = value.as_const().unwrap_or(0);
+		if let Err(e) = self.memory.write(address, concrete) {
+			return Err(SymbolicError::Vm(VmError::MemoryCapExceeded {
+				pc,
+				intcode,
+				address: e.address,
+				limit: e.limit,
+			}));
+		}
+		match value {
+			Expr::Const(_) => self.symbolic.remove(&address),
+			value => self.symbolic.insert(address, value),
+		};
+		return Ok(());
+	}
+}
+
+// Symbol values that satisfy a path
+#[derive(Clone, Debug, PartialEq)]
+pub struct Model {
+	values: BTreeMap<Symbol, i64>,
+}
+
+impl Model {
+	pub fn get(&self, symbol: Symbol) -> Option<i64> {
+		return self.values.get(&symbol).copied();
+	}
+}
+
+impl Index<Symbol> for Model {
+	type Output = i64;
+
+	fn index(&self, symbol: Symbol) -> &i64 {
+		return &self.values[&symbol];
+	}
+}
+
+// What a step of a path led to
+enum Step {
+	Next,
+	Fork {
+		pc: usize,
+		condition: Constraint,
+		target: Expr,
+	}, // A jump on a symbol
+	Concretise(Expr), // Needs the value to be concrete to carry on
+	End(PathEnd),
+}
+
+// Why an instruction couldn't be executed
+enum Stop {
+	Concretise(Expr),
+	Error(SymbolicError),
+}
+
+impl From<SymbolicError> for Stop {
+	fn from(error: SymbolicError) -> Self {
+		Stop::Error(error)
+	}
+}
+
+pub struct SymbolicVM {
+	program: Vec<i64>,
+	cells: Vec<(usize, Symbol)>, // Memory cells replaced by symbols
+	input: Vec<Expr>,            // Queued input, concrete or symbolic
+	symbols: Vec<SymbolInfo>,
+	memory_limit: usize,
+	budget: u64,
+	max_paths: usize,
+	fork_limit: u64,
+}
+
+impl SymbolicVM {
+	pub fn from_memory(program: &[i64]) -> Self {
+		SymbolicVM {
+			program: program.to_vec(),
+			cells: Vec::new(),
+			input: Vec::new(),
+			symbols: Vec::new(),
+			memory_limit: DEFAULT_MEMORY_LIMIT.max(program.len()),
+			budget: DEFAULT_PATH_BUDGET,
+			max_paths: DEFAULT_MAX_PATHS,
+			fork_limit: DEFAULT_FORK_LIMIT,
+		}
+	}
+
+	// Replaces the initial value of a memory cell with a symbol
+	pub fn symbolic_cell(
+		&mut self,
+		address: usize,
+		name: &str,
+		range: RangeInclusive<i64>,
+	) -> Symbol {
+		self.forget_loads();
+		let symbol = self.add_symbol(name, SymbolKind::Cell(address), range);
+		self.cells.push((address, symbol));
+		return symbol;
+	}
+
+	// Queues a symbol as the next input
+	pub fn symbolic_input(&mut self, name: &str, range: RangeInclusive<i64>) -> Symbol {
+		self.forget_loads();
+		let symbol = self.add_symbol(name, SymbolKind::Input(self.input.len()), range);
+		self.input.push(Expr::Symbol(symbol));
+		return symbol;
+	}
+
+	pub fn queue_input(&mut self, value: i64) {
+		self.input.push(Expr::Const(value));
+	}
+
+	pub fn symbol(&self, symbol: Symbol) -> &SymbolInfo {
+		return &self.symbols[symbol.0];
+	}
+
+	pub fn symbols(&self) -> &[SymbolInfo] {
+		return &self.symbols;
+	}
+
+	// Caps how many memory cells each path may allocate
+	pub fn set_memory_limit(&mut self, cells: usize) {
+		self.memory_limit = cells;
+	}
+
+	pub fn set_budget(&mut self, instructions: u64) {
+		self.budget = instructions;
+	}
+
+	pub fn set_max_paths(&mut self, paths: usize) {
+		self.max_paths = paths.max(1);
+	}
+
+	// Sets how many combinations of symbol values a path may fork into to
+	// make an address or jump target concrete
+	pub fn set_fork_limit(&mut self, combinations: u64) {
+		self.fork_limit = combinations;
+	}
+
+	// Whether `constraints` can all hold
+	pub fn check(&self, constraints: &[Constraint]) -> Satisfiability {
+		return solver::check(constraints, &self.symbols);
+	}
+
+	// Follows every feasible path through the program. Loads found along the
+	// way are added to the symbols, replacing those from the last exploration.
+	pub fn explore(&mut self) -> Vec<Path> {
+		self.forget_loads();
+		let mut memory = Memory::from_slice(&self.program);
+		memory.set_limit(self.memory_limit);
+		let mut symbolic = BTreeMap::new();
+		for &(address, symbol) in &self.cells {
+			let _ = memory.write(address, 0);
+			symbolic.insert(address, Expr::Symbol(symbol));
+		}
+		let start = Path {
+			constraints: Vec::new(),
+			output: Vec::new(),
+			end: PathEnd::BudgetExhausted,
+			pc: 0,
+			memory,
+			symbolic,
+			relative_base: 0,
+			input: self.input.iter().cloned().collect(),
+			instructions: 0,
+		};
+
+		let mut paths = Vec::new();
+		let mut pending = vec![start];
+		let mut started = 1;
+		'paths: while let Some(mut path) = pending.pop() {
+			path.end = loop {
+				if path.instructions >= self.budget {
+					break PathEnd::BudgetExhausted;
+				}
+				let (pc, condition, target) = match self.step(&mut path) {
+					Step::Next => continue,
+					Step::End(end) => break end,
+					Step::Fork {
+						pc,
+						condition,
+						target,
+					} => (pc, condition, target),
+					Step::Concretise(expr) => {
+						let (mut forks, failed) = self.concretise(&path, &expr);
+						let count = forks.len() + failed.len();
+						if started + count > self.max_paths + 1 {
+							break PathEnd::PathLimit;
+						}
+						started += count.saturating_sub(1);
+						paths.extend(failed);
+						path = match forks.pop() {
+							Some(fork) => fork,
+							None => continue 'paths, // No values are feasible or all failed
+						};
+						pending.extend(forks);
+						continue;
+					}
+				};
+				let opposite = match &condition {
+					Constraint::NonZero(expr) => Constraint::Zero(expr.clone()),
+					Constraint::Zero(expr) => Constraint::NonZero(expr.clone()),
+				};
+				let taken = self.feasible(&path.constraints, &condition);
+				let not_taken = self.feasible(&path.constraints, &opposite);
+				if taken && not_taken {
+					if started >= self.max_paths {
+						break PathEnd::PathLimit;
+					}
+					started += 1;
+					let mut fork = path.clone();
+					fork.constraints.push(condition);
+					if let Some(end) = jump(&mut fork, pc, target) {
+						fork.end = end;
+						paths.push(fork);
+					} else {
+						pending.push(fork);
+					}
+					path.constraints.push(opposite);
+				} else if taken {
+					path.constraints.push(condition);
+					if let Some(end) = jump(&mut path, pc, target) {
+						break end;
+					}
+				} else {
+					path.constraints.push(opposite);
+				}
+			};
+			paths.push(path);
+		}
+		return paths;
+	}
+
+	// Explores the program for a path on which `goal` can hold along with the
+	// path's own constraints, and returns symbol values that make it happen.
+	// Symbols that don't matter get the value in their range nearest zero.
+	pub fn solve<F: Fn(&Path) -> Vec<Constraint>>(&mut self, goal: F) -> Option<Model> {
+		for path in self.explore() {
+			if let PathEnd::Error(_) | PathEnd::PathLimit = path.end {
+				continue;
+			}
+			let mut constraints = path.constraints.clone();
+			constraints.extend(goal(&path));
+			let mut values = match self.check(&constraints) {
+				Satisfiability::Sat(values) => values,
+				_ => continue,
+			};
+			for (index, info) in self.symbols.iter().enumerate() {
+				if let SymbolKind::Load { .. } = info.kind {
+					continue;
+				}
+				let nearest_zero = 0.max(*info.range.start()).min(*info.range.end());
+				values.entry(Symbol(index)).or_insert(nearest_zero);
+			}
+			let model = Model { values };
+			if self.replay(&path, &model) {
+				return Some(model);
+			}
+		}
+		return None;
+	}
+
+	// Runs the program on the ordinary VM with `model`'s values, checking it
+	// ends the same way as `path` with the same output and memory. Values
+	// that depend on a load are skipped.
+	fn replay(&self, path: &Path, model: &Model) -> bool {
+		let value = |symbol: Symbol| model.get(symbol);
+		let mut vm = VM::from_memory(&self.program);
+		for &(address, symbol) in &self.cells {
+			if vm.write_memory(address, model[symbol]).is_err() {
+				return false;
+			}
+		}
+		for input in &self.input {
+			match input.eval(&value) {
+				Some(input) => vm.queue_input(input),
+				None => return false,
+			}
+		}
+		let status = loop {
+			let remaining = self.budget.saturating_sub(vm.instruction_count());
+			match vm.run_for(remaining) {
+				Ok(Status::NewOutput) => (),
+				Ok(status) => break status,
+				Err(_) => return false,
+			}
+		};
+		let expected = match path.end {
+			PathEnd::Halted => Status::Halt,
+			PathEnd::WaitForInput => Status::WaitForInput,
+			PathEnd::BudgetExhausted => Status::BudgetExhausted,
+			_ => return false,
+		};
+		if status != expected || vm.pc() != path.pc {
+			return false;
+		}
+
+		let output: Vec<i64> = vm.drain_output();
+		if output.len() != path.output.len() {
+			return false;
+		}
+		let matches = |expr: &Expr, actual: i64| match expr.eval(&value) {
+			Some(value) => value == actual,
+			None => true,
+		};
+		if !path
+			.output
+			.iter()
+			.zip(&output)
+			.all(|(expr, &actual)| matches(expr, actual))
+		{
+			return false;
+		}
+		for (start, cells) in path.memory.regions() {
+			for address in start..start + cells.len() {
+				if !matches(&path.memory(address), vm.memory().read(address)) {
+					return false;
+				}
+			}
+		}
+		return true;
+	}
+
+	// Drops the loads found by the last exploration, which always come after
+	// the symbols they could depend on
+	fn forget_loads(&mut self) {
+		let loads = self
+			.symbols
+			.iter()
+			.rev()
+			.take_while(|info| matches!(info.kind, SymbolKind::Load { .. }))
+			.count();
+		self.symbols.truncate(self.symbols.len() - loads);
+	}
+
+	fn add_symbol(&mut self, name: &str, kind: SymbolKind, range: RangeInclusive<i64>) -> Symbol {
+		self.symbols.push(SymbolInfo {
+			name: name.to_string(),
+			kind,
+			range,
+		});
+		return Symbol(self.symbols.len() - 1);
+	}
+
+	// Whether `expr` depends on few enough combinations of symbol values to
+	// fork into one path per combination
+	fn enumerable(&self, expr: &Expr) -> bool {
+		let mut symbols = BTreeSet::new();
+		expr.symbols(&mut symbols);
+		let mut combinations: u128 = 1;
+		for symbol in symbols {
+			let range = &self.symbols[symbol.0].range;
+			let values = (*range.end() as i128 - *range.start() as i128 + 1).max(0) as u128;
+			combinations = combinations.saturating_mul(values);
+		}
+		return combinations <= self.fork_limit as u128;
+	}
+
+	// Copies of `path` with each feasible combination of values for the
+	// symbols in `expr` substituted, and the copies that ended trying
+	fn concretise(&self, path: &Path, expr: &Expr) -> (Vec<Path>, Vec<Path>) {
+		let mut symbols = BTreeSet::new();
+		expr.symbols(&mut symbols);
+		let mut forks = vec![path.clone()];
+		let mut failed = Vec::new();
+		for symbol in symbols {
+			let range = self.symbols[symbol.0].range.clone();
+			let mut next = Vec::new();
+			for fork in &forks {
+				for value in range.clone() {
+					let mut fork = fork.clone();
+					let result = fork.substitute(symbol, value);
+					if self.check(&fork.constraints) == Satisfiability::Unsat {
+						continue;
+					}
+					match result {
+						Ok(()) => next.push(fork),
+						Err(error) => {
+							fork.end = PathEnd::Error(error);
+							failed.push(fork);
+						}
+					}
+				}
+			}
+			forks = next;
+		}
+		forks.reverse(); // So the lowest values are explored first
+		return (forks, failed);
+	}
+
+	// The value of `expr` if it's concrete, or why it can't be used yet
+	fn concrete(&self, expr: Expr, error: SymbolicError) -> Result<i64, Stop> {
+		match expr.as_const() {
+			Some(value) => Ok(value),
+			None if self.enumerable(&expr) => Err(Stop::Concretise(expr)),
+			None => Err(Stop::Error(error)),
+		}
+	}
+
+	fn feasible(&self, constraints: &[Constraint], condition: &Constraint) -> bool {
+		let mut constraints = constraints.to_vec();
+		constraints.push(condition.clone());
+		return self.check(&constraints) != Satisfiability::Unsat;
+	}
+
+	// Executes the instruction at the path's pc
+	fn step(&mut self, path: &mut Path) -> Step {
+		let pc = path.pc;
+		let result = self
+			.concrete(path.memory(pc), SymbolicError::SymbolicInstruction { pc })
+			.and_then(|intcode| self.execute(path, pc, intcode));
+		match result {
+			Ok(Step::End(end)) => return Step::End(end),
+			Ok(step) => {
+				path.instructions += 1;
+				return step;
+			}
+			Err(Stop::Concretise(expr)) => return Step::Concretise(expr),
+			Err(Stop::Error(error)) => return Step::End(PathEnd::Error(error)),
+		}
+	}
+
+	fn execute(&mut self, path: &mut Path, pc: usize, intcode: i64) -> Result<Step, Stop> {
+		let fault = |error| Err(Stop::Error(SymbolicError::Vm(error)));
+		let opcode = match Opcode::from_intcode(intcode) {
+			Some(opcode) => opcode,
+			None => return fault(VmError::UnknownOpcode { pc, intcode }),
+		};
+		let mut modes = [MemMode::Address; 3];
+		for (i, mode) in modes.iter_mut().enumerate().take(opcode.param_count()) {
+			let digit = (intcode / 10_i64.pow(i as u32 + 2)) % 10;
+			*mode = match MemMode::from_i64(digit) {
+				Some(mode) => mode,
+				None => {
+					return fault(VmError::InvalidMode {
+						pc,
+						intcode,
+						mode: digit,
+					})
+				}
+			};
+		}
+		let next = pc + 1 + opcode.param_count();
+
+		match opcode {
+			Opcode::ADD | Opcode::MUL | Opcode::LT | Opcode::EQ => {
+				let a = self.read(path, pc, intcode, 0, modes[0])?;
+				let b = self.read(path, pc, intcode, 1, modes[1])?;
+				let value = match opcode {
+					Opcode::ADD => Expr::sum(a, b),
+					Opcode::MUL => Expr::product(a, b),
+					Opcode::LT => Expr::lt(a, b),
+					_ => Expr::eq(a, b),
+				};
+				let address = self.write_address(path, pc, intcode, 2, modes[2])?;
+				path.write(pc, intcode, address, value)?;
+			}
+			Opcode::IN => {
+				let address = self.write_address(path, pc, intcode, 0, modes[0])?;
+				let input = match path.input.pop_front() {
+					Some(input) => input,
+					None => return Ok(Step::End(PathEnd::WaitForInput)),
+				};
+				path.write(pc, intcode, address, input)?;
+			}
+			Opcode::OUT => {
+				let value = self.read(path, pc, intcode, 0, modes[0])?;
+				path.output.push(value);
+			}
+			Opcode::JIT | Opcode::JIF => {
+				let condition = self.read(path, pc, intcode, 0, modes[0])?;
+				let target = self.read(path, pc, intcode, 1, modes[1])?;
+				let condition = match condition.as_const() {
+					Some(value) if (value != 0) == (opcode == Opcode::JIT) => {
+						// Taken, so the target has to be known
+						self.concrete(target.clone(), SymbolicError::SymbolicJump { pc })?;
+						return Ok(jump(path, pc, target).map_or(Step::Next, Step::End));
+					}
+					Some(_) => {
+						path.pc = next;
+						return Ok(Step::Next);
+					}
+					None if opcode == Opcode::JIT => Constraint::NonZero(condition),
+					None => Constraint::Zero(condition),
+				};
+				path.pc = next;
+				return Ok(Step::Fork {
+					pc,
+					condition,
+					target,
+				});
+			}
+			Opcode::ARB => {
+				let delta = self.read(path, pc, intcode, 0, modes[0])?;
+				let delta = self.concrete(delta, SymbolicError::SymbolicRelativeBase { pc })?;
+				path.relative_base = path.relative_base.wrapping_add(delta); // As the VM does
+			}
+			Opcode::HALT => return Ok(Step::End(PathEnd::Halted)),
+		}
+		path.pc = next;
+		return Ok(Step::Next);
+	}
+
+	// The address parameter `index` refers to, which may depend on a symbol
+	fn address(path: &Path, pc: usize, index: usize, mode: MemMode) -> Expr {
+		let param = path.memory(pc + 1 + index);
+		match mode {
+			MemMode::Relative => return Expr::sum(Expr::Const(path.relative_base), param),
+			_ => return param,
+		}
+	}
+
+	fn read(
+		&mut self,
+		path: &Path,
+		pc: usize,
+		intcode: i64,
+		index: usize,
+		mode: MemMode,
+	) -> Result<Expr, Stop> {
+		if mode == MemMode::Immediate {
+			return Ok(path.memory(pc + 1 + index));
+		}
+		let address = SymbolicVM::address(path, pc, index, mode);
+		match address.as_const() {
+			Some(address) if address < 0 => {
+				Err(Stop::Error(SymbolicError::Vm(VmError::NegativeAddress {
+					pc,
+					intcode,
+					address,
+				})))
+			}
+			Some(address) => Ok(path.memory(address as usize)),
+			None if self.enumerable(&address) => Err(Stop::Concretise(address)),
+			None => {
+				let name = format!("load@{}", pc);
+				let symbol = self.add_symbol(&name, SymbolKind::Load { pc }, i64::MIN..=i64::MAX);
+				Ok(Expr::Symbol(symbol))
+			}
+		}
+	}
+
+	fn write_address(
+		&self,
+		path: &Path,
+		pc: usize,
+		intcode: i64,
+		index: usize,
+		mode: MemMode,
+	) -> Result<usize, Stop> {
+		if mode == MemMode::Immediate {
+			return Err(Stop::Error(SymbolicError::Vm(VmError::ImmediateWrite {
+				pc,
+				intcode,
+			})));
+		}
+		let address = SymbolicVM::address(path, pc, index, mode);
+		match self.concrete(address, SymbolicError::SymbolicAddress { pc })? {
+			address if address < 0 => {
+				Err(Stop::Error(SymbolicError::Vm(VmError::NegativeAddress {
+					pc,
+					intcode,
+					address,
+				})))
+			}
+			address => Ok(address as usize),
+		}
+	}
+}
+
+// Moves the path to the target of the jump at `pc`, or leaves it on the jump
+// and returns how it ended if it can't
+fn jump(path: &mut Path, pc: usize, target: Expr) -> Option<PathEnd> {
+	let error = match target.as_const() {
+		Some(target) if target >= 0 => {
+			path.pc = target as usize;
+			return None;
+		}
+		Some(address) => {
+			let intcode = path.memory(pc).as_const().unwrap_or(0);
+			SymbolicError::Vm(VmError::NegativeAddress {
+				pc,
+				intcode,
+				address,
+			})
+		}
+		None => SymbolicError::SymbolicJump { pc },
+	};
+	path.pc = pc;
+	return Some(PathEnd::Error(error));
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn solves_day2() {
+		let program = crate::parse_program(include_str!("../../../input/2019/day2.txt")).unwrap();
+		let mut vm = SymbolicVM::from_memory(&program);
+		let noun = vm.symbolic_cell(1, "noun", 0..=99);
+		let verb = vm.symbolic_cell(2, "verb", 0..=99);
+		let model = vm
+			.solve(|path| vec![Constraint::equal(path.memory(0), 19690720)])
+			.unwrap();
+		assert_eq!(100 * model[noun] + model[verb], 5741);
+
+		// Nothing else reaches the target
+		let paths = vm.explore();
+		assert_eq!(paths.len(), 1);
+		assert_eq!(paths[0].end, PathEnd::Halted);
+		let mut constraints = paths[0].constraints.clone();
+		constraints.push(Constraint::equal(paths[0].memory(0), 19690720));
+		constraints.push(Constraint::Zero(Expr::eq(
+			Expr::Symbol(noun),
+			Expr::Const(57),
+		)));
+		assert_eq!(vm.check(&constraints), Satisfiability::Unsat);
+	}
+
+	#[test]
+	fn forks_on_symbolic_jumps() {
+		// Outputs 0 if the input is 0, otherwise 1
+		let program = [3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, -1, 0, 1, 9];
+		let mut vm = SymbolicVM::from_memory(&program);
+		let input = vm.symbolic_input("input", i64::MIN..=i64::MAX);
+		let paths = vm.explore();
+		assert_eq!(paths.len(), 2);
+		let constraints: Vec<String> = paths
+			.iter()
+			.map(|path| path.constraints[0].to_string())
+			.collect();
+		assert_eq!(constraints, ["s0 != 0", "s0 == 0"]);
+		assert!(paths
+			.iter()
+			.all(|path| path.end == PathEnd::Halted && path.pc == 11));
+
+		let model = vm
+			.solve(|path| vec![Constraint::equal(path.output[0].clone(), 1)])
+			.unwrap();
+		assert_eq!(model[input], 1);
+		let model = vm
+			.solve(|path| vec![Constraint::equal(path.output[0].clone(), 0)])
+			.unwrap();
+		assert_eq!(model[input], 0);
+		assert_eq!(
+			vm.solve(|path| vec![Constraint::equal(path.output[0].clone(), 2)]),
+			None
+		);
+
+		// Compares without jumping, so there's one path
+		let program = [3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8];
+		let mut vm = SymbolicVM::from_memory(&program);
+		let input = vm.symbolic_input("input", i64::MIN..=i64::MAX);
+		let model = vm
+			.solve(|path| vec![Constraint::equal(path.output[0].clone(), 1)])
+			.unwrap();
+		assert_eq!(model[input], 8);
+	}
+
+	#[test]
+	fn finds_amplifier_phases() {
+		let program = crate::parse_program(include_str!("../../../input/2019/day7.txt")).unwrap();
+		let mut expected = VM::from_memory(&program);
+		let thrust = expected
+			.run_until_halt(&[3, 7])
+			.unwrap()
+			.last_output()
+			.unwrap();
+
+		let mut vm = SymbolicVM::from_memory(&program);
+		let phase = vm.symbolic_input("phase", 0..=4);
+		vm.queue_input(7);
+		let feasible = vm
+			.explore()
+			.iter()
+			.filter(|path| path.end == PathEnd::Halted)
+			.count();
+		assert_eq!(feasible, 5);
+		let model = vm
+			.solve(|path| vec![Constraint::equal(path.output[0].clone(), thrust)])
+			.unwrap();
+		assert_eq!(model[phase], 3);
+	}
+
+	#[test]
+	fn concretises_symbolic_addresses() {
+		// Few enough addresses to fork into one path each
+		let mut vm = SymbolicVM::from_memory(&[3, 3, 4, 0, 99]);
+		vm.symbolic_input("address", 0..=4);
+		let paths = vm.explore();
+		let outputs: Vec<Option<i64>> =
+			paths.iter().map(|path| path.output[0].as_const()).collect();
+		assert_eq!(outputs, vec![Some(3), Some(3), Some(4), Some(3), Some(99)]);
+		assert!(paths.iter().all(|path| path.end == PathEnd::Halted));
+
+		// Too many, so it reads through the address it was given, which is a load
+		vm.set_fork_limit(4);
+		let paths = vm.explore();
+		assert_eq!(paths.len(), 1);
+		assert_eq!(paths[0].end, PathEnd::Halted);
+		let load = match paths[0].output[0] {
+			Expr::Symbol(symbol) => vm.symbol(symbol),
+			ref output => panic!("Expected a load, got {}", output),
+		};
+		assert_eq!(load.kind, SymbolKind::Load { pc: 2 });
+		assert_eq!(
+			vm.solve(|path| vec![Constraint::equal(path.output[0].clone(), 3)]),
+			None
+		);
+
+		// Each exploration replaces the loads of the last one
+		assert_eq!(vm.symbols().len(), 2);
+		vm.explore();
+		assert_eq!(vm.symbols().len(), 2);
+		let symbol = vm.symbolic_input("unused", 0..=0);
+		assert_eq!(vm.symbol(symbol).name, "unused");
+		assert_eq!(vm.symbols().len(), 2);
+	}
+
+	#[test]
+	fn substitution_respects_memory_limit() {
+		// Jumps through a cell that's symbolic until the jump makes it concrete
+		let far = 1 << 40;
+		let mut vm = SymbolicVM::from_memory(&[3, far, 105, 1, far, 99]);
+		vm.symbolic_input("target", 5..=5);
+		vm.set_memory_limit(16);
+		let paths = vm.explore();
+		assert_eq!(paths.len(), 1);
+		assert_eq!(
+			paths[0].end,
+			PathEnd::Error(SymbolicError::Vm(VmError::MemoryCapExceeded {
+				pc: 2,
+				intcode: 105,
+				address: far as usize,
+				limit: 16
+			}))
+		);
+	}
+
+	#[test]
+	fn stops_at_symbolic_addresses() {
+		// Writes to the address it was given
+		let mut vm = SymbolicVM::from_memory(&[3, 5, 1101, 1, 1, 0, 99]);
+		vm.symbolic_input("address", 0..=1000);
+		let paths = vm.explore();
+		assert_eq!(
+			paths[0].end,
+			PathEnd::Error(SymbolicError::SymbolicAddress { pc: 2 })
+		);
+	}
+
+	#[test]
+	fn relative_base_wraps() {
+		let mut vm = SymbolicVM::from_memory(&[109, i64::MAX, 109, 1, 99]);
+		let paths = vm.explore();
+		assert_eq!(paths.len(), 1);
+		assert_eq!(paths[0].end, PathEnd::Halted);
+		assert_eq!(paths[0].relative_base(), i64::MIN);
+	}
+}
@@ -0,0 +1,303 @@
+/*
+	--- Intcode VM: threaded machines ---
+
+	Runs a VM on its own thread, talking over mpsc channels. Where the VM on
+	its own stops with Status::WaitForInput and waits to be run again, a
+	threaded machine blocks until a value arrives, so it can sit alongside
+	other components that expect I/O to block. The thread finishes when the
+	program halts or faults, when its input or output channel hangs up, or
+	when it's asked to stop, and hands the VM back so its final memory can be
+	inspected. It can be waited on with join() or awaited as a Future.
+*/
+
+use crate::{Status, VmError, VM};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// Instructions between checks for a stop request
+const STOP_CHECK_INTERVAL: u64 = 100_000;
+// How long a blocked machine waits for input before checking again
+const INPUT_POLL: Duration = Duration::from_millis(10);
+
+// Why the thread finished
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExitReason {
+	Halted,
+	InputClosed,  // Waiting for input that can never arrive
+	OutputClosed, // Nobody is listening to the output any more
+	Stopped,      // stop() was called
+	Error(VmError),
+}
+
+// The machine as it was when the thread finished
+pub struct Exit {
+	pub vm: VM,
+	pub reason: ExitReason,
+}
+
+// Shared with the thread, so that a Future can be woken when it finishes
+#[derive(Default)]
+struct Finish {
+	exit: Option<Exit>,
+	waker: Option<Waker>,
+}
+
+pub struct VmThread {
+	input: Option<Sender<i64>>, // None if the caller supplied the channels
+	output: Option<Receiver<i64>>,
+	stop: Arc<AtomicBool>,
+	finish: Arc<Mutex<Finish>>,
+	handle: JoinHandle<()>,
+}
+
+impl VmThread {
+	// Starts `vm` running, with new channels for its input and output
+	pub fn spawn(vm: VM) -> Self {
+		let (input, input_receiver) = mpsc::channel();
+		let (output_sender, output) = mpsc::channel();
+		let mut thread = VmThread::spawn_with_channels(vm, input_receiver, output_sender);
+		thread.input = Some(input);
+		thread.output = Some(output);
+		return thread;
+	}
+
+	// Starts `vm` running on channels owned by the caller, e.g. to connect one
+	// machine's output to the next one's input
+	pub fn spawn_with_channels(vm: VM, input: Receiver<i64>, output: Sender<i64>) -> Self {
+		let stop = Arc::new(AtomicBool::new(false));
+		let finish = Arc::new(Mutex::new(Finish::default()));
+		let handle = {
+			let stop = Arc::clone(&stop);
+			let finish = Arc::clone(&finish);
+			thread::spawn(move || {
+				let exit = run(vm, &input, &output, &stop);
+				let mut finish = finish.lock().expect("Poisoned VM thread");
+				finish.exit = Some(exit);
+				if let Some(waker) = finish.waker.take() {
+					waker.wake();
+				}
+			})
+		};
+		VmThread {
+			input: None,
+			output: None,
+			stop,
+			finish,
+			handle,
+		}
+	}
+
+	// Where to send input, unless the caller supplied the channels
+	pub fn input(&self) -> Option<&Sender<i64>> {
+		return self.input.as_ref();
+	}
+
+	// Where output arrives, unless the caller supplied the channels
+	pub fn output(&self) -> Option<&Receiver<i64>> {
+		return self.output.as_ref();
+	}
+
+	// Sends a value, returning false if the machine has finished
+	pub fn send(&self, value: i64) -> bool {
+		return self
+			.input
+			.as_ref()
+			.is_some_and(|input| input.send(value).is_ok());
+	}
+
+	// Waits for the next output, or None once the machine has finished
+	pub fn recv(&self) -> Option<i64> {
+		return self.output.as_ref().and_then(|output| output.recv().ok());
+	}
+
+	// Hangs up the input, so the machine finishes once it needs more
+	pub fn close_input(&mut self) {
+		self.input = None;
+	}
+
+	// Asks the machine to stop at the next opportunity
+	pub fn stop(&self) {
+		self.stop.store(true, Ordering::SeqCst);
+	}
+
+	pub fn is_finished(&self) -> bool {
+		return self
+			.finish
+			.lock()
+			.expect("Poisoned VM thread")
+			.exit
+			.is_some();
+	}
+
+	// Waits for the thread to finish, which never happens if the program waits
+	// for input that's still open. Output that hasn't been received is lost.
+	pub fn join(self) -> Exit {
+		if let Err(panic) = self.handle.join() {
+			std::panic::resume_unwind(panic);
+		}
+		let exit = self.finish.lock().expect("Poisoned VM thread").exit.take();
+		return exit.expect("VM thread finished without exiting");
+	}
+
+	// Waits for the thread to finish without blocking an executor. The input
+	// and output channels are dropped.
+	pub fn into_future(self) -> ExitFuture {
+		ExitFuture {
+			finish: self.finish,
+			handle: Some(self.handle),
+		}
+	}
+}
+
+pub struct ExitFuture {
+	finish: Arc<Mutex<Finish>>,
+	handle: Option<JoinHandle<()>>,
+}
+
+impl Future for ExitFuture {
+	type Output = Exit;
+
+	fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<Exit> {
+		let exit = {
+			let mut finish = self.finish.lock().expect("Poisoned VM thread");
+			match finish.exit.take() {
+				Some(exit) => exit,
+				None => {
+					finish.waker = Some(context.waker().clone());
+					return Poll::Pending;
+				}
+			}
+		};
+		// The thread has stored its exit, so this won't block for long
+		if let Some(handle) = self.handle.take() {
+			let _ = handle.join();
+		}
+		return Poll::Ready(exit);
+	}
+}
+
+fn run(mut vm: VM, input: &Receiver<i64>, output: &Sender<i64>, stop: &AtomicBool) -> Exit {
+	let reason = loop {
+		if stop.load(Ordering::SeqCst) {
+			break ExitReason::Stopped;
+		}
+		let status = match vm.run_for(STOP_CHECK_INTERVAL) {
+			Ok(status) => status,
+			Err(error) => break ExitReason::Error(error),
+		};
+		if vm
+			.drain_output()
+			.into_iter()
+			.any(|value| output.send(value).is_err())
+		{
+			break ExitReason::OutputClosed;
+		}
+		match status {
+			Status::Halt => break ExitReason::Halted,
+			Status::WaitForInput => match input.recv_timeout(INPUT_POLL) {
+				Ok(value) => vm.queue_input(value),
+				Err(RecvTimeoutError::Timeout) => (),
+				Err(RecvTimeoutError::Disconnected) => break ExitReason::InputClosed,
+			},
+			_ => (),
+		}
+	};
+	return Exit { vm, reason };
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::task::Wake;
+
+	// Outputs the sum of each pair of inputs
+	const ADDER: [i64; 13] = [3, 13, 3, 14, 1, 13, 14, 15, 4, 15, 1105, 1, 0];
+
+	#[test]
+	fn blocks_on_input() {
+		let mut machine = VmThread::spawn(VM::from_memory(&ADDER));
+		assert!(machine.send(2));
+		assert!(machine.send(40));
+		assert_eq!(machine.recv(), Some(42));
+		assert!(machine.send(-1));
+		assert!(!machine.is_finished());
+
+		machine.close_input();
+		let exit = machine.join();
+		assert_eq!(exit.reason, ExitReason::InputClosed);
+		assert_eq!(exit.vm.memory().read(13), -1);
+	}
+
+	#[test]
+	fn chains_machines() {
+		// Each doubler feeds the next, and the last hangs up when it halts
+		let doubler = [3, 9, 102, 2, 9, 9, 4, 9, 99, 0];
+		let (input, receiver) = mpsc::channel();
+		let (sender, middle) = mpsc::channel();
+		let first = VmThread::spawn_with_channels(VM::from_memory(&doubler), receiver, sender);
+		let (sender, output) = mpsc::channel();
+		let second = VmThread::spawn_with_channels(VM::from_memory(&doubler), middle, sender);
+
+		input.send(5).unwrap();
+		assert_eq!(output.recv(), Ok(20));
+		assert_eq!(first.join().reason, ExitReason::Halted);
+		let exit = second.join();
+		assert_eq!(exit.reason, ExitReason::Halted);
+		assert_eq!(exit.vm.memory().read(9), 20);
+		assert_eq!(output.recv(), Err(mpsc::RecvError));
+	}
+
+	#[test]
+	fn stops_and_reports_errors() {
+		// Loops forever
+		let machine = VmThread::spawn(VM::from_memory(&[1105, 1, 0]));
+		machine.stop();
+		assert_eq!(machine.join().reason, ExitReason::Stopped);
+
+		let machine = VmThread::spawn(VM::from_memory(&[1, 0, 0, 0, 42]));
+		let exit = machine.join();
+		assert!(matches!(
+			exit.reason,
+			ExitReason::Error(VmError::UnknownOpcode { pc: 4, .. })
+		));
+		assert_eq!(exit.vm.memory().read(0), 2);
+	}
+
+	struct ThreadWaker(thread::Thread);
+
+	impl Wake for ThreadWaker {
+		fn wake(self: Arc<Self>) {
+			self.0.unpark();
+		}
+	}
+
+	// Polls a future on this thread until it completes
+	fn block_on<F: Future>(future: F) -> F::Output {
+		let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+		let mut context = Context::from_waker(&waker);
+		let mut future = Box::pin(future);
+		loop {
+			match future.as_mut().poll(&mut context) {
+				Poll::Ready(output) => return output,
+				Poll::Pending => thread::park(),
+			}
+		}
+	}
+
+	#[test]
+	fn awaits_exit() {
+		let machine = VmThread::spawn(VM::from_memory(&ADDER));
+		machine.send(1);
+		machine.send(2);
+		assert_eq!(machine.recv(), Some(3));
+		let exit = block_on(machine.into_future());
+		assert_eq!(exit.reason, ExitReason::InputClosed);
+		assert_eq!(exit.vm.memory().read(15), 3);
+	}
+}
@@ -0,0 +1,314 @@
+/*
+	--- Intcode VM: execution tracing ---
+*/
+
+use crate::disasm::{Instruction, Operand};
+use crate::VM;
+use log::Level;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryWrite {
+	pub address: usize,
+	pub old: i64,
+	pub new: i64,
+}
+
+// Everything that happened while executing one instruction
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceRecord {
+	pub step: u64,                  // Instruction count, starting at 1
+	pub pc: usize,                  // Address of the instruction
+	pub intcode: i64,               // Raw opcode and parameter modes
+	pub instruction: Instruction,   // Decoded operands
+	pub reads: Vec<i64>,            // Values of the input parameters
+	pub write: Option<MemoryWrite>, // Memory cell written, if any
+}
+
+impl fmt::Display for TraceRecord {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(
+			f,
+			"{:>8} {:>5}: {:<28}",
+			self.step,
+			self.pc,
+			self.instruction.to_string()
+		)?;
+		if !self.reads.is_empty() {
+			write!(f, " reads {:?}", self.reads)?;
+		}
+		if let Some(write) = self.write {
+			write!(f, " [{}] {} -> {}", write.address, write.old, write.new)?;
+		}
+		return Ok(());
+	}
+}
+
+impl TraceRecord {
+	// Renders the record as a single line of JSON
+	pub fn to_json(&self) -> String {
+		let operands: Vec<String> = self
+			.instruction
+			.operands
+			.iter()
+			.map(|operand| format!("\"{}\"", operand))
+			.collect();
+		let reads: Vec<String> = self.reads.iter().map(|x| x.to_string()).collect();
+		let write = match self.write {
+			Some(write) => format!(
+				"{{\"address\":{},\"old\":{},\"new\":{}}}",
+				write.address, write.old, write.new
+			),
+			None => String::from("null"),
+		};
+		return format!(
+			"{{\"step\":{},\"pc\":{},\"intcode\":{},\"op\":\"{}\",\"operands\":[{}],\"reads\":[{}],\"write\":{}}}",
+			self.step,
+			self.pc,
+			self.intcode,
+			self.instruction.opcode.mnemonic(),
+			operands.join(","),
+			reads.join(","),
+			write
+		);
+	}
+}
+
+// Receives a record for every instruction the VM executes
+pub trait Tracer {
+	fn record(&mut self, record: &TraceRecord);
+}
+
+// Lets a tracer be shared, so it can be inspected while attached to a VM
+impl<T: Tracer + ?Sized> Tracer for Arc<Mutex<T>> {
+	fn record(&mut self, record: &TraceRecord) {
+		self.lock().expect("Poisoned tracer").record(record);
+	}
+}
+
+// Emits each record through the `log` crate
+pub struct LogTracer {
+	level: Level,
+}
+
+impl LogTracer {
+	pub fn new(level: Level) -> Self {
+		LogTracer { level }
+	}
+}
+
+impl Default for LogTracer {
+	fn default() -> Self {
+		// enable_logging() filters at Info, so default to something visible
+		LogTracer::new(Level::Info)
+	}
+}
+
+impl Tracer for LogTracer {
+	fn record(&mut self, record: &TraceRecord) {
+		log::log!(self.level, "{}", record);
+	}
+}
+
+// Keeps the most recent records in memory
+pub struct RingBuffer {
+	capacity: usize,
+	records: VecDeque<TraceRecord>,
+}
+
+impl RingBuffer {
+	pub fn new(capacity: usize) -> Self {
+		RingBuffer {
+			capacity,
+			records: VecDeque::with_capacity(capacity),
+		}
+	}
+
+	pub fn shared(capacity: usize) -> Arc<Mutex<RingBuffer>> {
+		return Arc::new(Mutex::new(RingBuffer::new(capacity)));
+	}
+
+	// Oldest record first
+	pub fn records(&self) -> &VecDeque<TraceRecord> {
+		return &self.records;
+	}
+
+	pub fn clear(&mut self) {
+		self.records.clear();
+	}
+}
+
+impl Tracer for RingBuffer {
+	fn record(&mut self, record: &TraceRecord) {
+		if self.capacity == 0 {
+			return;
+		}
+		if self.records.len() == self.capacity {
+			self.records.pop_front();
+		}
+		self.records.push_back(record.clone());
+	}
+}
+
+// Writes one JSON object per line, e.g. to a file
+pub struct JsonLinesTracer<W: Write> {
+	writer: W,
+}
+
+impl<W: Write> JsonLinesTracer<W> {
+	pub fn new(writer: W) -> Self {
+		JsonLinesTracer { writer }
+	}
+
+	pub fn into_inner(self) -> W {
+		return self.writer;
+	}
+}
+
+impl<W: Write> Tracer for JsonLinesTracer<W> {
+	fn record(&mut self, record: &TraceRecord) {
+		// Tracing must never stop the program, so write errors are dropped
+		let _ = writeln!(self.writer, "{}", record.to_json());
+	}
+}
+
+impl VM {
+	pub fn set_tracer<T: Tracer + Send + 'static>(&mut self, tracer: T) {
+		self.tracer = Some(Box::new(tracer));
+	}
+
+	pub fn take_tracer(&mut self) -> Option<Box<dyn Tracer + Send>> {
+		return self.tracer.take();
+	}
+
+	// Decodes the instruction at the pc and captures what it's about to read,
+	// before it executes
+	pub(crate) fn trace_begin(&self) -> Option<TraceRecord> {
+		let pc = self.pc.get();
+		let instruction = self.decode_at(pc)?;
+		let writes = instruction.opcode.writes();
+		let count = instruction.operands.len();
+
+		let mut reads = Vec::new();
+		let mut write = None;
+		for (i, operand) in instruction.operands.iter().enumerate() {
+			let address = match *operand {
+				Operand::Immediate(value) => {
+					reads.push(value);
+					continue;
+				}
+				_ => self.operand_address(operand)?, // None if it's going to fault
+			};
+			let value = self.ram.read(address);
+			if writes && i == count - 1 {
+				write = Some(MemoryWrite {
+					address,
+					old: value,
+					new: value,
+				});
+			} else {
+				reads.push(value);
+			}
+		}
+
+		return Some(TraceRecord {
+			step: 0,
+			pc,
+			intcode: self.ram.read(pc),
+			instruction,
+			reads,
+			write,
+		});
+	}
+
+	// Fills in the results of an executed instruction and hands it to the tracer
+	pub(crate) fn trace_end(&mut self, mut record: TraceRecord) {
+		record.step = self.instructions;
+		if let Some(write) = record.write.as_mut() {
+			write.new = self.ram.read(write.address);
+		}
+		if let Some(tracer) = self.tracer.as_mut() {
+			tracer.record(&record);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::disasm;
+
+	#[test]
+	fn records_reads_and_writes() {
+		let mut vm = VM::from_memory(&[1002, 7, 3, 7, 104, 7, 99, 2]);
+		let ring = RingBuffer::shared(1);
+		vm.set_tracer(ring.clone());
+		vm.run_until_halt(&[]).unwrap();
+
+		// Only the OUT fits in the ring
+		let ring = ring.lock().unwrap();
+		assert_eq!(ring.records().len(), 1);
+		assert_eq!(
+			ring.records()[0].to_string(),
+			"       2     4: OUT #7                       reads [7]"
+		);
+	}
+
+	#[test]
+	fn traces_stray_mode_digits() {
+		// The VM ignores the modes past OUT's only parameter, so it's still traced
+		let mut vm = VM::from_memory(&[11104, 7, 99]);
+		let ring = RingBuffer::shared(10);
+		vm.set_tracer(ring.clone());
+		assert_eq!(vm.run_until_halt(&[]).unwrap().output, [7]);
+		let ring = ring.lock().unwrap();
+		assert_eq!(ring.records().len(), 1);
+		assert_eq!(ring.records()[0].intcode, 11104);
+		assert_eq!(ring.records()[0].instruction.to_string(), "OUT #7");
+	}
+
+	#[test]
+	fn json_lines() {
+		let record = TraceRecord {
+			step: 1,
+			pc: 0,
+			intcode: 1002,
+			instruction: disasm::decode(&[1002, 4, 3, 4], 0).unwrap(),
+			reads: vec![33, 3],
+			write: Some(MemoryWrite {
+				address: 4,
+				old: 33,
+				new: 99,
+			}),
+		};
+		let mut tracer = JsonLinesTracer::new(Vec::new());
+		tracer.record(&record);
+		assert_eq!(
+			String::from_utf8(tracer.into_inner()).unwrap(),
+			"{\"step\":1,\"pc\":0,\"intcode\":1002,\"op\":\"MUL\",\"operands\":[\"[4]\",\"#3\",\"[4]\"],\"reads\":[33,3],\"write\":{\"address\":4,\"old\":33,\"new\":99}}\n"
+		);
+	}
+
+	#[test]
+	fn matches_execution() {
+		let mut vm = VM::from_memory(&[1002, 4, 3, 4, 33]);
+		let ring = RingBuffer::shared(10);
+		vm.set_tracer(ring.clone());
+		vm.step().unwrap();
+		let ring = ring.lock().unwrap();
+		let record = &ring.records()[0];
+		assert_eq!(record.step, 1);
+		assert_eq!(record.reads, [33, 3]);
+		assert_eq!(
+			record.write,
+			Some(MemoryWrite {
+				address: 4,
+				old: 33,
+				new: 99
+			})
+		);
+	}
+}